@@ -1,6 +1,8 @@
 use std::fmt::{Debug, Display, Formatter};
 use std::sync::mpsc;
 
+use crate::transfer::TransferError;
+
 /// Cerial result type
 pub type Result<T> = std::result::Result<T, CerialError>;
 
@@ -17,6 +19,12 @@ pub enum CerialError {
     IOError(std::io::Error),
     /// Terminal not a TTY error
     NotTTY,
+    /// File transfer error
+    TransferError(TransferError),
+    /// Config file error
+    ConfigError(String),
+    /// Modbus command failed
+    ModbusError(String),
 }
 
 impl From<crossterm::ErrorKind> for CerialError {
@@ -43,6 +51,12 @@ impl From<std::io::Error> for CerialError {
     }
 }
 
+impl From<TransferError> for CerialError {
+    fn from(e: TransferError) -> Self {
+        Self::TransferError(e)
+    }
+}
+
 impl Display for CerialError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let msg = match self {
@@ -51,6 +65,9 @@ impl Display for CerialError {
             CerialError::ThreadMessageError(e) => e.to_string(),
             CerialError::IOError(e) => e.to_string(),
             CerialError::NotTTY => "Terminal is not TTY compatible".to_string(),
+            CerialError::TransferError(e) => e.to_string(),
+            CerialError::ConfigError(msg) => msg.clone(),
+            CerialError::ModbusError(msg) => msg.clone(),
         };
 
         write!(f, "Cerial Error: {}", msg)
@@ -1,56 +1,140 @@
 pub mod error;
 
-use crate::serial::SerialTelemetry;
-use serialport::SerialPortSettings;
+use crate::at::AtSession;
+use crate::baud::{AutoBaud, AutoBaudStatus};
+use crate::capture::{CaptureStatus, CsvCapture};
+use crate::checksum::{ChecksumKind, TxChecksum};
+use crate::decode::{DecoderRegistry, FrameView};
+use crate::macros::{parse_hex_bytes, Macros};
+use crate::modbus::{ModbusStatus, Transaction};
+use crate::nmea::NmeaSession;
+use crate::plot::Plotter;
+use crate::script::{Script, ScriptStatus};
+use crate::send::{FileSend, SendSettings, SendStatus};
+use crate::serial::{baud_rate_warning, LinkCounters, SerialTelemetry, Throughput};
+use crate::transfer::zmodem::ZmodemDetector;
+use crate::transfer::{Transfer, TransferStatus};
+use crate::trigger::Triggers;
+use crate::ui::layout::Layout;
+use crate::ui::line_edit::LineEditor;
+use crate::ui::render::LineRenderer;
+use crate::ui::scrollback::Scrollback;
+use serialport::{DataBits, FlowControl, Parity, SerialPortSettings, StopBits};
 use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
 /// Application state
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub enum CerialMode {
     /// Menu mode
+    #[default]
     Menu,
     /// Normal input mode
     Input,
-    /// Hex input mode
+    /// Hex bytes edited in the transmit pane, sent with the TX checksum on enter
     HexInput,
+    /// Line edited in the transmit pane, sent on enter
+    Line,
+    /// AT commands edited in the transmit pane, with responses grouped
+    At,
+    /// NMEA sentences and UBX commands edited in the transmit pane, sent with their checksums
+    Nmea,
+    /// Text entry on the menu bar
+    Prompt,
 }
 
 /// Menu state
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub enum MenuState {
     /// Menu line is hidden
     Hidden,
     /// Displays current serial settings
     SerialSettings,
     /// Display serial port telemetry
+    #[default]
     SerialTelemetry,
 }
 
-impl Default for MenuState {
-    fn default() -> Self {
-        Self::SerialTelemetry
+impl Display for CerialMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            CerialMode::Menu => "Menu",
+            CerialMode::Input => "Input",
+            CerialMode::HexInput => "Hex",
+            CerialMode::Line => "Line",
+            CerialMode::At => "AT",
+            CerialMode::Nmea => "NMEA",
+            CerialMode::Prompt => "Prompt",
+        };
+        write!(f, "{}", s)
     }
 }
 
-impl Default for CerialMode {
-    fn default() -> Self {
-        Self::Menu
-    }
+/// Action to take with the text entered at a prompt
+#[derive(Debug, Copy, Clone)]
+pub enum PromptKind {
+    /// Path of a file to send
+    SendFile,
+    /// File transfer command
+    Transfer,
+    /// Accept a ZMODEM transfer started by the remote sender
+    ZmodemReceive,
+    /// Files to send to a waiting ZMODEM receiver
+    ZmodemSend,
+    /// Path of a script to run
+    RunScript,
+    /// Trigger rules to enable or disable
+    Triggers,
+    /// Pattern of lines to show
+    IncludeFilter,
+    /// Pattern of lines to hide
+    ExcludeFilter,
+    /// Decoder to show received data with
+    Decoder,
+    /// Modbus master command
+    Modbus,
+    /// Checksum appended to hex frames and macros
+    Checksum,
+    /// CSV file to capture numeric fields to
+    Capture,
 }
 
-impl Display for CerialMode {
+impl Display for PromptKind {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let s = match self {
-            CerialMode::Menu => "Menu",
-            CerialMode::Input => "Input",
-            CerialMode::HexInput => "Hex",
+            PromptKind::SendFile => "Send file",
+            PromptKind::Transfer => "Transfer <protocol> <send|receive> [paths]",
+            PromptKind::ZmodemReceive => "ZMODEM receive requested, accept? (y/n)",
+            PromptKind::ZmodemSend => "ZMODEM receiver waiting, files to send",
+            PromptKind::RunScript => "Run script",
+            PromptKind::Triggers => "Toggle triggers <numbers|all>",
+            PromptKind::IncludeFilter => "Show only lines matching",
+            PromptKind::ExcludeFilter => "Hide lines matching",
+            PromptKind::Decoder => "Decoder <name> [raw] or off",
+            PromptKind::Modbus => "Modbus <unit> <function> <address> [count|values]",
+            PromptKind::Checksum => "TX checksum <name> [big|little] or off",
+            PromptKind::Capture => "Capture CSV to (empty for download directory)",
         };
         write!(f, "{}", s)
     }
 }
 
+/// Text prompt shown on the menu bar
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    /// Prompt action
+    pub kind: PromptKind,
+    /// Text entered so far
+    pub input: String,
+    /// Mode to return to when the prompt is closed
+    pub return_mode: CerialMode,
+}
+
 /// Struct respresing the application state
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Default)]
 pub struct CerialState {
     /// Application mode
     pub mode: CerialMode,
@@ -58,12 +142,86 @@ pub struct CerialState {
     pub exit: bool,
     /// Serial telemetry
     pub serial_telemetry: SerialTelemetry,
+    /// Byte, error, and timeout counts kept by the serial threads
+    pub link_counters: Arc<LinkCounters>,
+    /// Throughput measured from the link counters
+    pub throughput: Throughput,
+    /// Show bytes received with line errors in reverse video
+    pub mark_errors: bool,
     /// Path to serial device
     pub serial_dev: String,
     /// Current serial settings
     pub serial_settings: SerialPortSettings,
     /// Current menu state
     pub menu_state: MenuState,
+    /// Command to run in place of a serial port
+    pub exec_command: Option<Vec<String>>,
+    /// Exit status of the exec child process, if it has exited
+    pub process_exit: Option<ExitStatus>,
+    /// Active menu bar prompt
+    pub prompt: Option<Prompt>,
+    /// File send pacing settings
+    pub send_settings: SendSettings,
+    /// In progress file send
+    pub file_send: Option<FileSend>,
+    /// Status of the last file send
+    pub send_status: Option<SendStatus>,
+    /// In progress file transfer
+    pub transfer: Option<Transfer>,
+    /// Status of the last file transfer
+    pub transfer_status: Option<TransferStatus>,
+    /// Running script
+    pub script: Option<Script>,
+    /// Last status of the running or finished script
+    pub script_status: Option<ScriptStatus>,
+    /// Running auto baud detection
+    pub auto_baud: Option<AutoBaud>,
+    /// Last status of the running or finished auto baud detection
+    pub auto_baud_status: Option<AutoBaudStatus>,
+    /// Modbus master command waiting for its response
+    pub modbus: Option<Transaction>,
+    /// Status of the last Modbus master command
+    pub modbus_status: Option<ModbusStatus>,
+    /// Rates auto baud detection tries
+    pub baud_rates: Vec<u32>,
+    /// Directory received files are written to when no path is given
+    pub download_dir: PathBuf,
+    /// Watches received data for ZMODEM transfers started by the remote
+    pub zmodem_detector: ZmodemDetector,
+    /// Macros by key
+    pub macros: Macros,
+    /// Checksum appended to hex frames and hex macros without their own
+    pub tx_checksum: Option<TxChecksum>,
+    /// Trigger rules
+    pub triggers: Triggers,
+    /// Highlights and filters received text
+    pub renderer: LineRenderer,
+    /// Decoders received data can be shown with
+    pub decoders: DecoderRegistry,
+    /// Decodes received data into frames shown in place of the text
+    pub frame_view: Option<FrameView>,
+    /// Plots numeric fields of received lines in place of the text
+    pub plot: Option<Plotter>,
+    /// Captures numeric fields of received lines to a CSV file
+    pub capture: Option<CsvCapture>,
+    /// How the last capture ended
+    pub capture_status: Option<CaptureStatus>,
+    /// Pane layout
+    pub layout: Layout,
+    /// Received lines, unfiltered
+    pub scrollback: Scrollback,
+    /// Lines scrolled back from the newest line, 0 follows received data
+    pub scroll_offset: usize,
+    /// The receive pane needs to be redrawn from the scrollback
+    pub redraw: bool,
+    /// Line being edited in the transmit pane
+    pub line_editor: LineEditor,
+    /// AT command tracking, started the first time AT mode is entered
+    pub at: Option<AtSession>,
+    /// NMEA sentence checking and receiver state, started the first time NMEA mode is entered
+    pub nmea: Option<NmeaSession>,
+    /// Menu mode was entered from input mode with `Ctrl-5`, a digit runs a macro and returns
+    pub macro_pending: bool,
 }
 
 impl CerialState {
@@ -81,19 +239,431 @@ impl CerialState {
         self
     }
 
+    /// Update file send settings
+    pub fn update_send_settings(mut self, settings: SendSettings) -> Self {
+        self.send_settings = settings;
+        self
+    }
+
+    /// Update line error marking
+    /// **Note** Takes effect when the port is next opened
+    pub fn update_mark_errors(mut self, mark_errors: bool) -> Self {
+        self.mark_errors = mark_errors;
+        self
+    }
+
+    /// Update the rates auto baud detection tries
+    pub fn update_baud_rates(mut self, baud_rates: Vec<u32>) -> Self {
+        self.baud_rates = baud_rates;
+        self
+    }
+
+    /// Update download directory
+    pub fn update_download_dir(mut self, download_dir: PathBuf) -> Self {
+        self.download_dir = download_dir;
+        self
+    }
+
+    /// Update macros
+    pub fn update_macros(mut self, macros: Macros) -> Self {
+        self.macros = macros;
+        self
+    }
+
+    /// Update the checksum appended to hex frames and macros
+    pub fn update_tx_checksum(mut self, tx_checksum: Option<TxChecksum>) -> Self {
+        self.tx_checksum = tx_checksum;
+        self
+    }
+
+    /// Update trigger rules
+    pub fn update_triggers(mut self, triggers: Triggers) -> Self {
+        self.triggers = triggers;
+        self
+    }
+
+    /// Update received text renderer
+    pub fn update_renderer(mut self, renderer: LineRenderer) -> Self {
+        self.renderer = renderer;
+        self
+    }
+
+    /// Update frame decoders
+    pub fn update_decoders(mut self, decoders: DecoderRegistry) -> Self {
+        self.decoders = decoders;
+        self
+    }
+
+    /// Update the decoder received data is shown with
+    pub fn update_frame_view(mut self, frame_view: Option<FrameView>) -> Self {
+        self.frame_view = frame_view;
+        self
+    }
+
+    /// Update transmit line editor
+    pub fn update_line_editor(mut self, line_editor: LineEditor) -> Self {
+        self.line_editor = line_editor;
+        self
+    }
+
+    /// Open a menu bar prompt, the current mode is restored when it closes
+    pub fn open_prompt(&mut self, kind: PromptKind) {
+        self.prompt = Some(Prompt {
+            kind,
+            input: String::new(),
+            return_mode: self.mode,
+        });
+        self.mode = CerialMode::Prompt;
+    }
+
+    /// Close the menu bar prompt, returning it
+    pub fn close_prompt(&mut self) -> Option<Prompt> {
+        let prompt = self.prompt.take();
+        self.mode = match &prompt {
+            Some(prompt) => prompt.return_mode,
+            None => CerialMode::Menu,
+        };
+        prompt
+    }
+
+    /// Update exec command
+    /// **Note** Does not spawn the command
+    pub fn update_exec_command(mut self, command: Vec<String>) -> Self {
+        self.serial_dev = command.join(" ");
+        self.exec_command = Some(command);
+        self
+    }
+
     /// Get the serial settings display string
     fn display_serial_settings(&self) -> String {
-        format!("{} {}", self.serial_dev, self.serial_settings.baud_rate)
+        if self.exec_command.is_some() {
+            self.serial_dev.clone()
+        } else {
+            let requested = self.serial_settings.baud_rate;
+            match self.serial_telemetry.baud_rate {
+                Some(actual) => match baud_rate_warning(requested, actual) {
+                    Some(warning) => format!("{} {} ({})", self.serial_dev, requested, warning),
+                    None => format!("{} {} (actual {})", self.serial_dev, requested, actual),
+                },
+                None => format!("{} {}", self.serial_dev, requested),
+            }
+        }
+    }
+
+    /// Get the number of bits sent for each character, including start, parity, and stop bits
+    fn frame_bits(&self) -> u32 {
+        let settings = &self.serial_settings;
+        let data_bits = match settings.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity_bits = match settings.parity {
+            Parity::None => 0,
+            Parity::Odd | Parity::Even => 1,
+        };
+        let stop_bits = match settings.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        1 + data_bits + parity_bits + stop_bits
+    }
+
+    /// Get the link counters and throughput display string
+    ///
+    /// Utilisation is left out for child processes, which have no baud rate
+    fn display_counters(&self) -> String {
+        let counters = &self.link_counters;
+        let throughput = &self.throughput;
+        let utilisation = |rate: f64| match self.exec_command {
+            Some(_) => String::new(),
+            None => {
+                let capacity = self.serial_settings.baud_rate as f64 / self.frame_bits() as f64;
+                format!(" {:.0}%", rate * 100.0 / capacity)
+            }
+        };
+
+        format!(
+            concat!(
+                "RX: {} B {:.0} B/s (peak {:.0}){} | TX: {} B {:.0} B/s (peak {:.0}){} | ",
+                "Errors: {}{}"
+            ),
+            counters.rx.load(Ordering::Relaxed),
+            throughput.rx_rate,
+            throughput.rx_peak,
+            utilisation(throughput.rx_rate),
+            counters.tx.load(Ordering::Relaxed),
+            throughput.tx_rate,
+            throughput.tx_peak,
+            utilisation(throughput.tx_rate),
+            counters.read_errors.load(Ordering::Relaxed),
+            self.display_line_errors()
+        )
+    }
+
+    /// Get the UART line error counters display string
+    #[cfg(target_os = "linux")]
+    fn display_line_errors(&self) -> String {
+        let counters = &self.link_counters;
+        format!(
+            " | Line errors: {} marked, Frame: {} Parity: {} Overrun: {} Break: {}",
+            counters.marked_bytes.load(Ordering::Relaxed),
+            counters.framing_errors.load(Ordering::Relaxed),
+            counters.parity_errors.load(Ordering::Relaxed),
+            counters.overruns.load(Ordering::Relaxed),
+            counters.breaks.load(Ordering::Relaxed)
+        )
+    }
+
+    /// Line errors aren't detected on this platform
+    #[cfg(not(target_os = "linux"))]
+    fn display_line_errors(&self) -> String {
+        String::new()
+    }
+
+    /// Set the link counters and throughput back to zero
+    pub fn reset_counters(&mut self) {
+        self.link_counters.reset();
+        self.throughput = Throughput::default();
+    }
+
+    /// Get the child process status display string
+    fn display_process_status(&self) -> String {
+        match self.process_exit {
+            Some(status) => format!(" | Process exited ({}), r to restart", status),
+            None => String::new(),
+        }
+    }
+
+    /// Scroll the receive pane back by `lines`, or forward if negative
+    pub fn scroll(&mut self, lines: isize) {
+        let max = self.scrollback.len().saturating_sub(1);
+        self.scroll_offset = if lines < 0 {
+            self.scroll_offset.saturating_sub(lines.unsigned_abs())
+        } else {
+            (self.scroll_offset + lines as usize).min(max)
+        };
+        self.redraw = true;
+    }
+
+    /// Get the scrollback position display string
+    fn display_scroll(&self) -> String {
+        if self.scroll_offset > 0 {
+            format!(" | Scrollback -{}, End to return", self.scroll_offset)
+        } else {
+            String::new()
+        }
+    }
+
+    /// Get the line filter display string
+    fn display_filter(&self) -> String {
+        if self.renderer.filter.is_active() {
+            format!(" | {}", self.renderer.filter)
+        } else {
+            String::new()
+        }
+    }
+
+    /// Get the frame decoder display string
+    fn display_decoder(&self) -> String {
+        match &self.frame_view {
+            Some(frame_view) => format!(" | {}", frame_view),
+            None => String::new(),
+        }
+    }
+
+    /// Get the TX checksum display string
+    fn display_tx_checksum(&self) -> String {
+        match &self.tx_checksum {
+            Some(tx_checksum) => format!(" | TX checksum: {}", tx_checksum),
+            None => String::new(),
+        }
+    }
+
+    /// Get the frame hex input mode will send, with the TX checksum appended
+    pub fn hex_frame(&self) -> Result<Vec<u8>, String> {
+        let digits: String = self
+            .line_editor
+            .text()
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        let mut frame = parse_hex_bytes(&digits)?;
+        if frame.is_empty() {
+            return Err("Enter hex bytes, e.g. 01 03 00 00 00 02".to_string());
+        }
+        if let Some(tx_checksum) = &self.tx_checksum {
+            tx_checksum.append(&mut frame);
+        }
+        Ok(frame)
+    }
+
+    /// Get the hex input mode menu line, a preview of the frame enter sends
+    fn display_hex_preview(&self) -> String {
+        match self.hex_frame() {
+            Ok(frame) => {
+                let hex: Vec<String> = frame.iter().map(|byte| format!("{:02x}", byte)).collect();
+                let checksum = match &self.tx_checksum {
+                    Some(tx_checksum) => format!(
+                        ", {} {}",
+                        tx_checksum,
+                        hex[hex.len() - tx_checksum.kind.size()..].join(" ")
+                    ),
+                    None => String::new(),
+                };
+                format!(
+                    "Send: {} ({} bytes{})",
+                    hex.join(" "),
+                    frame.len(),
+                    checksum
+                )
+            }
+            Err(msg) => format!("Send: {}", msg),
+        }
+    }
+
+    /// Get the file send, transfer, script, and capture status display string
+    fn display_send_status(&self) -> String {
+        let mut s = String::new();
+        if let Some(status) = &self.send_status {
+            s.push_str(&format!(" | {}", status));
+        }
+        if let Some(status) = &self.transfer_status {
+            s.push_str(&format!(" | {}", status));
+        }
+        if let Some(status) = &self.script_status {
+            s.push_str(&format!(" | {}", status));
+        }
+        if let Some(status) = &self.auto_baud_status {
+            s.push_str(&format!(" | {}", status));
+        }
+        if let Some(status) = &self.modbus_status {
+            s.push_str(&format!(" | {}", status));
+        }
+        if let Some(capture) = &self.capture {
+            s.push_str(&format!(" | {}", capture));
+        }
+        if let Some(status) = &self.capture_status {
+            s.push_str(&format!(" | {}", status));
+        }
+        s
+    }
+
+    /// Get the status panel lines
+    pub fn status_lines(&self) -> Vec<String> {
+        let settings = &self.serial_settings;
+        let data_bits = match settings.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity = match settings.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+        };
+        let stop_bits = match settings.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        let flow_control = match settings.flow_control {
+            FlowControl::None => "none",
+            FlowControl::Software => "software",
+            FlowControl::Hardware => "hardware",
+        };
+
+        let mut lines = vec![
+            format!(
+                "Mode: {} | {} {}{}{} | Flow control: {}",
+                self.mode,
+                self.display_serial_settings(),
+                data_bits,
+                parity,
+                stop_bits,
+                flow_control
+            ),
+            format!("{}", self.serial_telemetry),
+            self.display_counters(),
+        ];
+
+        // Keep AT responses near the top where the default panel height shows them
+        if let Some(at) = &self.at {
+            lines.insert(1, at.to_string());
+        }
+        if let Some(nmea) = &self.nmea {
+            lines.splice(1..1, nmea.status_lines());
+        }
+
+        let status = format!(
+            "{}{}{}{}{}",
+            self.display_process_status(),
+            self.display_filter(),
+            self.display_decoder(),
+            self.display_tx_checksum(),
+            self.display_send_status()
+        );
+        if let Some(status) = status.strip_prefix(" | ") {
+            lines.push(status.to_string());
+        }
+
+        lines
     }
 
     /// Get the current menu line
     pub fn menu_string(&self) -> String {
+        if let Some(prompt) = &self.prompt {
+            return match prompt.kind {
+                PromptKind::Triggers => {
+                    format!("{}{}: {}", self.triggers, prompt.kind, prompt.input)
+                }
+                PromptKind::Decoder => format!(
+                    "{} ({}): {}",
+                    prompt.kind,
+                    self.decoders.names().join(", "),
+                    prompt.input
+                ),
+                PromptKind::Checksum => format!(
+                    "{} ({}): {}",
+                    prompt.kind,
+                    ChecksumKind::NAMES.join(", "),
+                    prompt.input
+                ),
+                _ => format!("{}: {}", prompt.kind, prompt.input),
+            };
+        }
+
+        // Show the frame hex input mode will send before it's sent
+        if let CerialMode::HexInput = self.mode {
+            return self.display_hex_preview();
+        }
+
         match self.menu_state {
-            MenuState::Hidden => String::new(),
-            MenuState::SerialSettings => {
-                format!("{}: {}", self.mode, self.display_serial_settings())
-            }
-            MenuState::SerialTelemetry => format!("{}: {}", self.mode, self.serial_telemetry),
+            MenuState::Hidden => self.display_scroll().trim_start_matches(" | ").to_string(),
+            MenuState::SerialSettings => format!(
+                "{}: {}{}{}{}{}{}{}",
+                self.mode,
+                self.display_serial_settings(),
+                self.display_scroll(),
+                self.display_process_status(),
+                self.display_filter(),
+                self.display_decoder(),
+                self.display_tx_checksum(),
+                self.display_send_status()
+            ),
+            MenuState::SerialTelemetry => format!(
+                "{}: {} | {}{}{}{}{}{}{}",
+                self.mode,
+                self.serial_telemetry,
+                self.display_counters(),
+                self.display_scroll(),
+                self.display_process_status(),
+                self.display_filter(),
+                self.display_decoder(),
+                self.display_tx_checksum(),
+                self.display_send_status()
+            ),
         }
     }
 }
@@ -1,8 +1,15 @@
+use regex::bytes::Regex;
 use serialport::{DataBits, Error, ErrorKind, FlowControl, Parity, SerialPortSettings, StopBits};
 use std::ffi::OsString;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
-use structopt::StructOpt;
+use structopt::{clap, StructOpt};
+
+use crate::checksum::{ChecksumKind, Endian};
+use crate::pipe::PipeSettings;
+use crate::send::{LineEnding, SendSettings};
+use crate::transfer::{Direction, Protocol};
 
 /// Parse serial data bits from a str
 fn parse_data_bits(src: &str) -> Result<DataBits, Error> {
@@ -45,6 +52,16 @@ fn parse_parity(src: &str) -> Result<Parity, Error> {
     }
 }
 
+/// Parse a line ending from a str
+fn parse_line_ending(src: &str) -> Result<LineEnding, Error> {
+    match src.to_ascii_lowercase().as_str() {
+        "lf" => Ok(LineEnding::Lf),
+        "cr" => Ok(LineEnding::Cr),
+        "crlf" => Ok(LineEnding::CrLf),
+        _ => Err(Error::new(ErrorKind::InvalidInput, "Invalid line ending.")),
+    }
+}
+
 /// Parse stop bits from a str
 fn parse_stop_bits(src: &str) -> Result<StopBits, Error> {
     let bits = u8::from_str(src).map_err(|f| Error::new(ErrorKind::InvalidInput, f.to_string()))?;
@@ -55,13 +72,45 @@ fn parse_stop_bits(src: &str) -> Result<StopBits, Error> {
     }
 }
 
+// Cerial subcommands, doc comments on this enum would override the app about text
+#[derive(Debug, StructOpt, Clone)]
+pub enum CerialCommand {
+    /// Use a child process's stdin and stdout in place of a serial port
+    Exec {
+        /// Command to run followed by its arguments
+        #[structopt(required = true)]
+        command: Vec<String>,
+    },
+    /// Transfer files using XMODEM, XMODEM-1K, YMODEM, ZMODEM, or Kermit
+    Transfer {
+        /// Protocol xmodem, xmodem-1k, ymodem, zmodem, or kermit
+        protocol: Protocol,
+        /// Direction send or receive
+        direction: Direction,
+        /// Files to send, or the file (XMODEM) or directory (others) to receive into
+        paths: Vec<PathBuf>,
+    },
+    /// Send a Modbus RTU request and print the response, e.g. `modbus 1 read-holding 0 10`
+    Modbus {
+        /// Unit, function, address, then a count for reads or values for writes
+        #[structopt(required = true)]
+        command: Vec<String>,
+    },
+    /// Run a Rhai script against the port, alongside the UI or headless when piped
+    Run {
+        /// Script to run
+        #[structopt(parse(from_os_str))]
+        script: PathBuf,
+    },
+}
+
 #[derive(Debug, StructOpt, Clone)]
 #[structopt(name = "Cerial", about = "Part of a complete serial breakfast!")]
 pub struct CerialArgs {
-    /// Serial port
-    pub serial_port: OsString,
+    /// Serial port, or loop:// for a virtual loopback device
+    pub serial_port: Option<OsString>,
     /// Baud rate
-    pub baud_rate: u32,
+    pub baud_rate: Option<u32>,
     /// Data bits 5, 6, 7, or 8 bits
     #[structopt(short, long, default_value = "8", parse(try_from_str = parse_data_bits))]
     pub data_bits: DataBits,
@@ -77,17 +126,137 @@ pub struct CerialArgs {
     /// Timeout in milliseconds
     #[structopt(short, long, default_value = "10")]
     pub timeout: u64,
+    /// Try standard baud rates and lock onto the one that receives the most sensible text
+    #[structopt(long)]
+    pub auto_baud: bool,
+    /// Extra rates for --auto-baud to try, comma separated
+    #[structopt(long, use_delimiter = true)]
+    pub baud_rates: Vec<u32>,
+    /// Pipe mode: exit successfully once received data matches this regex
+    #[structopt(long)]
+    pub until: Option<Regex>,
+    /// Pipe mode: exit after this many milliseconds, fails if --until has not matched
+    #[structopt(long)]
+    pub exit_timeout: Option<u64>,
+    /// File to send once the port is open
+    #[structopt(long, parse(from_os_str))]
+    pub send: Option<PathBuf>,
+    /// File send: delay after each character in milliseconds
+    #[structopt(long, default_value = "0")]
+    pub char_delay: u64,
+    /// File send: delay after each line in milliseconds
+    #[structopt(long, default_value = "0")]
+    pub line_delay: u64,
+    /// File send: line ending to send, LF, CR, or CRLF
+    #[structopt(long, default_value = "lf", parse(try_from_str = parse_line_ending))]
+    pub line_ending: LineEnding,
+    /// File send: wait for received data to match this regex between lines
+    #[structopt(long)]
+    pub prompt: Option<Regex>,
+    /// File send: milliseconds to wait for the prompt
+    #[structopt(long, default_value = "5000")]
+    pub prompt_timeout: u64,
+    /// Config file, defaults to cerial/config.toml in the user config directory
+    #[structopt(long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+    /// Config profile to use
+    #[structopt(long)]
+    pub profile: Option<String>,
+    /// Highlight preset to apply to received text, zephyr, esp-idf, dmesg, at, or nmea
+    #[structopt(long)]
+    pub highlight: Vec<String>,
+    /// Show received data as frames decoded with slip, cobs, length, modbus, or a decoder from the
+    /// config
+    #[structopt(long)]
+    pub decode: Option<String>,
+    /// Show each decoded frame's raw bytes in hex
+    #[structopt(long)]
+    pub raw_hex: bool,
+    /// Checksum appended to hex frames and hex macros, crc8, crc16-ccitt, crc16-modbus, crc32,
+    /// xor, or sum8
+    #[structopt(long)]
+    pub tx_checksum: Option<ChecksumKind>,
+    /// TX checksum byte order, big or little, defaults to the checksum's usual order
+    #[structopt(long)]
+    pub tx_endian: Option<Endian>,
+    /// Show bytes received with framing or parity errors in reverse video, Linux only
+    #[structopt(long)]
+    pub mark_errors: bool,
+    /// Append all received data to this file, unfiltered
+    #[structopt(long, parse(from_os_str))]
+    pub log: Option<PathBuf>,
+    /// Directory received files are written to when no path is given
+    #[structopt(long, default_value = ".", parse(from_os_str))]
+    pub download_dir: PathBuf,
+    #[structopt(subcommand)]
+    pub command: Option<CerialCommand>,
+}
+
+impl From<CerialArgs> for PipeSettings {
+    fn from(args: CerialArgs) -> Self {
+        PipeSettings {
+            until: args.until,
+            timeout: args.exit_timeout.map(Duration::from_millis),
+            frame_view: None,
+            log_file: None,
+            tx_checksum: None,
+        }
+    }
+}
+
+impl From<CerialArgs> for SendSettings {
+    fn from(args: CerialArgs) -> Self {
+        SendSettings {
+            char_delay: Duration::from_millis(args.char_delay),
+            line_delay: Duration::from_millis(args.line_delay),
+            line_ending: args.line_ending,
+            prompt: args.prompt,
+            prompt_timeout: Duration::from_millis(args.prompt_timeout),
+        }
+    }
+}
+
+impl CerialArgs {
+    /// Exit with an error if options that only apply in pipe mode are given
+    pub fn reject_pipe_options(&self) {
+        if self.until.is_some() || self.exit_timeout.is_some() {
+            clap::Error::with_description(
+                "--until and --exit-timeout only apply when stdout is not a terminal",
+                clap::ErrorKind::ArgumentConflict,
+            )
+            .exit()
+        }
+    }
+
+    /// Get the serial port and baud rate, exits with a usage error if either are missing
+    ///
+    /// The baud rate may be left out with `--auto-baud`, it is only the rate detection starts at
+    pub fn require_serial_port(&self) -> (OsString, u32) {
+        match (&self.serial_port, self.baud_rate) {
+            (Some(serial_port), Some(baud_rate)) => (serial_port.clone(), baud_rate),
+            (Some(serial_port), None) if self.auto_baud => {
+                (serial_port.clone(), SerialPortSettings::default().baud_rate)
+            }
+            _ => clap::Error::with_description(
+                "The serial port and baud rate are required",
+                clap::ErrorKind::MissingRequiredArgument,
+            )
+            .exit(),
+        }
+    }
 }
 
-impl Into<SerialPortSettings> for CerialArgs {
-    fn into(self) -> SerialPortSettings {
+impl From<CerialArgs> for SerialPortSettings {
+    fn from(args: CerialArgs) -> Self {
         SerialPortSettings {
-            baud_rate: self.baud_rate,
-            data_bits: self.data_bits,
-            flow_control: self.flow_control,
-            parity: self.parity,
-            stop_bits: self.stop_bits,
-            timeout: Duration::from_millis(self.timeout),
+            baud_rate: args
+                .baud_rate
+                .unwrap_or_else(|| SerialPortSettings::default().baud_rate),
+            data_bits: args.data_bits,
+            flow_control: args.flow_control,
+            parity: args.parity,
+            stop_bits: args.stop_bits,
+            timeout: Duration::from_millis(args.timeout),
         }
     }
 }
@@ -0,0 +1,396 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// Commands without a final result code after this long are given up on
+///
+/// Network scans like `AT+COPS=?` can take a few minutes
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// Longest response line kept, longer lines are cut
+const MAX_LINE: usize = 1024;
+
+/// Built-in command catalog used for completion
+const CATALOG: &[(&str, &str)] = &[
+    ("AT", "Check the modem responds"),
+    ("AT&F", "Restore factory settings"),
+    ("AT&W", "Save settings"),
+    ("AT+CCID", "SIM card ICCID"),
+    ("AT+CEREG?", "EPS network registration"),
+    ("AT+CFUN=0", "Minimum functionality"),
+    ("AT+CFUN=1", "Full functionality"),
+    ("AT+CFUN?", "Functionality level"),
+    ("AT+CGATT?", "Packet domain attach state"),
+    ("AT+CGDCONT?", "PDP contexts"),
+    ("AT+CGMI", "Manufacturer"),
+    ("AT+CGMM", "Model"),
+    ("AT+CGMR", "Firmware revision"),
+    ("AT+CGPADDR", "PDP addresses"),
+    ("AT+CGREG?", "GPRS network registration"),
+    ("AT+CGSN", "IMEI"),
+    ("AT+CIMI", "IMSI"),
+    ("AT+CLIP=1", "Enable caller ID"),
+    ("AT+CMEE=2", "Verbose error results"),
+    ("AT+CMGF=1", "SMS text mode"),
+    ("AT+CMGL=\"ALL\"", "List SMS messages"),
+    ("AT+CMGR=", "Read SMS message"),
+    ("AT+CMGS=", "Send SMS message"),
+    ("AT+CNMI=2,1", "New SMS indications"),
+    ("AT+COPS=?", "Scan networks"),
+    ("AT+COPS?", "Current operator"),
+    ("AT+CPIN?", "SIM PIN state"),
+    ("AT+CPIN=", "Enter SIM PIN"),
+    ("AT+CREG=2", "Enable registration URCs with location"),
+    ("AT+CREG?", "Network registration"),
+    ("AT+CSQ", "Signal quality"),
+    ("AT+GMR", "Firmware revision"),
+    ("AT+IPR?", "Baud rate"),
+    ("ATA", "Answer call"),
+    ("ATD", "Dial"),
+    ("ATE0", "Disable echo"),
+    ("ATE1", "Enable echo"),
+    ("ATH", "Hang up"),
+    ("ATI", "Product information"),
+    ("ATZ", "Reset settings"),
+];
+
+/// Unsolicited result codes, lines starting with these aren't part of another command's response
+pub const URC_PREFIXES: &[&str] = &[
+    "RING", "+CRING:", "+CLIP:", "+CMTI:", "+CMT:", "+CDS:", "+CREG:", "+CGREG:", "+CEREG:",
+    "+CUSD:", "+CGEV:", "+CPIN:", "+QIURC:", "+UUSORD:", "+UUSOCL:",
+];
+
+/// How a command ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum AtResult {
+    /// `OK` or `CONNECT`
+    Ok(String),
+    /// `ERROR`, `+CME ERROR`, `+CMS ERROR`, or a call failure
+    Error(String),
+    /// No final result code in time
+    Timeout,
+}
+
+impl Display for AtResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtResult::Ok(code) | AtResult::Error(code) => write!(f, "{}", code),
+            AtResult::Timeout => write!(f, "no response"),
+        }
+    }
+}
+
+impl AtResult {
+    /// Get the result for a final result code line
+    fn parse(line: &str) -> Option<Self> {
+        match line {
+            "OK" => Some(AtResult::Ok(line.to_string())),
+            "ERROR" | "NO CARRIER" | "BUSY" | "NO ANSWER" | "NO DIALTONE" => {
+                Some(AtResult::Error(line.to_string()))
+            }
+            _ if line.starts_with("CONNECT") => Some(AtResult::Ok(line.to_string())),
+            _ if line.starts_with("+CME ERROR:") || line.starts_with("+CMS ERROR:") => {
+                Some(AtResult::Error(line.to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Command response, grouped up to its final result code
+#[derive(Debug, Clone)]
+pub struct AtResponse {
+    /// Command sent
+    pub command: String,
+    /// Information lines, without the echo and final result code
+    pub lines: Vec<String>,
+    /// Final result
+    pub result: AtResult,
+    /// Time from sending the command to its final result
+    pub latency: Duration,
+}
+
+impl Display for AtResponse {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.command, self.result)?;
+        match self.lines.len() {
+            0 => {}
+            1 => write!(f, ", 1 line")?,
+            n => write!(f, ", {} lines", n)?,
+        }
+        write!(f, ", {} ms", self.latency.as_millis())
+    }
+}
+
+/// Command waiting for its final result code
+#[derive(Debug, Clone)]
+struct Pending {
+    /// Command sent
+    command: String,
+    /// Prefix of information lines in the response, e.g. `+CSQ` for `AT+CSQ`
+    prefix: Option<String>,
+    /// When the command was sent
+    sent: Instant,
+    /// Information lines so far
+    lines: Vec<String>,
+}
+
+/// Tracks AT commands and groups the received lines into responses
+#[derive(Debug, Clone, Default)]
+pub struct AtSession {
+    /// Command waiting for its final result code
+    pending: Option<Pending>,
+    /// Current received line
+    line: Vec<u8>,
+    /// Last finished command
+    last_response: Option<AtResponse>,
+    /// Last unsolicited result code
+    last_urc: Option<String>,
+    /// Catalog entries matching the last completion
+    completions: Vec<&'static str>,
+}
+
+impl AtSession {
+    /// Start waiting for the response to a command
+    ///
+    /// A command still waiting for its response is given up on. Blank lines aren't commands.
+    pub fn command_sent(&mut self, command: &str) {
+        let command = command.trim().to_string();
+        if command.is_empty() {
+            return;
+        }
+        // Responses use the upper case name whatever case the command was typed in
+        let prefix = command
+            .get(2..)
+            .filter(|name| name.starts_with('+'))
+            .map(|name| {
+                name.split(['=', '?'])
+                    .next()
+                    .unwrap_or(name)
+                    .to_ascii_uppercase()
+            });
+
+        self.pending = Some(Pending {
+            command,
+            prefix,
+            sent: Instant::now(),
+            lines: Vec::new(),
+        });
+        self.completions.clear();
+    }
+
+    /// Process received data, returning finished commands with the offset just past the line
+    /// their final result code ends on
+    pub fn process(&mut self, data: &[u8]) -> Vec<(usize, AtResponse)> {
+        let mut events = Vec::new();
+        for (i, &byte) in data.iter().enumerate() {
+            match byte {
+                b'\n' => {
+                    let line = String::from_utf8_lossy(&self.line).trim().to_string();
+                    self.line.clear();
+                    if let Some(response) = self.process_line(line) {
+                        events.push((i + 1, response));
+                    }
+                }
+                _ if self.line.len() < MAX_LINE => self.line.push(byte),
+                _ => {}
+            }
+        }
+        events
+    }
+
+    /// Classify a received line, returning the response it finishes
+    fn process_line(&mut self, line: String) -> Option<AtResponse> {
+        if line.is_empty() {
+            return None;
+        }
+
+        let pending = match &mut self.pending {
+            Some(pending) => pending,
+            None => {
+                // Anything received while no command is waiting is unsolicited
+                if AtResult::parse(&line).is_none() {
+                    self.last_urc = Some(line);
+                }
+                return None;
+            }
+        };
+
+        if let Some(result) = AtResult::parse(&line) {
+            let pending = self.pending.take().unwrap();
+            return Some(self.finish(pending, result));
+        }
+
+        // Skip the command echo
+        if line.eq_ignore_ascii_case(&pending.command) {
+            return None;
+        }
+
+        // URCs can arrive in the middle of a response, unless it's the command's own
+        let own_prefix = pending
+            .prefix
+            .as_ref()
+            .is_some_and(|prefix| line.starts_with(prefix.as_str()));
+        if !own_prefix && is_urc(&line) {
+            self.last_urc = Some(line);
+            return None;
+        }
+
+        pending.lines.push(line);
+        None
+    }
+
+    /// Record a finished command
+    fn finish(&mut self, pending: Pending, result: AtResult) -> AtResponse {
+        let response = AtResponse {
+            command: pending.command,
+            lines: pending.lines,
+            result,
+            latency: pending.sent.elapsed(),
+        };
+        self.last_response = Some(response.clone());
+        response
+    }
+
+    /// Give up on a command that has waited too long for its response
+    pub fn check_timeout(&mut self) -> Option<AtResponse> {
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|pending| pending.sent.elapsed() > RESPONSE_TIMEOUT)
+        {
+            let pending = self.pending.take().unwrap();
+            Some(self.finish(pending, AtResult::Timeout))
+        } else {
+            None
+        }
+    }
+
+    /// Complete a command from the catalog, returning the completed line
+    ///
+    /// Several matches are completed as far as they agree and listed in the status
+    pub fn complete(&mut self, line: &str) -> Option<String> {
+        let upper = line.to_ascii_uppercase();
+        self.completions = CATALOG
+            .iter()
+            .map(|(command, _)| *command)
+            .filter(|command| command.starts_with(&upper))
+            .collect();
+
+        let first = self.completions.first()?;
+        let common = self.completions.iter().fold(first.len(), |len, command| {
+            first
+                .bytes()
+                .zip(command.bytes())
+                .take(len)
+                .take_while(|(a, b)| a == b)
+                .count()
+        });
+        Some(first[..common].to_string())
+    }
+}
+
+impl Display for AtSession {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.completions.as_slice() {
+            [] => {}
+            [command] => {
+                let description = CATALOG
+                    .iter()
+                    .find(|(c, _)| c == command)
+                    .map_or("", |(_, d)| d);
+                return write!(f, "AT: {} - {}", command, description);
+            }
+            commands => return write!(f, "AT: {}", commands.join(" ")),
+        }
+
+        match (&self.pending, &self.last_response) {
+            (Some(pending), _) => write!(
+                f,
+                "AT: waiting for {} ({:.1} s)",
+                pending.command,
+                pending.sent.elapsed().as_secs_f32()
+            )?,
+            (None, Some(response)) => write!(f, "AT: {}", response)?,
+            (None, None) => write!(f, "AT: ready, Tab completes commands")?,
+        }
+        if let Some(urc) = &self.last_urc {
+            write!(f, " | Last URC: {}", urc)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check if a line is an unsolicited result code
+fn is_urc(line: &str) -> bool {
+    URC_PREFIXES.iter().any(|prefix| line.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send a command and feed the session its response
+    fn run(session: &mut AtSession, command: &str, received: &str) -> Vec<AtResponse> {
+        session.command_sent(command);
+        session
+            .process(received.as_bytes())
+            .into_iter()
+            .map(|(_, response)| response)
+            .collect()
+    }
+
+    #[test]
+    fn groups_response() {
+        let mut session = AtSession::default();
+        let responses = run(
+            &mut session,
+            "AT+CSQ",
+            "AT+CSQ\r\r\n+CSQ: 20,99\r\n\r\nOK\r\n",
+        );
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].command, "AT+CSQ");
+        assert_eq!(responses[0].lines, vec!["+CSQ: 20,99"]);
+        assert_eq!(responses[0].result, AtResult::Ok("OK".to_string()));
+    }
+
+    #[test]
+    fn final_result_codes() {
+        let mut session = AtSession::default();
+        for (received, result) in [
+            ("ERROR", AtResult::Error("ERROR".to_string())),
+            (
+                "+CME ERROR: 10",
+                AtResult::Error("+CME ERROR: 10".to_string()),
+            ),
+            ("NO CARRIER", AtResult::Error("NO CARRIER".to_string())),
+            ("CONNECT 115200", AtResult::Ok("CONNECT 115200".to_string())),
+        ] {
+            let responses = run(&mut session, "ATD123", &format!("{}\r\n", received));
+            assert_eq!(responses[0].result, result);
+        }
+    }
+
+    #[test]
+    fn urcs() {
+        let mut session = AtSession::default();
+        let responses = run(
+            &mut session,
+            "at+creg?",
+            "+CREG: 0,1\r\nRING\r\n+CMTI: \"SM\",3\r\nOK\r\n",
+        );
+        // The command's own prefix is part of the response even when typed in lower case
+        assert_eq!(responses[0].lines, vec!["+CREG: 0,1"]);
+        assert_eq!(session.last_urc.as_deref(), Some("+CMTI: \"SM\",3"));
+
+        session.process(b"RING\r\n");
+        assert_eq!(session.last_urc.as_deref(), Some("RING"));
+    }
+
+    #[test]
+    fn blank_command() {
+        let mut session = AtSession::default();
+        session.command_sent("ATI");
+        session.command_sent("  ");
+        assert_eq!(session.pending.as_ref().unwrap().command, "ATI");
+    }
+}
@@ -0,0 +1,289 @@
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serialport::{ClearBuffer, SerialPort};
+
+use crate::serial::LinkCounters;
+use crate::ui::DisplayUpdateEvent;
+
+/// Rates tried by default, slowest first
+pub const STANDARD_RATES: &[u32] = &[
+    1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600,
+];
+
+/// Longest time to listen at each rate
+const SAMPLE_TIME: Duration = Duration::from_millis(1000);
+
+/// Stop listening at a rate once this much has been received
+const SAMPLE_SIZE: usize = 256;
+
+/// Data received right after a rate change may have been sent at the old rate
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+/// Fewer bytes than this aren't enough to judge a rate
+const MIN_SAMPLE_SIZE: usize = 16;
+
+/// A rate scoring this well is locked onto without trying the rest
+const GOOD_SCORE: f64 = 1.05;
+
+/// Auto baud status
+#[derive(Debug, Clone)]
+pub enum AutoBaudStatus {
+    /// Listening at a rate
+    Trying(u32),
+    /// Settled on the rate that scored best
+    Locked(u32),
+    /// No rate could be picked, or detection was cancelled
+    Failed(String),
+}
+
+impl Display for AutoBaudStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AutoBaudStatus::Trying(rate) => write!(f, "Auto baud: trying {}", rate),
+            AutoBaudStatus::Locked(rate) => write!(f, "Auto baud: locked to {}", rate),
+            AutoBaudStatus::Failed(msg) => write!(f, "Auto baud failed: {}", msg),
+        }
+    }
+}
+
+/// Score received data on how likely it was received at the right rate, higher is better
+///
+/// Text received at the wrong rate has unprintable bytes and line errors, and rarely has line
+/// endings at sensible intervals
+pub fn score(data: &[u8], line_errors: u64) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let len = data.len() as f64;
+
+    let printable = data
+        .iter()
+        .filter(|b| matches!(b, 0x20..=0x7e | b'\r' | b'\n' | b'\t'))
+        .count();
+    let errors = (line_errors as f64 / len).min(1.0);
+
+    let lines = data.iter().filter(|b| **b == b'\n').count();
+    let structure = match data.len().checked_div(lines) {
+        Some(line_length) if (2..=160).contains(&line_length) => 1.0,
+        Some(_) => 0.5,
+        None => 0.0,
+    };
+
+    printable as f64 / len - errors + 0.1 * structure
+}
+
+/// Handle to a running auto baud detection
+#[derive(Debug, Clone)]
+pub struct AutoBaud {
+    /// Forwards received serial data to the detection thread
+    pub serial_input_tx: Sender<Vec<u8>>,
+    /// Set to cancel detection
+    cancel: Arc<AtomicBool>,
+}
+
+impl AutoBaud {
+    /// Start trying rates in a new thread, the port is left at the best rate
+    pub fn start(
+        rates: Vec<u32>,
+        serial_port: Arc<Mutex<Box<dyn SerialPort>>>,
+        counters: Arc<LinkCounters>,
+        display_update_tx: Sender<DisplayUpdateEvent>,
+    ) -> Self {
+        let (serial_input_tx, serial_input_rx) = channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let thread_cancel = cancel.clone();
+
+        thread::spawn(move || {
+            let detector = Detector {
+                serial_port,
+                counters,
+                serial_input_rx,
+                display_update_tx: display_update_tx.clone(),
+                cancel: thread_cancel,
+            };
+            let status = match detector.run(&rates) {
+                Ok(rate) => AutoBaudStatus::Locked(rate),
+                Err(msg) => AutoBaudStatus::Failed(msg),
+            };
+            display_update_tx
+                .send(DisplayUpdateEvent::AutoBaud(status))
+                .ok();
+        });
+
+        Self {
+            serial_input_tx,
+            cancel,
+        }
+    }
+
+    /// Cancel detection, the port goes back to its original rate
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Get the rates to try, the standard rates followed by any others listed
+pub fn rates(extra: &[u32]) -> Vec<u32> {
+    let mut rates = STANDARD_RATES.to_vec();
+    rates.extend(extra.iter().filter(|rate| !STANDARD_RATES.contains(rate)));
+    rates
+}
+
+/// State of the detection thread
+struct Detector {
+    /// Serial port, used to change rates
+    serial_port: Arc<Mutex<Box<dyn SerialPort>>>,
+    /// Line error counts
+    counters: Arc<LinkCounters>,
+    /// Received serial data
+    serial_input_rx: Receiver<Vec<u8>>,
+    /// Channel to report progress on
+    display_update_tx: Sender<DisplayUpdateEvent>,
+    /// Cancel flag
+    cancel: Arc<AtomicBool>,
+}
+
+impl Detector {
+    /// Try each rate, returning the best one once the port has been set to it
+    fn run(&self, rates: &[u32]) -> Result<u32, String> {
+        let original = self.lock_port()?.baud_rate().map_err(|e| e.to_string())?;
+
+        let mut best: Option<(u32, f64)> = None;
+        for &rate in rates {
+            let score = match self.try_rate(rate) {
+                Ok(score) => score,
+                Err(msg) => {
+                    self.set_rate(original).ok();
+                    return Err(msg);
+                }
+            };
+
+            if let Some(score) = score {
+                if best.is_none_or(|(_, best)| score > best) {
+                    best = Some((rate, score));
+                }
+                if score >= GOOD_SCORE {
+                    break;
+                }
+            }
+        }
+
+        match best {
+            Some((rate, _)) => {
+                self.set_rate(rate)?;
+                Ok(rate)
+            }
+            None => {
+                self.set_rate(original)?;
+                Err("nothing received".to_string())
+            }
+        }
+    }
+
+    /// Listen at a rate, returning its score or `None` if too little was received
+    fn try_rate(&self, rate: u32) -> Result<Option<f64>, String> {
+        self.display_update_tx
+            .send(DisplayUpdateEvent::AutoBaud(AutoBaudStatus::Trying(rate)))
+            .ok();
+        self.set_rate(rate)?;
+
+        // Throw away anything received at the old rate
+        thread::sleep(SETTLE_TIME);
+        self.lock_port()?.clear(ClearBuffer::Input).ok();
+        while self.serial_input_rx.try_recv().is_ok() {}
+        let errors_before = self.line_errors();
+
+        let mut data = Vec::new();
+        let deadline = Instant::now() + SAMPLE_TIME;
+        while data.len() < SAMPLE_SIZE {
+            if self.cancel.load(Ordering::Relaxed) {
+                return Err("cancelled".to_string());
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            match self
+                .serial_input_rx
+                .recv_timeout(remaining.min(Duration::from_millis(100)))
+            {
+                Ok(received) => data.extend(received),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err("port closed".to_string()),
+            }
+        }
+
+        if data.len() < MIN_SAMPLE_SIZE {
+            return Ok(None);
+        }
+        let errors = self.line_errors().saturating_sub(errors_before);
+        Ok(Some(score(&data, errors)))
+    }
+
+    /// Lock the serial port
+    fn lock_port(&self) -> Result<std::sync::MutexGuard<'_, Box<dyn SerialPort>>, String> {
+        self.serial_port
+            .lock()
+            .map_err(|_| "serial port lock poisoned".to_string())
+    }
+
+    /// Change the port's rate
+    fn set_rate(&self, rate: u32) -> Result<(), String> {
+        self.lock_port()?
+            .set_baud_rate(rate)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Total line errors counted so far
+    ///
+    /// Only bytes marked by the kernel are counted, drivers that keep their own error counts
+    /// count the same bytes again
+    fn line_errors(&self) -> u64 {
+        self.counters.marked_bytes.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &[u8] = b"[    0.000000] Booting Linux on physical CPU 0x0\r\nlogin: ";
+
+    #[test]
+    fn clean_text() {
+        assert!(score(TEXT, 0) >= GOOD_SCORE);
+        assert!(score(b"OK\r\n", 0) >= GOOD_SCORE);
+        assert_eq!(score(b"", 0), 0.0);
+    }
+
+    #[test]
+    fn garbage() {
+        // Text received at twice the baud rate it was sent at
+        let garbage = [
+            0x80, 0xf8, 0x00, 0x78, 0xe0, 0x1e, 0x86, 0xfe, 0x00, 0x98, 0x0a, 0xf0,
+        ];
+        assert!(score(&garbage, 0) < score(TEXT, 0) - 0.5);
+        assert!(score(&garbage, 0) < GOOD_SCORE);
+    }
+
+    #[test]
+    fn line_errors() {
+        let clean = score(TEXT, 0);
+        let noisy = score(TEXT, 5);
+        assert!(noisy < clean);
+        assert!(noisy < GOOD_SCORE);
+        assert!(score(TEXT, 1000) < 0.5);
+    }
+
+    #[test]
+    fn structure() {
+        // Printable data with no line endings is less likely to be right
+        assert!(score(&[b'U'; 64], 0) < GOOD_SCORE);
+        assert!(score(&[b'U'; 64], 0) < score(TEXT, 0));
+    }
+}
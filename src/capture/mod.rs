@@ -0,0 +1,265 @@
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::plot::parse_fields;
+
+/// Longest line parsed, longer lines are cut
+const MAX_LINE: usize = 1024;
+
+/// How a capture ended
+#[derive(Debug, Clone)]
+pub enum CaptureStatus {
+    /// Stopped from the menu
+    Stopped {
+        /// File written
+        path: PathBuf,
+        /// Rows written
+        rows: u64,
+    },
+    /// Writing the file failed
+    Failed(String),
+}
+
+impl Display for CaptureStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureStatus::Stopped { path, rows } => {
+                write!(f, "Captured {} rows to {}", rows, path.display())
+            }
+            CaptureStatus::Failed(msg) => write!(f, "Capture failed: {}", msg),
+        }
+    }
+}
+
+/// Captures numeric fields of received lines to a CSV file
+///
+/// Each line with numbers is a row with its UTC time, seconds since the capture started, and a
+/// column per series, left empty when the line doesn't have that series.
+#[derive(Debug)]
+pub struct CsvCapture {
+    /// File being written
+    path: PathBuf,
+    /// Open file
+    file: File,
+    /// Series names seen so far
+    series: Vec<String>,
+    /// Rows written, one per line with numbers
+    rows: u64,
+    /// Current received line
+    line: Vec<u8>,
+    /// When the capture started
+    start: Instant,
+}
+
+impl CsvCapture {
+    /// Create the file, write the header, and start capturing
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        writeln!(file, "time,elapsed")?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            series: Vec::new(),
+            rows: 0,
+            line: Vec::new(),
+            start: Instant::now(),
+        })
+    }
+
+    /// Get a file name in `dir` for a capture started now
+    pub fn default_path(dir: &Path) -> PathBuf {
+        let time = utc_timestamp(SystemTime::now());
+        let name: String = time[..19]
+            .chars()
+            .filter(|c| c.is_ascii_digit() || *c == 'T')
+            .collect();
+        dir.join(format!("capture-{}.csv", name.replace('T', "-")))
+    }
+
+    /// Process received data, writing rows for each line with numbers
+    pub fn process(&mut self, data: &[u8]) -> io::Result<()> {
+        for &byte in data {
+            match byte {
+                b'\n' => {
+                    let line = String::from_utf8_lossy(&self.line).to_string();
+                    self.line.clear();
+                    self.add_line(&line)?;
+                }
+                _ if self.line.len() < MAX_LINE => self.line.push(byte),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a row for a line, adding columns for series not seen before
+    fn add_line(&mut self, line: &str) -> io::Result<()> {
+        let fields = parse_fields(line);
+        if fields.is_empty() {
+            return Ok(());
+        }
+
+        let known = self.series.len();
+        for (name, _) in &fields {
+            if !self.series.contains(name) {
+                self.series.push(name.clone());
+            }
+        }
+        if self.series.len() > known {
+            self.rewrite_header(self.series.len() - known)?;
+        }
+
+        // Later values of a series repeated in one line win
+        let mut values = vec![String::new(); self.series.len()];
+        for (name, value) in &fields {
+            if let Some(i) = self.series.iter().position(|s| s == name) {
+                values[i] = value.to_string();
+            }
+        }
+        let row = format!(
+            "{},{:.3},{}\n",
+            utc_timestamp(SystemTime::now()),
+            self.start.elapsed().as_secs_f64(),
+            values.join(",")
+        );
+        self.file.write_all(row.as_bytes())?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Rewrite the file with a header for every series, padding the rows already written with
+    /// `added` empty cells so they keep the same number of columns
+    fn rewrite_header(&mut self, added: usize) -> io::Result<()> {
+        let mut contents = String::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_string(&mut contents)?;
+
+        let mut header = String::from("time,elapsed");
+        for name in &self.series {
+            header.push(',');
+            header.push_str(&csv_field(name));
+        }
+        header.push('\n');
+        let padding = ",".repeat(added);
+        for row in contents.lines().skip(1) {
+            header.push_str(row);
+            header.push_str(&padding);
+            header.push('\n');
+        }
+
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(header.as_bytes())
+    }
+
+    /// Stop capturing
+    pub fn stop(self) -> CaptureStatus {
+        CaptureStatus::Stopped {
+            path: self.path,
+            rows: self.rows,
+        }
+    }
+}
+
+impl Display for CsvCapture {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Capturing to {}, {} rows, {} series",
+            self.path.display(),
+            self.rows,
+            self.series.len()
+        )
+    }
+}
+
+/// Quote a CSV field if it needs it
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Format a time as UTC ISO 8601, e.g. `2026-10-19T12:34:56.789Z`
+fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+    // Convert days since 1970-01-01 to a civil date
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let leap_day = UNIX_EPOCH + Duration::from_millis(951_782_400_000 + 45_296_789);
+        assert_eq!(utc_timestamp(leap_day), "2000-02-29T12:34:56.789Z");
+        let new_year = UNIX_EPOCH + Duration::from_secs(1_798_761_599);
+        assert_eq!(utc_timestamp(new_year), "2026-12-31T23:59:59.000Z");
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(csv_field("temp"), "temp");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn wide_rows() {
+        let path = std::env::temp_dir().join(format!("cerial-capture-{}.csv", std::process::id()));
+        let mut capture = CsvCapture::create(&path).unwrap();
+        capture
+            .process(b"temp: 23.4\nboot ok\ntemp: 23.5 hum: 40\nhum: 41\nhu")
+            .unwrap();
+        assert_eq!(capture.to_string().split(", ").nth(1), Some("3 rows"));
+        assert_eq!(capture.to_string().split(", ").nth(2), Some("2 series"));
+        drop(capture);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let rows: Vec<Vec<&str>> = contents.lines().map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0], ["time", "elapsed", "temp", "hum"]);
+        assert!(rows.iter().all(|row| row.len() == 4));
+        let values: Vec<&[&str]> = rows[1..].iter().map(|row| &row[2..]).collect();
+        assert_eq!(values, [&["23.4", ""], &["23.5", "40"], &["", "41"]]);
+    }
+}
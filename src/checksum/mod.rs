@@ -0,0 +1,357 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+/// CRC-16/XMODEM (poly 0x1021, init 0x0000), used by XMODEM and YMODEM
+pub fn crc16_xmodem(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, byte| {
+        let mut crc = crc ^ ((*byte as u16) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// 8-bit sum of all bytes
+pub fn sum8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// CRC-32 (reflected poly 0xEDB88320, init 0xFFFFFFFF), used by ZMODEM
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(0xffff_ffffu32, |crc, byte| {
+        let mut crc = crc ^ *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+/// CRC-16/KERMIT (reflected poly 0x8408, init 0x0000), used by Kermit block check type 3
+pub fn crc16_kermit(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, byte| {
+        let mut crc = crc ^ *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+/// CRC-16/MODBUS (reflected poly 0xA001, init 0xFFFF), sent low byte first by Modbus RTU
+pub fn crc16_modbus(data: &[u8]) -> u16 {
+    data.iter().fold(0xffffu16, |crc, byte| {
+        let mut crc = crc ^ *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+/// XOR of all bytes, used by NMEA 0183 sentences
+pub fn xor8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |xor, byte| xor ^ byte)
+}
+
+/// 8-bit Fletcher checksum `(CK_A, CK_B)`, used by u-blox UBX frames
+pub fn fletcher8(data: &[u8]) -> (u8, u8) {
+    data.iter().fold((0u8, 0u8), |(a, b), byte| {
+        let a = a.wrapping_add(*byte);
+        (a, b.wrapping_add(a))
+    })
+}
+
+/// CRC-8/SMBUS (poly 0x07, init 0x00)
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, byte| {
+        let mut crc = crc ^ byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF)
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    data.iter().fold(0xffffu16, |crc, byte| {
+        let mut crc = crc ^ ((*byte as u16) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// Checksum that can be appended to sent frames
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChecksumKind {
+    /// CRC-8/SMBUS
+    Crc8,
+    /// CRC-16/CCITT-FALSE
+    Crc16Ccitt,
+    /// CRC-16/MODBUS
+    Crc16Modbus,
+    /// CRC-32
+    Crc32,
+    /// XOR of all bytes
+    Xor,
+    /// 8-bit sum of all bytes
+    Sum8,
+}
+
+impl ChecksumKind {
+    /// Names of every kind, as parsed and displayed
+    pub const NAMES: &'static [&'static str] = &[
+        "crc8",
+        "crc16-ccitt",
+        "crc16-modbus",
+        "crc32",
+        "xor",
+        "sum8",
+    ];
+
+    /// Size of the checksum in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            ChecksumKind::Crc8 | ChecksumKind::Xor | ChecksumKind::Sum8 => 1,
+            ChecksumKind::Crc16Ccitt | ChecksumKind::Crc16Modbus => 2,
+            ChecksumKind::Crc32 => 4,
+        }
+    }
+
+    /// Byte order the checksum is usually sent in, Modbus and CRC-32 are little endian
+    pub fn default_endian(&self) -> Endian {
+        match self {
+            ChecksumKind::Crc16Modbus | ChecksumKind::Crc32 => Endian::Little,
+            _ => Endian::Big,
+        }
+    }
+
+    /// Calculate the checksum of data
+    pub fn compute(&self, data: &[u8]) -> u32 {
+        match self {
+            ChecksumKind::Crc8 => crc8(data) as u32,
+            ChecksumKind::Crc16Ccitt => crc16_ccitt(data) as u32,
+            ChecksumKind::Crc16Modbus => crc16_modbus(data) as u32,
+            ChecksumKind::Crc32 => crc32(data),
+            ChecksumKind::Xor => xor8(data) as u32,
+            ChecksumKind::Sum8 => sum8(data) as u32,
+        }
+    }
+}
+
+impl FromStr for ChecksumKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "crc8" => Ok(ChecksumKind::Crc8),
+            "crc16-ccitt" => Ok(ChecksumKind::Crc16Ccitt),
+            "crc16-modbus" => Ok(ChecksumKind::Crc16Modbus),
+            "crc32" => Ok(ChecksumKind::Crc32),
+            "xor" => Ok(ChecksumKind::Xor),
+            "sum8" => Ok(ChecksumKind::Sum8),
+            _ => Err(format!(
+                "Unknown checksum '{}', expected one of {}",
+                s,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl Display for ChecksumKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ChecksumKind::Crc8 => "crc8",
+            ChecksumKind::Crc16Ccitt => "crc16-ccitt",
+            ChecksumKind::Crc16Modbus => "crc16-modbus",
+            ChecksumKind::Crc32 => "crc32",
+            ChecksumKind::Xor => "xor",
+            ChecksumKind::Sum8 => "sum8",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Byte order of a multi-byte checksum
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    /// Most significant byte first
+    Big,
+    /// Least significant byte first
+    Little,
+}
+
+impl FromStr for Endian {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "big" | "be" => Ok(Endian::Big),
+            "little" | "le" => Ok(Endian::Little),
+            _ => Err(format!(
+                "Unknown byte order '{}', expected big or little",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Endian {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endian::Big => write!(f, "big"),
+            Endian::Little => write!(f, "little"),
+        }
+    }
+}
+
+/// Checksum appended to sent frames
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TxChecksum {
+    /// Checksum to calculate
+    pub kind: ChecksumKind,
+    /// Byte order it's sent in
+    pub endian: Endian,
+}
+
+impl TxChecksum {
+    /// Create a TX checksum, in the kind's usual byte order unless one is given
+    pub fn new(kind: ChecksumKind, endian: Option<Endian>) -> Self {
+        Self {
+            kind,
+            endian: endian.unwrap_or_else(|| kind.default_endian()),
+        }
+    }
+
+    /// Get the checksum bytes of data, in the order they're sent
+    pub fn bytes(&self, data: &[u8]) -> Vec<u8> {
+        let value = self.kind.compute(data);
+        let size = self.kind.size();
+        match self.endian {
+            Endian::Big => value.to_be_bytes()[4 - size..].to_vec(),
+            Endian::Little => value.to_le_bytes()[..size].to_vec(),
+        }
+    }
+
+    /// Append the checksum of data to it
+    pub fn append(&self, data: &mut Vec<u8>) {
+        let checksum = self.bytes(data);
+        data.extend_from_slice(&checksum);
+    }
+}
+
+impl FromStr for TxChecksum {
+    type Err = String;
+
+    /// Parse `<kind> [big|little]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let kind = words.next().unwrap_or_default().parse()?;
+        let endian = words.next().map(str::parse).transpose()?;
+        if let Some(word) = words.next() {
+            return Err(format!("Unexpected '{}' after the byte order", word));
+        }
+        Ok(Self::new(kind, endian))
+    }
+}
+
+impl Display for TxChecksum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind.size() {
+            1 => write!(f, "{}", self.kind),
+            _ => write!(f, "{} {}", self.kind, self.endian),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Standard check input for CRC catalogues
+    const CHECK: &[u8] = b"123456789";
+
+    #[test]
+    fn xmodem() {
+        assert_eq!(crc16_xmodem(CHECK), 0x31c3);
+        assert_eq!(crc16_xmodem(&[]), 0);
+        assert_eq!(sum8(CHECK), 0xdd);
+        assert_eq!(sum8(&[0xff, 0x02]), 0x01);
+    }
+
+    #[test]
+    fn zmodem() {
+        assert_eq!(crc32(CHECK), 0xcbf4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn kermit() {
+        assert_eq!(crc16_kermit(CHECK), 0x2189);
+        assert_eq!(crc16_kermit(&[]), 0);
+    }
+
+    #[test]
+    fn catalogue() {
+        assert_eq!(crc8(CHECK), 0xf4);
+        assert_eq!(crc16_ccitt(CHECK), 0x29b1);
+        assert_eq!(crc16_modbus(CHECK), 0x4b37);
+        assert_eq!(xor8(CHECK), 0x31);
+        assert_eq!(
+            fletcher8(&[0x06, 0x01, 0x03, 0x00, 0xf0, 0x01]),
+            (0xfb, 0x16)
+        );
+    }
+
+    #[test]
+    fn tx_checksum() {
+        let modbus: TxChecksum = "crc16-modbus".parse().unwrap();
+        assert_eq!(modbus.endian, Endian::Little);
+        assert_eq!(modbus.bytes(CHECK), [0x37, 0x4b]);
+        assert_eq!(modbus.to_string(), "crc16-modbus little");
+
+        let big: TxChecksum = "CRC32 be".parse().unwrap();
+        assert_eq!(big.bytes(CHECK), [0xcb, 0xf4, 0x39, 0x26]);
+
+        let mut frame = vec![0xff, 0x02];
+        "sum8".parse::<TxChecksum>().unwrap().append(&mut frame);
+        assert_eq!(frame, [0xff, 0x02, 0x01]);
+
+        assert!("crc7".parse::<TxChecksum>().is_err());
+        assert!("crc8 big little".parse::<TxChecksum>().is_err());
+        assert!("".parse::<TxChecksum>().is_err());
+    }
+}
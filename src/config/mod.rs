@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::app::error::{CerialError, Result};
+use crate::checksum::{ChecksumKind, Endian};
+use crate::decode::DecoderDef;
+use crate::highlight::HighlightDef;
+use crate::macros::MacroDef;
+use crate::trigger::TriggerDef;
+
+/// Settings that can be given globally or per profile
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Macros by key name, `F1`-`F12` or `0`-`9`
+    pub macros: HashMap<String, MacroDef>,
+    /// Trigger rules, a profile's rules are added after the global ones
+    pub triggers: Vec<TriggerDef>,
+    /// Highlight rules, a profile's rules take priority over the global ones
+    pub highlights: Vec<HighlightDef>,
+    /// Built-in highlight presets, `zephyr`, `esp-idf`, or `dmesg`
+    pub highlight_presets: Vec<String>,
+    /// Frame decoders, a profile's decoders replace global ones with the same name
+    pub decoders: Vec<DecoderDef>,
+    /// Checksum appended to hex frames and hex macros
+    pub tx_checksum: Option<ChecksumKind>,
+    /// TX checksum byte order, defaults to the checksum's usual order
+    pub tx_endian: Option<Endian>,
+}
+
+impl Profile {
+    /// Merge another profile on top of this one, its settings win
+    fn merge(mut self, other: &Profile) -> Self {
+        self.macros
+            .extend(other.macros.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.triggers.extend(other.triggers.iter().cloned());
+        self.highlights = other
+            .highlights
+            .iter()
+            .chain(self.highlights.iter())
+            .cloned()
+            .collect();
+        self.highlight_presets
+            .extend(other.highlight_presets.iter().cloned());
+        self.decoders.extend(other.decoders.iter().cloned());
+        self.tx_checksum = other.tx_checksum.or(self.tx_checksum);
+        self.tx_endian = other.tx_endian.or(self.tx_endian);
+        self
+    }
+}
+
+/// Cerial config file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Global settings
+    #[serde(flatten)]
+    pub global: Profile,
+    /// Named profiles selected with `--profile`
+    pub profiles: HashMap<String, Profile>,
+}
+
+impl Config {
+    /// Default config file location
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("cerial").join("config.toml"))
+    }
+
+    /// Load the config file
+    ///
+    /// A missing file is only an error when the path was given explicitly
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            Err(e) => {
+                return Err(CerialError::ConfigError(format!(
+                    "{}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+
+        toml::from_str(&contents)
+            .map_err(|e| CerialError::ConfigError(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Get the settings for a profile, merged over the global settings
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        match name {
+            Some(name) => match self.profiles.get(name) {
+                Some(profile) => Ok(self.global.clone().merge(profile)),
+                None => Err(CerialError::ConfigError(format!(
+                    "Unknown profile '{}'",
+                    name
+                ))),
+            },
+            None => Ok(self.global.clone()),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use super::{Decoder, Frame, MAX_FRAME};
+
+/// COBS decoder
+///
+/// Frames are COBS encoded and end with `0x00`, which never appears inside an encoded frame.
+#[derive(Debug, Clone, Default)]
+pub struct CobsDecoder {
+    /// Encoded bytes received for the current frame
+    raw: Vec<u8>,
+    /// The current frame is longer than `MAX_FRAME` and is being skipped
+    overlong: bool,
+}
+
+/// Decode one COBS frame, without its `0x00` delimiter
+fn decode_frame(encoded: &[u8]) -> Result<Vec<u8>, String> {
+    let mut payload = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let code = encoded[i] as usize;
+        let end = i + code;
+        if end > encoded.len() {
+            return Err(format!(
+                "code {:02x} at {} runs past the end of the frame",
+                code, i
+            ));
+        }
+        payload.extend_from_slice(&encoded[i + 1..end]);
+        // A full block of 254 bytes has no zero after it, nor does the last block
+        if code < 0xff && end < encoded.len() {
+            payload.push(0);
+        }
+        i = end;
+    }
+    Ok(payload)
+}
+
+impl Decoder for CobsDecoder {
+    fn decode(&mut self, data: &[u8]) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        for &byte in data {
+            if byte != 0 {
+                if self.raw.len() < MAX_FRAME {
+                    self.raw.push(byte);
+                } else {
+                    self.overlong = true;
+                }
+                continue;
+            }
+
+            let mut raw = std::mem::take(&mut self.raw);
+            if std::mem::take(&mut self.overlong) {
+                raw.push(0);
+                let error = format!("frame longer than {} bytes", MAX_FRAME);
+                frames.push(Frame::error(raw, error));
+                continue;
+            }
+            if raw.is_empty() {
+                continue;
+            }
+            let frame = match decode_frame(&raw) {
+                Ok(payload) => {
+                    raw.push(0);
+                    Frame::payload(raw, payload)
+                }
+                Err(error) => {
+                    raw.push(0);
+                    Frame::error(raw, error)
+                }
+            };
+            frames.push(frame);
+        }
+        frames
+    }
+
+    fn box_clone(&self) -> Box<dyn Decoder> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_frames() {
+        for (encoded, payload) in [
+            (&[0x01, 0x01][..], &[0x00][..]),
+            (&[0x01, 0x01, 0x01], &[0x00, 0x00]),
+            (&[0x03, 0x11, 0x22, 0x02, 0x33], &[0x11, 0x22, 0x00, 0x33]),
+            (&[0x05, 0x11, 0x22, 0x33, 0x44], &[0x11, 0x22, 0x33, 0x44]),
+        ] {
+            assert_eq!(decode_frame(encoded).unwrap(), payload);
+        }
+
+        // A full block isn't followed by a zero
+        let payload: Vec<u8> = (1..=254).collect();
+        let mut encoded = vec![0xff];
+        encoded.extend(&payload);
+        assert_eq!(decode_frame(&encoded).unwrap(), payload);
+    }
+
+    #[test]
+    fn frames() {
+        let mut decoder = CobsDecoder::default();
+        assert!(decoder.decode(&[0x00, 0x03, 0x11]).is_empty());
+        let frames = decoder.decode(&[0x22, 0x00, 0x05, 0x11, 0x00]);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].data(), Some(&[0x11, 0x22][..]));
+        assert_eq!(frames[0].raw, [0x03, 0x11, 0x22, 0x00]);
+        assert_eq!(
+            frames[1].error.as_deref(),
+            Some("code 05 at 0 runs past the end of the frame")
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use super::{Decoder, Frame, MAX_FRAME};
+
+/// Length prefixed frame decoder
+///
+/// Each frame is an optional sync byte, a 1, 2, or 4 byte length, then the payload. Without a
+/// sync byte a bad length can't be recovered from, so one byte is skipped and the next tried.
+#[derive(Debug, Clone)]
+pub struct LengthDecoder {
+    /// Size of the length field
+    length_bytes: usize,
+    /// The length is big endian
+    big_endian: bool,
+    /// The length counts the sync byte and length field too
+    includes_header: bool,
+    /// Byte every frame starts with
+    sync: Option<u8>,
+    /// Bytes received and not yet decoded
+    buffer: Vec<u8>,
+}
+
+impl LengthDecoder {
+    /// Create a decoder, `length_bytes` must be 1, 2, or 4
+    pub fn new(
+        length_bytes: usize,
+        big_endian: bool,
+        includes_header: bool,
+        sync: Option<u8>,
+    ) -> Self {
+        Self {
+            length_bytes,
+            big_endian,
+            includes_header,
+            sync,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Size of the sync byte and length field
+    fn header_len(&self) -> usize {
+        self.sync.map_or(0, |_| 1) + self.length_bytes
+    }
+
+    /// Get the payload length from a complete header
+    fn payload_len(&self, header: &[u8]) -> Result<usize, String> {
+        let field = &header[header.len() - self.length_bytes..];
+        let mut bytes = field.to_vec();
+        if !self.big_endian {
+            bytes.reverse();
+        }
+        let length = bytes
+            .iter()
+            .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+
+        let payload_len = if self.includes_header {
+            length
+                .checked_sub(header.len())
+                .ok_or_else(|| format!("length {} is shorter than the header", length))?
+        } else {
+            length
+        };
+        if payload_len > MAX_FRAME {
+            return Err(format!(
+                "length {} is longer than {} bytes",
+                length, MAX_FRAME
+            ));
+        }
+        Ok(payload_len)
+    }
+
+    /// Drop bytes before the next sync byte, returning them as an error frame
+    fn resync(&mut self) -> Option<Frame> {
+        let sync = self.sync?;
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let skip = match self.buffer.iter().position(|byte| *byte == sync) {
+            Some(0) => return None,
+            Some(skip) => skip,
+            None => self.buffer.len(),
+        };
+        let raw: Vec<u8> = self.buffer.drain(..skip).collect();
+        let error = format!("skipped {} bytes looking for sync {:02x}", raw.len(), sync);
+        Some(Frame::error(raw, error))
+    }
+
+    /// Take the next frame from the buffer if it's all been received
+    fn next_frame(&mut self) -> Option<Frame> {
+        let header_len = self.header_len();
+        if self.buffer.len() < header_len {
+            return None;
+        }
+
+        match self.payload_len(&self.buffer[..header_len]) {
+            Ok(payload_len) if self.buffer.len() >= header_len + payload_len => {
+                let raw: Vec<u8> = self.buffer.drain(..header_len + payload_len).collect();
+                let payload = raw[header_len..].to_vec();
+                Some(Frame::payload(raw, payload))
+            }
+            Ok(_) => None,
+            // Skip the first byte and look for the next frame from the one after
+            Err(error) => {
+                let raw = self.buffer.drain(..1).collect();
+                Some(Frame::error(raw, error))
+            }
+        }
+    }
+}
+
+impl Decoder for LengthDecoder {
+    fn decode(&mut self, data: &[u8]) -> Vec<Frame> {
+        self.buffer.extend_from_slice(data);
+
+        let mut frames = Vec::new();
+        loop {
+            frames.extend(self.resync());
+            match self.next_frame() {
+                Some(frame) => frames.push(frame),
+                None => break,
+            }
+        }
+        frames
+    }
+
+    fn box_clone(&self) -> Box<dyn Decoder> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn big_endian() {
+        let mut decoder = LengthDecoder::new(2, true, false, None);
+        assert!(decoder.decode(&[0x00, 0x03, 0xaa]).is_empty());
+        let frames = decoder.decode(&[0xbb, 0xcc, 0x00, 0x00]);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].data(), Some(&[0xaa, 0xbb, 0xcc][..]));
+        assert_eq!(frames[0].raw, [0x00, 0x03, 0xaa, 0xbb, 0xcc]);
+        assert_eq!(frames[1].data(), Some(&[][..]));
+    }
+
+    #[test]
+    fn little_endian_including_header() {
+        let mut decoder = LengthDecoder::new(4, false, true, Some(0x7e));
+        let frames = decoder.decode(&[0x7e, 0x06, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data(), Some(&[0x01][..]));
+
+        // Shorter than the header
+        let frames = decoder.decode(&[0x7e, 0x02, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            frames[0].error.as_deref(),
+            Some("length 2 is shorter than the header")
+        );
+    }
+
+    #[test]
+    fn resync() {
+        let mut decoder = LengthDecoder::new(1, true, false, Some(0x7e));
+        let frames = decoder.decode(&[0x01, 0x02, 0x7e, 0x01, 0xaa]);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            frames[0].error.as_deref(),
+            Some("skipped 2 bytes looking for sync 7e")
+        );
+        assert_eq!(frames[1].data(), Some(&[0xaa][..]));
+    }
+}
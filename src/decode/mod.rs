@@ -0,0 +1,504 @@
+pub mod cobs;
+pub mod length;
+pub mod slip;
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use cobs::CobsDecoder;
+use length::LengthDecoder;
+use slip::SlipDecoder;
+
+use crate::modbus::monitor::ModbusDecoder;
+
+/// Frames longer than this are dropped, they're most likely noise or a lost delimiter
+pub const MAX_FRAME: usize = 4096;
+
+/// Decoded field value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Unsigned integer
+    Unsigned(u64),
+    /// Signed integer
+    Signed(i64),
+    /// Single precision floating point number
+    Float32(f32),
+    /// Double precision floating point number
+    Float(f64),
+    /// Raw bytes, shown as hex
+    Bytes(Vec<u8>),
+    /// Text, like a function name
+    Text(String),
+    /// Several values, like a run of registers
+    List(Vec<Value>),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Unsigned(value) => write!(f, "{}", value),
+            Value::Signed(value) => write!(f, "{}", value),
+            Value::Float32(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Bytes(bytes) => write!(f, "{}", hex(bytes)),
+            Value::Text(text) => write!(f, "{}", text),
+            Value::List(values) => {
+                let values: Vec<String> = values.iter().map(Value::to_string).collect();
+                write!(f, "{}", values.join(","))
+            }
+        }
+    }
+}
+
+/// Named value in a frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    /// Field name
+    pub name: String,
+    /// Field value
+    pub value: Value,
+}
+
+impl Field {
+    /// Create a field
+    pub fn new(name: &str, value: Value) -> Self {
+        Self {
+            name: name.to_string(),
+            value,
+        }
+    }
+}
+
+/// Frame decoded from received data
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frame {
+    /// Decoded fields
+    pub fields: Vec<Field>,
+    /// Received bytes the frame was decoded from, including delimiters and escapes
+    pub raw: Vec<u8>,
+    /// Why the frame couldn't be decoded, the fields may be incomplete
+    pub error: Option<String>,
+}
+
+impl Frame {
+    /// Create a frame with `len` and `data` fields for its payload
+    pub fn payload(raw: Vec<u8>, payload: Vec<u8>) -> Self {
+        Self {
+            fields: vec![
+                Field::new("len", Value::Unsigned(payload.len() as u64)),
+                Field::new("data", Value::Bytes(payload)),
+            ],
+            raw,
+            error: None,
+        }
+    }
+
+    /// Create a frame for bytes that couldn't be decoded
+    pub fn error(raw: Vec<u8>, error: String) -> Self {
+        Self {
+            fields: Vec::new(),
+            raw,
+            error: Some(error),
+        }
+    }
+
+    /// Get the payload of a frame created with [`Frame::payload`]
+    pub fn data(&self) -> Option<&[u8]> {
+        self.fields.iter().find_map(|field| match &field.value {
+            Value::Bytes(bytes) if field.name == "data" => Some(bytes.as_slice()),
+            _ => None,
+        })
+    }
+}
+
+/// Turns a stream of received bytes into frames
+///
+/// Data is passed in as it's received, so frames can be split across calls
+pub trait Decoder: Debug + Send {
+    /// Decode received data, returning the frames it finishes
+    fn decode(&mut self, data: &[u8]) -> Vec<Frame>;
+
+    /// Copy the decoder, including any partly received frame
+    fn box_clone(&self) -> Box<dyn Decoder>;
+
+    /// Called periodically, returning frames ended by a gap in the data
+    fn flush(&mut self) -> Vec<Frame> {
+        Vec::new()
+    }
+}
+
+impl Clone for Box<dyn Decoder> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// Creates a new decoder
+pub type DecoderFactory = Arc<dyn Fn() -> Box<dyn Decoder> + Send + Sync>;
+
+/// Decoders by name
+#[derive(Clone)]
+pub struct DecoderRegistry {
+    /// Decoder factories, sorted by name
+    factories: BTreeMap<String, DecoderFactory>,
+}
+
+impl Debug for DecoderRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.factories.keys()).finish()
+    }
+}
+
+impl Default for DecoderRegistry {
+    /// Registry with the built-in `slip`, `cobs`, `length`, and `modbus` decoders
+    fn default() -> Self {
+        let mut registry = Self {
+            factories: BTreeMap::new(),
+        };
+        registry.register("slip", || Box::new(SlipDecoder::default()));
+        registry.register("cobs", || Box::new(CobsDecoder::default()));
+        registry.register("length", || {
+            Box::new(LengthDecoder::new(2, true, false, None))
+        });
+        registry.register("modbus", || Box::new(ModbusDecoder::default()));
+        registry
+    }
+}
+
+impl DecoderRegistry {
+    /// Add a decoder, replacing any with the same name
+    pub fn register<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn() -> Box<dyn Decoder> + Send + Sync + 'static,
+    {
+        self.factories
+            .insert(name.to_ascii_lowercase(), Arc::new(factory));
+    }
+
+    /// Add a decoder defined in the config file
+    pub fn register_def(&mut self, def: &DecoderDef) -> Result<(), String> {
+        def.validate()?;
+        let def = def.clone();
+        let name = def.name.clone();
+        self.register(&name, move || Box::new(LayoutDecoder::new(&def)));
+        Ok(())
+    }
+
+    /// Get the names of all decoders
+    pub fn names(&self) -> Vec<&str> {
+        self.factories.keys().map(String::as_str).collect()
+    }
+
+    /// Create a decoder by name
+    pub fn create(&self, name: &str) -> Result<Box<dyn Decoder>, String> {
+        self.factories
+            .get(&name.to_ascii_lowercase())
+            .map(|factory| factory())
+            .ok_or_else(|| {
+                format!(
+                    "Unknown decoder '{}', expected one of {}",
+                    name,
+                    self.names().join(", ")
+                )
+            })
+    }
+}
+
+/// Type of a field in a decoder definition
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    /// Unsigned byte
+    U8,
+    /// Signed byte
+    I8,
+    /// Unsigned 16-bit integer
+    U16,
+    /// Signed 16-bit integer
+    I16,
+    /// Unsigned 32-bit integer
+    U32,
+    /// Signed 32-bit integer
+    I32,
+    /// Unsigned 64-bit integer
+    U64,
+    /// Signed 64-bit integer
+    I64,
+    /// 32-bit float
+    F32,
+    /// 64-bit float
+    F64,
+    /// The rest of the payload
+    Bytes,
+}
+
+impl FieldType {
+    /// Size in bytes, `None` for the rest of the payload
+    fn size(self) -> Option<usize> {
+        match self {
+            FieldType::U8 | FieldType::I8 => Some(1),
+            FieldType::U16 | FieldType::I16 => Some(2),
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => Some(4),
+            FieldType::U64 | FieldType::I64 | FieldType::F64 => Some(8),
+            FieldType::Bytes => None,
+        }
+    }
+
+    /// Read a value from the start of `bytes`, which must be at least the field's size
+    fn read(self, bytes: &[u8], big_endian: bool) -> Value {
+        let mut buf = [0u8; 8];
+        let size = bytes.len().min(8);
+        buf[..size].copy_from_slice(&bytes[..size]);
+        if big_endian {
+            buf[..size].reverse();
+        }
+        let raw = u64::from_le_bytes(buf);
+
+        match self {
+            FieldType::U8 | FieldType::U16 | FieldType::U32 | FieldType::U64 => {
+                Value::Unsigned(raw)
+            }
+            FieldType::I8 => Value::Signed(raw as u8 as i8 as i64),
+            FieldType::I16 => Value::Signed(raw as u16 as i16 as i64),
+            FieldType::I32 => Value::Signed(raw as u32 as i32 as i64),
+            FieldType::I64 => Value::Signed(raw as i64),
+            FieldType::F32 => Value::Float32(f32::from_bits(raw as u32)),
+            FieldType::F64 => Value::Float(f64::from_bits(raw)),
+            FieldType::Bytes => Value::Bytes(bytes.to_vec()),
+        }
+    }
+}
+
+/// Field in a decoder definition
+#[derive(Debug, Clone, Deserialize)]
+pub struct FieldDef {
+    /// Field name
+    pub name: String,
+    /// Field type
+    #[serde(rename = "type")]
+    pub kind: FieldType,
+}
+
+/// Decoder as written in the config file
+#[derive(Debug, Clone, Deserialize)]
+pub struct DecoderDef {
+    /// Name to select the decoder by
+    pub name: String,
+    /// How frames are delimited, `slip`, `cobs`, or `length`
+    pub framing: String,
+    /// Length framing: size of the length field, 1, 2, or 4 bytes
+    #[serde(default = "default_length_bytes")]
+    pub length_bytes: usize,
+    /// Length framing: the length counts the sync byte and length field too
+    #[serde(default)]
+    pub length_includes_header: bool,
+    /// Length framing: byte every frame starts with, used to find the start of a frame
+    pub sync: Option<u8>,
+    /// The length field and payload fields are big endian
+    #[serde(default)]
+    pub big_endian: bool,
+    /// Payload fields in order, left out the payload is shown as hex
+    #[serde(default)]
+    pub fields: Vec<FieldDef>,
+}
+
+/// Length fields are one byte unless set
+fn default_length_bytes() -> usize {
+    1
+}
+
+impl DecoderDef {
+    /// Check the definition can be used
+    fn validate(&self) -> Result<(), String> {
+        match self.framing.to_ascii_lowercase().as_str() {
+            "slip" | "cobs" => {}
+            "length" if matches!(self.length_bytes, 1 | 2 | 4) => {}
+            "length" => {
+                return Err(format!(
+                    "Decoder '{}': length_bytes must be 1, 2, or 4",
+                    self.name
+                ))
+            }
+            framing => {
+                return Err(format!(
+                    "Decoder '{}': unknown framing '{}', expected slip, cobs, or length",
+                    self.name, framing
+                ))
+            }
+        }
+        let rest = self.fields.iter().position(|f| f.kind == FieldType::Bytes);
+        if rest.is_some_and(|i| i + 1 != self.fields.len()) {
+            return Err(format!(
+                "Decoder '{}': only the last field can be bytes",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Decoder defined in the config file, splits each frame's payload into fields
+#[derive(Debug, Clone)]
+struct LayoutDecoder {
+    /// Finds the frames
+    framing: Box<dyn Decoder>,
+    /// Payload fields
+    fields: Vec<FieldDef>,
+    /// Fields are big endian
+    big_endian: bool,
+}
+
+impl LayoutDecoder {
+    /// Create a decoder from a validated definition
+    fn new(def: &DecoderDef) -> Self {
+        let framing: Box<dyn Decoder> = match def.framing.to_ascii_lowercase().as_str() {
+            "slip" => Box::new(SlipDecoder::default()),
+            "cobs" => Box::new(CobsDecoder::default()),
+            _ => Box::new(LengthDecoder::new(
+                def.length_bytes,
+                def.big_endian,
+                def.length_includes_header,
+                def.sync,
+            )),
+        };
+        Self {
+            framing,
+            fields: def.fields.clone(),
+            big_endian: def.big_endian,
+        }
+    }
+
+    /// Replace a frame's payload with its fields
+    fn split(&self, frame: Frame) -> Frame {
+        let payload = match frame.data() {
+            Some(payload) if !self.fields.is_empty() => payload.to_vec(),
+            _ => return frame,
+        };
+
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        for def in &self.fields {
+            let size = def.kind.size().unwrap_or(payload.len() - offset);
+            if offset + size > payload.len() {
+                return Frame {
+                    fields,
+                    raw: frame.raw,
+                    error: Some(format!(
+                        "payload too short for {}, {} bytes",
+                        def.name,
+                        payload.len()
+                    )),
+                };
+            }
+            let value = def
+                .kind
+                .read(&payload[offset..offset + size], self.big_endian);
+            fields.push(Field::new(&def.name, value));
+            offset += size;
+        }
+        if offset < payload.len() {
+            fields.push(Field::new("rest", Value::Bytes(payload[offset..].to_vec())));
+        }
+
+        Frame {
+            fields,
+            raw: frame.raw,
+            error: None,
+        }
+    }
+}
+
+impl Decoder for LayoutDecoder {
+    fn decode(&mut self, data: &[u8]) -> Vec<Frame> {
+        let frames = self.framing.decode(data);
+        frames.into_iter().map(|frame| self.split(frame)).collect()
+    }
+
+    fn flush(&mut self) -> Vec<Frame> {
+        let frames = self.framing.flush();
+        frames.into_iter().map(|frame| self.split(frame)).collect()
+    }
+
+    fn box_clone(&self) -> Box<dyn Decoder> {
+        Box::new(self.clone())
+    }
+}
+
+/// Shows received data as decoded frames, one per line
+#[derive(Debug, Clone)]
+pub struct FrameView {
+    /// Decoder name, shown with each frame
+    name: String,
+    /// Decoder
+    decoder: Box<dyn Decoder>,
+    /// Show each frame's raw bytes after its fields
+    raw_hex: bool,
+    /// Frames decoded
+    frames: u64,
+    /// Frames with errors
+    errors: u64,
+}
+
+impl Display for FrameView {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Decoder: {}, {} frames, {} errors",
+            self.name, self.frames, self.errors
+        )
+    }
+}
+
+impl FrameView {
+    /// Create a frame view with a decoder from the registry
+    pub fn new(registry: &DecoderRegistry, name: &str, raw_hex: bool) -> Result<Self, String> {
+        Ok(Self {
+            name: name.to_ascii_lowercase(),
+            decoder: registry.create(name)?,
+            raw_hex,
+            frames: 0,
+            errors: 0,
+        })
+    }
+
+    /// Decode received data, returning a line for each frame it finishes, without line endings
+    pub fn lines(&mut self, data: &[u8]) -> Vec<String> {
+        let frames = self.decoder.decode(data);
+        frames.iter().map(|frame| self.line(frame)).collect()
+    }
+
+    /// Get a line for each frame ended by a gap in the data
+    pub fn flush(&mut self) -> Vec<String> {
+        let frames = self.decoder.flush();
+        frames.iter().map(|frame| self.line(frame)).collect()
+    }
+
+    /// Format a frame as a line
+    fn line(&mut self, frame: &Frame) -> String {
+        self.frames += 1;
+        let mut line = format!("[{}]", self.name);
+        for field in &frame.fields {
+            line.push_str(&format!(" {}={}", field.name, field.value));
+        }
+        if let Some(error) = &frame.error {
+            self.errors += 1;
+            line.push_str(&format!(" error: {}", error));
+        }
+        if self.raw_hex {
+            line.push_str(&format!(" | raw {}", hex(&frame.raw)));
+        }
+        line
+    }
+}
+
+/// Format bytes as space separated hex
+pub fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
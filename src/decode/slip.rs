@@ -0,0 +1,116 @@
+use super::{Decoder, Frame, MAX_FRAME};
+
+/// Ends a frame
+const END: u8 = 0xc0;
+/// Starts an escape
+const ESC: u8 = 0xdb;
+/// Escaped `END`
+const ESC_END: u8 = 0xdc;
+/// Escaped `ESC`
+const ESC_ESC: u8 = 0xdd;
+
+/// SLIP (RFC 1055) decoder
+///
+/// Frames end with `0xc0`, many senders also start frames with one so empty frames are skipped.
+/// A bad escape or an overlong frame is reported when the frame ends.
+#[derive(Debug, Clone, Default)]
+pub struct SlipDecoder {
+    /// Bytes received for the current frame
+    raw: Vec<u8>,
+    /// Payload decoded so far
+    payload: Vec<u8>,
+    /// The last byte was `ESC`
+    escaped: bool,
+    /// Why the current frame is bad
+    error: Option<String>,
+}
+
+impl SlipDecoder {
+    /// Finish the current frame
+    fn end_frame(&mut self) -> Option<Frame> {
+        let raw = std::mem::take(&mut self.raw);
+        let payload = std::mem::take(&mut self.payload);
+        self.escaped = false;
+        match self.error.take() {
+            Some(error) => Some(Frame::error(raw, error)),
+            None if payload.is_empty() => None,
+            None => Some(Frame::payload(raw, payload)),
+        }
+    }
+}
+
+impl Decoder for SlipDecoder {
+    fn decode(&mut self, data: &[u8]) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        for &byte in data {
+            if self.raw.len() < MAX_FRAME {
+                self.raw.push(byte);
+            }
+            if byte == END {
+                frames.extend(self.end_frame());
+                continue;
+            }
+            if self.error.is_some() {
+                continue;
+            }
+
+            let decoded = match (self.escaped, byte) {
+                (false, ESC) => {
+                    self.escaped = true;
+                    continue;
+                }
+                (false, byte) => byte,
+                (true, ESC_END) => END,
+                (true, ESC_ESC) => ESC,
+                (true, byte) => {
+                    self.error = Some(format!("bad escape {:02x}", byte));
+                    continue;
+                }
+            };
+            self.escaped = false;
+
+            if self.payload.len() < MAX_FRAME {
+                self.payload.push(decoded);
+            } else {
+                self.error = Some(format!("frame longer than {} bytes", MAX_FRAME));
+            }
+        }
+        frames
+    }
+
+    fn box_clone(&self) -> Box<dyn Decoder> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes() {
+        let mut decoder = SlipDecoder::default();
+        let frames = decoder.decode(&[END, 0x01, ESC, ESC_END, 0x02, ESC, ESC_ESC, END]);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data(), Some(&[0x01, END, 0x02, ESC][..]));
+        assert_eq!(frames[0].raw.len(), 7);
+    }
+
+    #[test]
+    fn split_frames() {
+        let mut decoder = SlipDecoder::default();
+        assert!(decoder.decode(&[0x01, ESC]).is_empty());
+        let frames = decoder.decode(&[ESC_END, END, END, 0x02, END]);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].data(), Some(&[0x01, END][..]));
+        assert_eq!(frames[1].data(), Some(&[0x02][..]));
+    }
+
+    #[test]
+    fn bad_escape() {
+        let mut decoder = SlipDecoder::default();
+        let frames = decoder.decode(&[0x01, ESC, 0x02, END, 0x03, END]);
+        assert_eq!(frames[0].error.as_deref(), Some("bad escape 02"));
+        assert_eq!(frames[1].data(), Some(&[0x03][..]));
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt::{Display, Formatter};
+
+use regex::bytes::Regex;
+
+/// Which received lines are displayed
+#[derive(Debug, Clone, Default)]
+pub struct LineFilter {
+    /// Only show lines matching this pattern
+    pub include: Option<Regex>,
+    /// Hide lines matching this pattern
+    pub exclude: Option<Regex>,
+}
+
+impl LineFilter {
+    /// Check if any filters are set
+    pub fn is_active(&self) -> bool {
+        self.include.is_some() || self.exclude.is_some()
+    }
+
+    /// Check if a line should be displayed
+    pub fn allows(&self, line: &[u8]) -> bool {
+        let included = self.include.as_ref().is_none_or(|r| r.is_match(line));
+        let excluded = self.exclude.as_ref().is_some_and(|r| r.is_match(line));
+        included && !excluded
+    }
+
+    /// Parse a filter pattern entered at a prompt, an empty pattern clears the filter
+    pub fn parse_pattern(pattern: &str) -> Result<Option<Regex>, String> {
+        if pattern.is_empty() {
+            Ok(None)
+        } else {
+            Regex::new(pattern).map(Some).map_err(|e| e.to_string())
+        }
+    }
+}
+
+impl Display for LineFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Filter")?;
+        if let Some(include) = &self.include {
+            write!(f, " +{}", include)?;
+        }
+        if let Some(exclude) = &self.exclude {
+            write!(f, " -{}", exclude)?;
+        }
+        Ok(())
+    }
+}
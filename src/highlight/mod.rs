@@ -0,0 +1,230 @@
+use std::convert::TryFrom;
+
+use crossterm::style::{Attribute, Color, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use regex::bytes::Regex;
+use serde::Deserialize;
+
+use crate::at::URC_PREFIXES;
+
+/// Highlight rule as written in the config file
+#[derive(Debug, Clone, Deserialize)]
+pub struct HighlightDef {
+    /// Pattern to highlight
+    pub pattern: String,
+    /// Foreground color name, e.g. `red` or `dark_yellow`
+    pub fg: Option<String>,
+    /// Background color name
+    pub bg: Option<String>,
+    /// Bold text
+    #[serde(default)]
+    pub bold: bool,
+}
+
+/// Text style applied to a match
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Style {
+    /// Foreground color
+    fg: Option<Color>,
+    /// Background color
+    bg: Option<Color>,
+    /// Bold text
+    bold: bool,
+}
+
+impl Style {
+    /// Get the escape sequence that switches to this style from the default style
+    fn escape(&self) -> String {
+        let mut s = String::new();
+        if let Some(fg) = self.fg {
+            s.push_str(&SetForegroundColor(fg).to_string());
+        }
+        if let Some(bg) = self.bg {
+            s.push_str(&SetBackgroundColor(bg).to_string());
+        }
+        if self.bold {
+            s.push_str(&SetAttribute(Attribute::Bold).to_string());
+        }
+        s
+    }
+}
+
+/// Highlight rule
+#[derive(Debug, Clone)]
+pub struct HighlightRule {
+    /// Pattern to highlight, matched against one line at a time
+    pattern: Regex,
+    /// Style of matching text
+    style: Style,
+}
+
+impl HighlightRule {
+    /// Create a highlight rule
+    fn new(pattern: &str, fg: Option<Color>, bg: Option<Color>, bold: bool) -> Self {
+        Self {
+            pattern: Regex::new(pattern).unwrap(),
+            style: Style { fg, bg, bold },
+        }
+    }
+
+    /// Parse a highlight rule definition
+    pub fn parse(def: &HighlightDef) -> Result<Self, String> {
+        let color = |name: &Option<String>| {
+            name.as_deref()
+                .map(|name| {
+                    Color::try_from(name).map_err(|_| {
+                        format!("Highlight '{}': unknown color '{}'", def.pattern, name)
+                    })
+                })
+                .transpose()
+        };
+
+        Ok(Self {
+            pattern: Regex::new(&def.pattern)
+                .map_err(|e| format!("Highlight '{}': {}", def.pattern, e))?,
+            style: Style {
+                fg: color(&def.fg)?,
+                bg: color(&def.bg)?,
+                bold: def.bold,
+            },
+        })
+    }
+}
+
+/// Get the rules of a built-in preset, `zephyr`, `esp-idf`, `dmesg`, `at`, or `nmea`
+pub fn preset(name: &str) -> Result<Vec<HighlightRule>, String> {
+    let rule = HighlightRule::new;
+    let rules = match name.to_ascii_lowercase().as_str() {
+        "zephyr" => vec![
+            rule(r"<err>.*", Some(Color::Red), None, true),
+            rule(r"<wrn>.*", Some(Color::Yellow), None, false),
+            rule(r"<inf>", Some(Color::Green), None, false),
+            rule(r"<dbg>.*", Some(Color::DarkGrey), None, false),
+            rule(
+                r"^\*\*\* Booting Zephyr OS.*",
+                Some(Color::Cyan),
+                None,
+                true,
+            ),
+            rule(
+                r"^\[\d{2}:\d{2}:\d{2}\.\d{3},\d{3}\]",
+                Some(Color::DarkGrey),
+                None,
+                false,
+            ),
+        ],
+        "esp-idf" => vec![
+            rule(r"^E \(\d+\) .*", Some(Color::Red), None, true),
+            rule(r"^W \(\d+\) .*", Some(Color::Yellow), None, false),
+            rule(r"^I \(\d+\) .*", Some(Color::Green), None, false),
+            rule(r"^[DV] \(\d+\) .*", Some(Color::DarkGrey), None, false),
+            rule(r"Guru Meditation Error.*", Some(Color::Red), None, true),
+            rule(r"abort\(\) was called.*", Some(Color::Red), None, true),
+            rule(r"^Backtrace:.*", Some(Color::Magenta), None, false),
+        ],
+        "dmesg" => vec![
+            rule(
+                r"Kernel panic.*",
+                Some(Color::White),
+                Some(Color::DarkRed),
+                true,
+            ),
+            rule(r"^\[\s*\d+\.\d+\]", Some(Color::DarkGreen), None, false),
+            rule(
+                r"(?i)\b(error|fail(ed|ure)?|oops|bug)\b",
+                Some(Color::Red),
+                None,
+                true,
+            ),
+            rule(r"(?i)\bwarn(ing)?\b", Some(Color::Yellow), None, true),
+            rule(r"Call Trace:", Some(Color::Red), None, false),
+        ],
+        "at" => {
+            let urcs = URC_PREFIXES
+                .iter()
+                .map(|prefix| regex::escape(prefix))
+                .collect::<Vec<_>>()
+                .join("|");
+            vec![
+                rule(r"^(OK|CONNECT.*)$", Some(Color::Green), None, true),
+                rule(
+                    r"^(ERROR|\+CM[ES] ERROR:.*|NO CARRIER|BUSY|NO ANSWER|NO DIALTONE)$",
+                    Some(Color::Red),
+                    None,
+                    true,
+                ),
+                rule(&format!("^({}).*", urcs), Some(Color::Yellow), None, true),
+                rule(r"^\[AT\] .*", Some(Color::DarkGrey), None, false),
+            ]
+        }
+        "nmea" => vec![
+            rule(r"^\[NMEA\] .*", Some(Color::Red), None, true),
+            rule(r"^[$!]P[A-Z0-9]+", Some(Color::Magenta), None, false),
+            rule(r"^[$!][A-Z0-9]{5}", Some(Color::Cyan), None, false),
+            rule(r"\*[0-9A-Fa-f]{2}$", Some(Color::DarkGrey), None, false),
+        ],
+        _ => {
+            return Err(format!(
+                "Unknown highlight preset '{}', expected zephyr, esp-idf, dmesg, at, or nmea",
+                name
+            ))
+        }
+    };
+
+    Ok(rules)
+}
+
+/// Parse highlight rules from the config file followed by the rules of the named presets
+pub fn parse_highlights(
+    defs: &[HighlightDef],
+    presets: &[String],
+) -> Result<Vec<HighlightRule>, String> {
+    let mut rules = defs
+        .iter()
+        .map(HighlightRule::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    for name in presets {
+        rules.extend(preset(name)?);
+    }
+    Ok(rules)
+}
+
+/// Get the style of each byte of a line, earlier rules win where matches overlap
+///
+/// The line ending is left out when matching so `$` works
+pub fn line_styles(rules: &[HighlightRule], line: &[u8]) -> Vec<Option<Style>> {
+    let end = line
+        .iter()
+        .rposition(|b| *b != b'\n' && *b != b'\r')
+        .map_or(0, |i| i + 1);
+    let text = &line[..end];
+
+    let mut styles: Vec<Option<Style>> = vec![None; line.len()];
+    for rule in rules {
+        for m in rule.pattern.find_iter(text) {
+            for style in &mut styles[m.start()..m.end()] {
+                style.get_or_insert(rule.style);
+            }
+        }
+    }
+    styles
+}
+
+/// Write styled bytes, switching styles with escape sequences
+pub fn write_styled(bytes: &[u8], styles: &[Option<Style>], out: &mut Vec<u8>) {
+    let mut current = None;
+    for (byte, style) in bytes.iter().zip(styles) {
+        if *style != current {
+            if current.is_some() {
+                out.extend(SetAttribute(Attribute::Reset).to_string().bytes());
+            }
+            if let Some(style) = style {
+                out.extend(style.escape().bytes());
+            }
+            current = *style;
+        }
+        out.push(*byte);
+    }
+    if current.is_some() {
+        out.extend(SetAttribute(Attribute::Reset).to_string().bytes());
+    }
+}
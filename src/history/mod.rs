@@ -0,0 +1,124 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Number of lines kept in the history file
+const MAX_ENTRIES: usize = 1000;
+/// Number of lines the history file can grow to before it's rewritten with only the kept lines
+const COMPACT_ENTRIES: usize = 2 * MAX_ENTRIES;
+/// Mode whose history file is named after the profile alone
+pub const LINE_MODE: &str = "line";
+
+/// Lines sent from a line editing mode, saved per config profile
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    /// Oldest entry first
+    entries: Vec<String>,
+    /// File new entries are appended to, history isn't saved without one
+    path: Option<PathBuf>,
+}
+
+impl History {
+    /// History file location for a profile and mode
+    ///
+    /// Line mode uses the profile name, other modes add their name to it. Profile names that
+    /// aren't a plain file name have no history file.
+    pub fn default_path(profile: Option<&str>, mode: &str) -> Option<PathBuf> {
+        let name = profile.unwrap_or("default");
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return None;
+        }
+        let name = match mode {
+            LINE_MODE => name.to_string(),
+            mode => format!("{}.{}", name, mode),
+        };
+        dirs::data_dir().map(|dir| dir.join("cerial").join("history").join(name))
+    }
+
+    /// Load the history for a profile and mode
+    ///
+    /// History is a convenience, a missing or unreadable file gives an empty history
+    pub fn load(profile: Option<&str>, mode: &str) -> Self {
+        let path = Self::default_path(profile, mode);
+        let mut entries: Vec<String> = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+
+        // Only keep the newest entries, and compact the file once it grows well past the limit
+        let lines = entries.len();
+        if lines > MAX_ENTRIES {
+            entries.drain(..lines - MAX_ENTRIES);
+        }
+        if lines > COMPACT_ENTRIES {
+            if let Some(path) = &path {
+                fs::write(path, entries.join("\n") + "\n").ok();
+            }
+        }
+
+        Self { entries, path }
+    }
+
+    /// Add a sent line, repeats of the last entry and empty lines aren't kept
+    pub fn push(&mut self, line: &str) {
+        if line.is_empty() || self.entries.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.entries.push(line.to_string());
+
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).ok();
+            }
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                writeln!(file, "{}", line).ok();
+            }
+        }
+    }
+
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if there are no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get an entry, 0 is the oldest
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    /// Find the newest entry before `before` containing `query`
+    pub fn search(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_names() {
+        for name in ["", ".", "..", "../x", "a/b", "a\\b"] {
+            assert_eq!(
+                History::default_path(Some(name), LINE_MODE),
+                None,
+                "{}",
+                name
+            );
+        }
+        if let Some(path) = History::default_path(Some("board"), LINE_MODE) {
+            assert!(path.ends_with("cerial/history/board"));
+        }
+        if let Some(path) = History::default_path(Some("board"), "hex") {
+            assert!(path.ends_with("cerial/history/board.hex"));
+        }
+    }
+}
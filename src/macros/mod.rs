@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use crossterm::event::KeyCode;
+use serde::Deserialize;
+
+use crate::checksum::{ChecksumKind, Endian, TxChecksum};
+
+/// Macro definition as written in the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum MacroDef {
+    /// Text payload with escapes, e.g. `"reboot\r"`
+    Text(String),
+    /// Hex payload, e.g. `{ hex = "aa 55 {delay 10ms} 01" }`
+    Hex {
+        /// Hex bytes, whitespace is ignored
+        hex: String,
+        /// Checksum appended to the payload, in place of the default TX checksum
+        checksum: Option<ChecksumKind>,
+        /// Checksum byte order, defaults to the checksum's usual order
+        endian: Option<Endian>,
+    },
+}
+
+/// Step of a macro
+#[derive(Debug, Clone)]
+pub enum MacroStep {
+    /// Send bytes
+    Send(Vec<u8>),
+    /// Wait before the next step
+    Delay(Duration),
+}
+
+/// Key a macro is bound to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MacroKey {
+    /// Function key F1-F12
+    F(u8),
+    /// Digit typed after `Ctrl-5`
+    Digit(u8),
+}
+
+impl MacroKey {
+    /// Parse a key name, `F1` to `F12` or `0` to `9`
+    pub fn parse(name: &str) -> Result<Self, String> {
+        let upper = name.to_ascii_uppercase();
+        if let Some(n) = upper.strip_prefix('F') {
+            if let Ok(n) = n.parse::<u8>() {
+                if (1..=12).contains(&n) {
+                    return Ok(MacroKey::F(n));
+                }
+            }
+        } else if let Ok(n) = upper.parse::<u8>() {
+            if n <= 9 {
+                return Ok(MacroKey::Digit(n));
+            }
+        }
+
+        Err(format!(
+            "Unknown macro key '{}', expected F1-F12 or 0-9",
+            name
+        ))
+    }
+
+    /// Get the macro key for a function key press
+    pub fn from_key_code(code: KeyCode) -> Option<Self> {
+        match code {
+            KeyCode::F(n) => Some(MacroKey::F(n)),
+            _ => None,
+        }
+    }
+}
+
+/// Byte sequence bound to a key
+#[derive(Debug, Clone, Default)]
+pub struct Macro {
+    /// Steps to run in order
+    pub steps: Vec<MacroStep>,
+    /// The default TX checksum is appended when the macro is run, for hex macros without their own
+    pub default_checksum: bool,
+}
+
+/// Macros by key
+pub type Macros = HashMap<MacroKey, Macro>;
+
+impl Macro {
+    /// Parse a macro definition
+    pub fn parse(def: &MacroDef) -> Result<Self, String> {
+        match def {
+            MacroDef::Text(text) => Self::parse_text(text),
+            MacroDef::Hex {
+                hex,
+                checksum: Some(kind),
+                endian,
+            } => {
+                let mut parsed = Self::parse_hex(hex)?;
+                parsed.steps = with_checksum(&parsed.steps, &TxChecksum::new(*kind, *endian));
+                Ok(parsed)
+            }
+            MacroDef::Hex { hex, .. } => Ok(Macro {
+                default_checksum: true,
+                ..Self::parse_hex(hex)?
+            }),
+        }
+    }
+
+    /// Parse a text payload
+    ///
+    /// Supports `\r`, `\n`, `\t`, `\0`, `\\`, `\{`, `\xNN`, and `{delay <n>ms}` or `{delay <n>s}`
+    pub fn parse_text(text: &str) -> Result<Self, String> {
+        let mut builder = MacroBuilder::default();
+        let mut chars = text.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    let byte = match chars.next() {
+                        Some('r') => b'\r',
+                        Some('n') => b'\n',
+                        Some('t') => b'\t',
+                        Some('0') => b'\0',
+                        Some('e') => 0x1b,
+                        Some('\\') => b'\\',
+                        Some('{') => b'{',
+                        Some('x') => {
+                            let digits: String = chars.by_ref().take(2).collect();
+                            u8::from_str_radix(&digits, 16)
+                                .map_err(|_| format!("Invalid escape '\\x{}'", digits))?
+                        }
+                        Some(c) => return Err(format!("Invalid escape '\\{}'", c)),
+                        None => return Err("Trailing '\\'".to_string()),
+                    };
+                    builder.push(&[byte]);
+                }
+                '{' => {
+                    let directive: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    builder.delay(parse_directive(&directive)?);
+                }
+                c => {
+                    let mut buf = [0u8; 4];
+                    builder.push(c.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+        }
+
+        Ok(builder.build())
+    }
+
+    /// Parse a hex payload, bytes may be separated by whitespace and `{delay <n>ms}` is supported
+    pub fn parse_hex(hex: &str) -> Result<Self, String> {
+        let mut builder = MacroBuilder::default();
+        let mut digits = String::new();
+        let mut chars = hex.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let directive: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    builder.push(&parse_hex_bytes(&digits)?);
+                    digits.clear();
+                    builder.delay(parse_directive(&directive)?);
+                }
+                c if c.is_whitespace() => {}
+                c => digits.push(c),
+            }
+        }
+        builder.push(&parse_hex_bytes(&digits)?);
+
+        Ok(builder.build())
+    }
+
+    /// Send the macro, in a new thread if it has delays
+    ///
+    /// `tx_checksum` is appended to hex macros without a checksum of their own
+    pub fn run(&self, serial_send_tx: &Sender<Vec<u8>>, tx_checksum: Option<&TxChecksum>) {
+        let steps = match tx_checksum {
+            Some(checksum) if self.default_checksum => with_checksum(&self.steps, checksum),
+            _ => self.steps.clone(),
+        };
+        let has_delay = steps.iter().any(|step| matches!(step, MacroStep::Delay(_)));
+
+        if has_delay {
+            let serial_send_tx = serial_send_tx.clone();
+            thread::spawn(move || run_steps(&steps, &serial_send_tx));
+        } else {
+            run_steps(&steps, serial_send_tx);
+        }
+    }
+}
+
+/// Run macro steps
+fn run_steps(steps: &[MacroStep], serial_send_tx: &Sender<Vec<u8>>) {
+    for step in steps {
+        match step {
+            MacroStep::Send(data) => {
+                if serial_send_tx.send(data.clone()).is_err() {
+                    return;
+                }
+            }
+            MacroStep::Delay(delay) => thread::sleep(*delay),
+        }
+    }
+}
+
+/// Append a checksum of all the bytes sent to the last data sent, delays are left in place
+fn with_checksum(steps: &[MacroStep], checksum: &TxChecksum) -> Vec<MacroStep> {
+    let data: Vec<u8> = steps
+        .iter()
+        .filter_map(|step| match step {
+            MacroStep::Send(data) => Some(data.as_slice()),
+            MacroStep::Delay(_) => None,
+        })
+        .flatten()
+        .copied()
+        .collect();
+
+    let mut steps = steps.to_vec();
+    let last = steps.iter_mut().rev().find_map(|step| match step {
+        MacroStep::Send(data) => Some(data),
+        MacroStep::Delay(_) => None,
+    });
+    match last {
+        Some(last) => last.extend(checksum.bytes(&data)),
+        None => steps.push(MacroStep::Send(checksum.bytes(&data))),
+    }
+    steps
+}
+
+/// Parse a `{...}` directive, only `delay <n>ms` and `delay <n>s` are supported
+fn parse_directive(directive: &str) -> Result<Duration, String> {
+    let mut parts = directive.split_whitespace();
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("delay"), Some(time), None) => {
+            let (value, scale) = if let Some(ms) = time.strip_suffix("ms") {
+                (ms, 1)
+            } else if let Some(s) = time.strip_suffix('s') {
+                (s, 1000)
+            } else {
+                (time, 1)
+            };
+
+            value
+                .parse::<u64>()
+                .map(|value| Duration::from_millis(value * scale))
+                .map_err(|_| format!("Invalid delay '{}'", time))
+        }
+        _ => Err(format!("Unknown directive '{{{}}}'", directive)),
+    }
+}
+
+/// Parse a string of hex digits into bytes
+pub fn parse_hex_bytes(digits: &str) -> Result<Vec<u8>, String> {
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("Odd number of hex digits in '{}'", digits));
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            digits
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("Invalid hex '{}'", digits))
+        })
+        .collect()
+}
+
+/// Collects macro steps, merging adjacent data
+#[derive(Default)]
+struct MacroBuilder {
+    /// Steps so far
+    steps: Vec<MacroStep>,
+}
+
+impl MacroBuilder {
+    /// Add bytes to send
+    fn push(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        match self.steps.last_mut() {
+            Some(MacroStep::Send(last)) => last.extend_from_slice(data),
+            _ => self.steps.push(MacroStep::Send(data.to_vec())),
+        }
+    }
+
+    /// Add a delay
+    fn delay(&mut self, delay: Duration) {
+        self.steps.push(MacroStep::Delay(delay));
+    }
+
+    /// Get the finished macro
+    fn build(self) -> Macro {
+        Macro {
+            steps: self.steps,
+            default_checksum: false,
+        }
+    }
+}
+
+/// Parse macro definitions from the config file
+pub fn parse_macros(defs: &HashMap<String, MacroDef>) -> Result<Macros, String> {
+    defs.iter()
+        .map(|(key, def)| {
+            let macro_key = MacroKey::parse(key)?;
+            let parsed = Macro::parse(def).map_err(|e| format!("Macro {}: {}", key, e))?;
+            Ok((macro_key, parsed))
+        })
+        .collect()
+}
@@ -1,36 +1,83 @@
+use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
 use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 use crossterm::event::KeyEvent;
 use crossterm::terminal::{size, ClearType};
 use crossterm::tty::IsTty;
 use crossterm::{
-    cursor::{MoveTo, RestorePosition, SavePosition},
+    cursor::{MoveTo, MoveToColumn},
     event::{KeyCode, KeyModifiers},
     execute,
-    style::{Color, Print, SetForegroundColor},
+    style::Print,
     terminal::{
         disable_raw_mode, enable_raw_mode, Clear, EnableLineWrap, EnterAlternateScreen,
         LeaveAlternateScreen,
     },
 };
+use serialport::SerialPort;
 use structopt::StructOpt;
 
 use app::error::Result;
 use app::{CerialMode, CerialState};
-use args::CerialArgs;
-use serial::{serial_rx_thread, serial_tx_thread};
+use args::{CerialArgs, CerialCommand};
+use pipe::{pipe_loop, stdin_thread, PipeSettings};
+use serial::{reopen_serial_port, serial_rx_thread, serial_tx_thread};
+use ui::draw::{draw_panes, redraw_all, redraw_rx, reset_rx_region, write_rx};
 use ui::{terminal_event_thread, DisplayUpdateEvent};
 
 use crate::app::error::CerialError;
-use crate::app::MenuState;
+use crate::app::{MenuState, PromptKind};
+use crate::at::AtSession;
+use crate::baud::{AutoBaud, AutoBaudStatus};
+use crate::capture::{CaptureStatus, CsvCapture};
+use crate::checksum::TxChecksum;
+use crate::config::Config;
+use crate::decode::{DecoderRegistry, FrameView};
+use crate::filter::LineFilter;
+use crate::highlight::{parse_highlights, preset};
+use crate::history::{History, LINE_MODE};
+use crate::macros::{parse_macros, MacroKey};
+use crate::modbus::{Command, ModbusStatus, Transaction};
+use crate::nmea::NmeaSession;
+use crate::plot::{PlotStyle, Plotter};
+use crate::script::{Script, ScriptStatus};
+use crate::send::{FileSend, SendStatus};
+use crate::transfer::zmodem::CANCEL_SEQUENCE;
+use crate::transfer::{
+    parse_transfer_command, run_transfer, Direction, Protocol, Transfer, TransferStatus,
+};
+use crate::trigger::Triggers;
 use crate::ui::input::key_event_to_vec;
+use crate::ui::line_edit::LineEditor;
+use crate::ui::render::LineRenderer;
 
 mod app;
 mod args;
+mod at;
+mod baud;
+mod capture;
+mod checksum;
+mod config;
+mod decode;
+mod filter;
+mod highlight;
+mod history;
+mod macros;
+mod modbus;
+mod nmea;
+mod pipe;
+mod plot;
+mod script;
+mod send;
 mod serial;
+mod transfer;
+mod trigger;
 mod ui;
 
 /// Handles user inputs in menu mode
@@ -38,8 +85,119 @@ fn menu_mode<T: Write>(
     app_state: &mut CerialState,
     _stream: &mut T,
     key_event: KeyEvent,
+    serial_port: &Arc<Mutex<Box<dyn SerialPort>>>,
+    serial_send_tx: &Sender<Vec<u8>>,
+    display_update_tx: &Sender<DisplayUpdateEvent>,
 ) -> Result<()> {
+    // Only the key right after Ctrl-5 can run a digit macro and return to input mode
+    let macro_pending = app_state.macro_pending;
+    app_state.macro_pending = false;
+
     match key_event {
+        KeyEvent {
+            code: KeyCode::Char(c @ '0'..='9'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            let key = MacroKey::Digit(c as u8 - b'0');
+            if let Some(macro_def) = app_state.macros.get(&key) {
+                macro_def.run(serial_send_tx, app_state.tx_checksum.as_ref());
+                if macro_pending {
+                    app_state.mode = CerialMode::Input;
+                }
+            }
+        }
+        KeyEvent {
+            code: KeyCode::F(n),
+            ..
+        } => {
+            if let Some(macro_def) = app_state.macros.get(&MacroKey::F(n)) {
+                macro_def.run(serial_send_tx, app_state.tx_checksum.as_ref());
+            }
+        }
+        KeyEvent {
+            code: KeyCode::Char('s'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            // Cancel the current send, or prompt for a file to send
+            if let Some(file_send) = &app_state.file_send {
+                file_send.cancel();
+            } else {
+                app_state.open_prompt(PromptKind::SendFile);
+            }
+        }
+        KeyEvent {
+            code: KeyCode::Char('t'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            // Cancel the current transfer, or prompt for a transfer to start
+            if let Some(transfer) = &app_state.transfer {
+                transfer.cancel();
+            } else {
+                app_state.open_prompt(PromptKind::Transfer);
+            }
+        }
+        KeyEvent {
+            code: KeyCode::Char('x'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            // Cancel the running script, or prompt for a script to run
+            if let Some(script) = &app_state.script {
+                script.cancel();
+            } else {
+                app_state.open_prompt(PromptKind::RunScript);
+            }
+        }
+        KeyEvent {
+            code: KeyCode::Char('g'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            if !app_state.triggers.rules.is_empty() {
+                app_state.open_prompt(PromptKind::Triggers);
+            }
+        }
+        KeyEvent {
+            code: KeyCode::Char('f'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            let pattern = app_state
+                .renderer
+                .filter
+                .include
+                .as_ref()
+                .map(|r| r.to_string());
+            app_state.open_prompt(PromptKind::IncludeFilter);
+            app_state.prompt.as_mut().unwrap().input = pattern.unwrap_or_default();
+        }
+        KeyEvent {
+            code: KeyCode::Char('F'),
+            ..
+        } => {
+            let pattern = app_state
+                .renderer
+                .filter
+                .exclude
+                .as_ref()
+                .map(|r| r.to_string());
+            app_state.open_prompt(PromptKind::ExcludeFilter);
+            app_state.prompt.as_mut().unwrap().input = pattern.unwrap_or_default();
+        }
+        KeyEvent {
+            code: KeyCode::Char('d'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            app_state.open_prompt(PromptKind::Decoder);
+        }
+        KeyEvent {
+            code: KeyCode::Char('M'),
+            ..
+        } => {
+            // Stop waiting for the current response, or prompt for a command to send
+            if let Some(modbus) = &app_state.modbus {
+                modbus.cancel();
+            } else {
+                app_state.open_prompt(PromptKind::Modbus);
+            }
+        }
         KeyEvent {
             code: KeyCode::Char('i'),
             modifiers: KeyModifiers::NONE,
@@ -51,7 +209,143 @@ fn menu_mode<T: Write>(
             modifiers: KeyModifiers::NONE,
         } => {
             app_state.mode = CerialMode::HexInput;
+            app_state.layout.show_tx = true;
+            app_state.line_editor.set_mode("hex");
+        }
+        KeyEvent {
+            code: KeyCode::Char('v'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            // Cycle through the text, braille plot, and block plot views
+            app_state.plot = match &app_state.plot {
+                None => Some(Plotter::new(PlotStyle::Braille)),
+                Some(plot) if plot.style == PlotStyle::Braille => {
+                    let mut plot = plot.clone();
+                    plot.style = PlotStyle::Block;
+                    Some(plot)
+                }
+                Some(_) => None,
+            };
+            app_state.redraw = true;
+        }
+        KeyEvent {
+            code: KeyCode::Char('k'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            app_state.open_prompt(PromptKind::Checksum);
+        }
+        KeyEvent {
+            code: KeyCode::Char('w'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            // Stop the current capture, or prompt for a file to capture to
+            match app_state.capture.take() {
+                Some(capture) => app_state.capture_status = Some(capture.stop()),
+                None => app_state.open_prompt(PromptKind::Capture),
+            }
+        }
+        KeyEvent {
+            code: KeyCode::Char('l'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            app_state.mode = CerialMode::Line;
+            app_state.layout.show_tx = true;
+            app_state.line_editor.set_mode(LINE_MODE);
+        }
+        KeyEvent {
+            code: KeyCode::Char('a'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            app_state.mode = CerialMode::At;
+            app_state.layout.show_tx = true;
+            app_state.line_editor.set_mode("at");
+            // Responses are grouped from here on
+            if app_state.at.is_none() {
+                app_state.at = Some(AtSession::default());
+            }
+            if app_state
+                .renderer
+                .set_mode_rules("at", || preset("at").unwrap())
+            {
+                app_state.redraw = true;
+            }
+        }
+        KeyEvent {
+            code: KeyCode::Char('n'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            app_state.mode = CerialMode::Nmea;
+            app_state.layout.show_tx = true;
+            app_state.line_editor.set_mode("nmea");
+            // Sentences are checked from here on, with room for the receiver state
+            if app_state.nmea.is_none() {
+                app_state.nmea = Some(NmeaSession::default());
+                app_state.layout.grow_status(5);
+                app_state.redraw = true;
+            }
+            if app_state
+                .renderer
+                .set_mode_rules("nmea", || preset("nmea").unwrap())
+            {
+                app_state.redraw = true;
+            }
+        }
+        KeyEvent {
+            code: KeyCode::Char('c'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            app_state.reset_counters();
         }
+        KeyEvent {
+            code: KeyCode::Char('b'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            if let Some(auto_baud) = &app_state.auto_baud {
+                auto_baud.cancel();
+            } else if app_state.exec_command.is_none() {
+                start_auto_baud(app_state, serial_port, display_update_tx);
+            }
+        }
+        KeyEvent {
+            code: KeyCode::Char('e'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            app_state.layout.show_tx = !app_state.layout.show_tx;
+        }
+        KeyEvent {
+            code: KeyCode::Char('p'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            app_state.layout.show_status = !app_state.layout.show_status;
+        }
+        KeyEvent {
+            code: KeyCode::Char(c @ '+'),
+            ..
+        }
+        | KeyEvent {
+            code: KeyCode::Char(c @ '-'),
+            ..
+        } => {
+            app_state.layout.resize_status(c == '+');
+        }
+        KeyEvent {
+            code: KeyCode::PageUp,
+            ..
+        } => app_state.scroll(10),
+        KeyEvent {
+            code: KeyCode::PageDown,
+            ..
+        } => app_state.scroll(-10),
+        KeyEvent {
+            code: KeyCode::Up, ..
+        } => app_state.scroll(1),
+        KeyEvent {
+            code: KeyCode::Down,
+            ..
+        } => app_state.scroll(-1),
+        KeyEvent {
+            code: KeyCode::End, ..
+        } => app_state.scroll(isize::MIN),
         KeyEvent {
             code: KeyCode::Char('q'),
             modifiers: KeyModifiers::NONE,
@@ -68,6 +362,16 @@ fn menu_mode<T: Write>(
                 MenuState::SerialTelemetry => MenuState::Hidden,
             };
         }
+        KeyEvent {
+            code: KeyCode::Char('r'),
+            modifiers: KeyModifiers::NONE,
+        } => {
+            // Restart the exec child process once it has exited, so a running session isn't lost
+            if app_state.exec_command.is_some() && app_state.process_exit.is_some() {
+                reopen_serial_port(serial_port, app_state, display_update_tx)?;
+                app_state.process_exit = None;
+            }
+        }
         KeyEvent { .. } => {}
     };
 
@@ -87,51 +391,458 @@ fn insert_mode<T: Write>(
             modifiers: KeyModifiers::CONTROL,
         } => {
             app_state.mode = CerialMode::Menu;
+            app_state.macro_pending = true;
         }
         KeyEvent { .. } => {
-            let data = key_event_to_vec(key_event);
+            // Function keys with a macro send the macro in place of their escape sequence
+            let macro_def =
+                MacroKey::from_key_code(key_event.code).and_then(|key| app_state.macros.get(&key));
+
+            match macro_def {
+                Some(macro_def) => macro_def.run(serial_send_tx, app_state.tx_checksum.as_ref()),
+                None => {
+                    let data = key_event_to_vec(key_event);
+                    serial_send_tx.send(data).unwrap();
+                }
+            }
+        }
+    };
+
+    Ok(())
+}
+
+/// Handles user input in line, AT, NMEA, and hex modes, the line is edited locally and sent when
+/// enter is pressed
+///
+/// AT commands always end with a carriage return and can be completed with tab. NMEA sentences and
+/// UBX commands are sent with their checksums. Hex frames are sent with the TX checksum, and only
+/// once they parse.
+fn line_mode(
+    app_state: &mut CerialState,
+    key_event: KeyEvent,
+    serial_send_tx: &Sender<Vec<u8>>,
+) -> Result<()> {
+    let editor = &mut app_state.line_editor;
+
+    match key_event {
+        KeyEvent {
+            code: KeyCode::Char('5'),
+            modifiers: KeyModifiers::CONTROL,
+        } => {
+            editor.end_search();
+            app_state.mode = CerialMode::Menu;
+        }
+        KeyEvent {
+            code: KeyCode::Enter,
+            ..
+        } => {
+            if let CerialMode::HexInput = app_state.mode {
+                if let Ok(frame) = app_state.hex_frame() {
+                    app_state.line_editor.accept();
+                    serial_send_tx.send(frame).unwrap();
+                }
+                return Ok(());
+            }
+            let line = editor.accept();
+            if let (CerialMode::Nmea, Some(nmea)) = (app_state.mode, &mut app_state.nmea) {
+                match nmea::command(&line) {
+                    Ok(data) => serial_send_tx.send(data).unwrap(),
+                    Err(msg) => nmea.set_error(msg),
+                }
+                return Ok(());
+            }
+            let line_ending = match (app_state.mode, &mut app_state.at) {
+                (CerialMode::At, Some(at)) => {
+                    at.command_sent(&line);
+                    b"\r"
+                }
+                _ => app_state.send_settings.line_ending.as_bytes(),
+            };
+            let mut data = line.into_bytes();
+            data.extend_from_slice(line_ending);
             serial_send_tx.send(data).unwrap();
         }
+        KeyEvent {
+            code: KeyCode::Tab, ..
+        } => {
+            if let (CerialMode::At, Some(at)) = (app_state.mode, &mut app_state.at) {
+                editor.end_search();
+                if let Some(line) = at.complete(&editor.text()) {
+                    editor.replace(&line);
+                }
+            }
+        }
+        KeyEvent {
+            code: KeyCode::Esc, ..
+        }
+        | KeyEvent {
+            code: KeyCode::Char('g'),
+            modifiers: KeyModifiers::CONTROL,
+        } => editor.cancel_search(),
+        KeyEvent {
+            code: KeyCode::Char('r'),
+            modifiers: KeyModifiers::CONTROL,
+        } => editor.search(),
+        KeyEvent {
+            code: KeyCode::Char('w'),
+            modifiers: KeyModifiers::CONTROL,
+        } => editor.delete_word(),
+        KeyEvent {
+            code: KeyCode::Char('u'),
+            modifiers: KeyModifiers::CONTROL,
+        } => editor.delete_to_start(),
+        KeyEvent {
+            code: KeyCode::Char('a'),
+            modifiers: KeyModifiers::CONTROL,
+        }
+        | KeyEvent {
+            code: KeyCode::Home,
+            ..
+        } => editor.home(),
+        KeyEvent {
+            code: KeyCode::Char('e'),
+            modifiers: KeyModifiers::CONTROL,
+        }
+        | KeyEvent {
+            code: KeyCode::End, ..
+        } => editor.end(),
+        KeyEvent {
+            code: KeyCode::Left,
+            ..
+        } => editor.left(),
+        KeyEvent {
+            code: KeyCode::Right,
+            ..
+        } => editor.right(),
+        KeyEvent {
+            code: KeyCode::Up, ..
+        } => editor.older(),
+        KeyEvent {
+            code: KeyCode::Down,
+            ..
+        } => editor.newer(),
+        KeyEvent {
+            code: KeyCode::Backspace,
+            ..
+        } => editor.backspace(),
+        KeyEvent {
+            code: KeyCode::Delete,
+            ..
+        } => editor.delete(),
+        KeyEvent {
+            code: KeyCode::Char(c),
+            modifiers: KeyModifiers::NONE,
+        }
+        | KeyEvent {
+            code: KeyCode::Char(c),
+            modifiers: KeyModifiers::SHIFT,
+        } => editor.insert(c),
+        KeyEvent { .. } => {}
     };
 
     Ok(())
 }
 
-/// Clear the menu bar line
-fn clear_menu_bar<T: Write>(stream: &mut T, menu_pos: u16) -> Result<()> {
-    execute!(
-        stream,
-        SavePosition,
-        MoveTo(0, menu_pos),
-        Clear(ClearType::CurrentLine),
-        RestorePosition
-    )
-    .map_err(|e| e.into())
+/// Start a file transfer in the background
+fn start_transfer(
+    app_state: &mut CerialState,
+    serial_port: &Arc<Mutex<Box<dyn SerialPort>>>,
+    protocol: Protocol,
+    direction: Direction,
+    mut paths: Vec<PathBuf>,
+    received: Vec<u8>,
+    display_update_tx: &Sender<DisplayUpdateEvent>,
+) {
+    if direction == Direction::Receive && paths.is_empty() {
+        paths.push(app_state.download_dir.clone());
+    }
+
+    app_state.transfer_status = None;
+    app_state.transfer = Some(Transfer::start(
+        serial_port.clone(),
+        protocol,
+        direction,
+        paths,
+        received,
+        display_update_tx.clone(),
+    ));
 }
 
-/// Display the current menu bar
-fn print_menu_bar<T: Write>(app_state: &CerialState, stream: &mut T, menu_pos: u16) -> Result<()> {
-    execute!(
-        stream,
-        SavePosition,
-        MoveTo(0, menu_pos),
-        Clear(ClearType::CurrentLine),
-        SetForegroundColor(Color::White),
-        Print(app_state.menu_string()),
-        RestorePosition
-    )
-    .map_err(|e| e.into())
+/// Start running a script in the background
+fn start_script(
+    app_state: &mut CerialState,
+    path: &Path,
+    serial_port: &Arc<Mutex<Box<dyn SerialPort>>>,
+    serial_send_tx: &Sender<Vec<u8>>,
+    display_update_tx: &Sender<DisplayUpdateEvent>,
+) {
+    app_state.script_status = None;
+    app_state.script = Some(Script::start(
+        path,
+        serial_port.clone(),
+        serial_send_tx.clone(),
+        display_update_tx.clone(),
+    ));
+}
+
+/// Start auto baud detection in the background
+fn start_auto_baud(
+    app_state: &mut CerialState,
+    serial_port: &Arc<Mutex<Box<dyn SerialPort>>>,
+    display_update_tx: &Sender<DisplayUpdateEvent>,
+) {
+    app_state.auto_baud_status = None;
+    app_state.auto_baud = Some(AutoBaud::start(
+        baud::rates(&app_state.baud_rates),
+        serial_port.clone(),
+        app_state.link_counters.clone(),
+        display_update_tx.clone(),
+    ));
+}
+
+/// Handles user input while a menu bar prompt is active
+fn prompt_mode(
+    app_state: &mut CerialState,
+    key_event: KeyEvent,
+    serial_port: &Arc<Mutex<Box<dyn SerialPort>>>,
+    serial_send_tx: &Sender<Vec<u8>>,
+    display_update_tx: &Sender<DisplayUpdateEvent>,
+) -> Result<()> {
+    let prompt = match &mut app_state.prompt {
+        Some(prompt) => prompt,
+        None => {
+            app_state.mode = CerialMode::Menu;
+            return Ok(());
+        }
+    };
+
+    // Yes or no prompts act on a single key
+    if let PromptKind::ZmodemReceive = prompt.kind {
+        match key_event.code {
+            KeyCode::Char('y') | KeyCode::Enter => {
+                app_state.close_prompt();
+                let received = app_state.zmodem_detector.take_held();
+                start_transfer(
+                    app_state,
+                    serial_port,
+                    Protocol::Zmodem,
+                    Direction::Receive,
+                    Vec::new(),
+                    received,
+                    display_update_tx,
+                );
+            }
+            KeyCode::Char('n') | KeyCode::Esc => {
+                app_state.close_prompt();
+                app_state.zmodem_detector.take_held();
+                serial_send_tx.send(CANCEL_SEQUENCE.to_vec()).ok();
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+
+    match key_event.code {
+        // Ctrl and Alt chords aren't text
+        KeyCode::Char(c)
+            if matches!(
+                key_event.modifiers,
+                KeyModifiers::NONE | KeyModifiers::SHIFT
+            ) =>
+        {
+            prompt.input.push(c)
+        }
+        KeyCode::Backspace => {
+            prompt.input.pop();
+        }
+        KeyCode::Esc => {
+            // Don't leave the remote receiver waiting
+            if let Some(PromptKind::ZmodemSend) = app_state.close_prompt().map(|p| p.kind) {
+                app_state.zmodem_detector.take_held();
+                serial_send_tx.send(CANCEL_SEQUENCE.to_vec()).ok();
+            }
+        }
+        KeyCode::Enter => {
+            let prompt = app_state.close_prompt().unwrap();
+
+            match prompt.kind {
+                PromptKind::SendFile => {
+                    app_state.send_status = None;
+                    app_state.file_send = Some(FileSend::start(
+                        Path::new(prompt.input.trim()),
+                        app_state.send_settings.clone(),
+                        serial_send_tx.clone(),
+                        display_update_tx.clone(),
+                    ));
+                }
+                PromptKind::Transfer => match parse_transfer_command(&prompt.input) {
+                    Ok((protocol, direction, paths)) => start_transfer(
+                        app_state,
+                        serial_port,
+                        protocol,
+                        direction,
+                        paths,
+                        Vec::new(),
+                        display_update_tx,
+                    ),
+                    Err(msg) => app_state.transfer_status = Some(TransferStatus::Failed(msg)),
+                },
+                PromptKind::ZmodemSend => {
+                    let paths: Vec<PathBuf> =
+                        prompt.input.split_whitespace().map(PathBuf::from).collect();
+                    let received = app_state.zmodem_detector.take_held();
+                    if paths.is_empty() {
+                        serial_send_tx.send(CANCEL_SEQUENCE.to_vec()).ok();
+                    } else {
+                        start_transfer(
+                            app_state,
+                            serial_port,
+                            Protocol::Zmodem,
+                            Direction::Send,
+                            paths,
+                            received,
+                            display_update_tx,
+                        );
+                    }
+                }
+                PromptKind::RunScript => start_script(
+                    app_state,
+                    Path::new(prompt.input.trim()),
+                    serial_port,
+                    serial_send_tx,
+                    display_update_tx,
+                ),
+                PromptKind::Triggers => {
+                    let input = prompt.input.trim();
+                    if input.is_empty() || input == "all" {
+                        app_state.triggers.toggle_all();
+                    } else {
+                        for number in input.split_whitespace().filter_map(|n| n.parse().ok()) {
+                            app_state.triggers.toggle(number);
+                        }
+                    }
+                }
+                PromptKind::IncludeFilter | PromptKind::ExcludeFilter => {
+                    match LineFilter::parse_pattern(&prompt.input) {
+                        Ok(pattern) => {
+                            let filter = &mut app_state.renderer.filter;
+                            match prompt.kind {
+                                PromptKind::IncludeFilter => filter.include = pattern,
+                                _ => filter.exclude = pattern,
+                            }
+                            // Show the scrollback through the new filter
+                            app_state.redraw = true;
+                        }
+                        // Leave the prompt open so the pattern can be fixed
+                        Err(_) => {
+                            app_state.open_prompt(prompt.kind);
+                            app_state.prompt.as_mut().unwrap().input = prompt.input;
+                        }
+                    }
+                }
+                PromptKind::Decoder => {
+                    let mut words = prompt.input.split_whitespace();
+                    match words.next() {
+                        None | Some("off") => app_state.frame_view = None,
+                        Some(name) => {
+                            let raw_hex = words.next() == Some("raw");
+                            match FrameView::new(&app_state.decoders, name, raw_hex) {
+                                Ok(frame_view) => app_state.frame_view = Some(frame_view),
+                                // Leave the prompt open so the name can be fixed
+                                Err(_) => {
+                                    app_state.open_prompt(prompt.kind);
+                                    app_state.prompt.as_mut().unwrap().input = prompt.input;
+                                }
+                            }
+                        }
+                    }
+                }
+                PromptKind::Checksum => match prompt.input.trim() {
+                    "" | "off" => app_state.tx_checksum = None,
+                    input => match input.parse::<TxChecksum>() {
+                        Ok(tx_checksum) => app_state.tx_checksum = Some(tx_checksum),
+                        // Leave the prompt open so the name can be fixed
+                        Err(_) => {
+                            app_state.open_prompt(prompt.kind);
+                            app_state.prompt.as_mut().unwrap().input = prompt.input;
+                        }
+                    },
+                },
+                PromptKind::Modbus => match prompt.input.parse::<Command>() {
+                    Ok(command) => {
+                        app_state.modbus_status = None;
+                        app_state.modbus = Some(Transaction::start(
+                            command,
+                            serial_send_tx.clone(),
+                            display_update_tx.clone(),
+                        ));
+                    }
+                    Err(msg) => {
+                        app_state.modbus_status = Some(ModbusStatus::Failed(prompt.input, msg))
+                    }
+                },
+                PromptKind::Capture => {
+                    let path = match prompt.input.trim() {
+                        "" => CsvCapture::default_path(&app_state.download_dir),
+                        input => PathBuf::from(input),
+                    };
+                    app_state.capture_status = None;
+                    match CsvCapture::create(&path) {
+                        Ok(capture) => app_state.capture = Some(capture),
+                        Err(e) => {
+                            app_state.capture_status =
+                                Some(CaptureStatus::Failed(format!("{}: {}", path.display(), e)))
+                        }
+                    }
+                }
+                PromptKind::ZmodemReceive => {}
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Add received text to the scrollback and display it in the receive pane
+fn display_rx<T: Write>(stream: &mut T, app_state: &mut CerialState, data: &[u8]) -> Result<()> {
+    // Keep the view still while scrolled back, until it reaches the oldest line kept
+    let completed = app_state.scrollback.push(data);
+    if app_state.scroll_offset > 0 {
+        let max = app_state.scrollback.len().saturating_sub(1);
+        app_state.scroll_offset = (app_state.scroll_offset + completed).min(max);
+    }
+
+    // Display data to terminal, the renderer keeps track of lines while scrolled or plotting
+    let rendered = app_state.renderer.render(data);
+    match &mut app_state.plot {
+        Some(plot) => plot.process(data),
+        None if app_state.scroll_offset == 0 => write_rx(stream, &rendered)?,
+        None => {}
+    }
+
+    // Stop capturing if the file can't be written
+    if let Some(capture) = &mut app_state.capture {
+        if let Err(e) = capture.process(data) {
+            app_state.capture = None;
+            app_state.capture_status = Some(CaptureStatus::Failed(e.to_string()));
+        }
+    }
+
+    Ok(())
 }
 
 /// Main display loop
 fn display_loop(
     mut cerial_state: CerialState,
     display_update_rx: Receiver<DisplayUpdateEvent>,
+    display_update_tx: Sender<DisplayUpdateEvent>,
     serial_send_tx: Sender<Vec<u8>>,
+    serial_port: Arc<Mutex<Box<dyn SerialPort>>>,
+    mut log_file: Option<File>,
 ) -> Result<()> {
-    // Get stdout and stderr file descs
+    // Get stdout file desc
     let mut stdout = io::stdout();
-    let mut stderr = io::stderr();
 
     // If stdout is not a tty, exit
     if !stdout.is_tty() {
@@ -151,23 +862,17 @@ fn display_loop(
     )?;
 
     // Get initial terminal size
-    // NOTE: these values appear to be much larger than what the terminal size actually is.
-    // It could be a Konsole thing, might want to test this on other platforms.
-    // The values we get from resize events look ok, so these are good enough stand in values.
-    let (mut rows, mut _cols) = size()?;
-
-    // Print the menu bar
-    print_menu_bar(&cerial_state, &mut stderr, rows - 1)?;
+    let (mut cols, mut rows) = size()?;
 
-    // Get real terminal size after we have written to it
+    // Draw the panes
+    let mut panes = cerial_state.layout.panes(rows);
+    redraw_all(&mut stdout, &mut cerial_state, &panes, cols)?;
 
     // Until the user exits
     while !cerial_state.exit {
         // Wait for display update event
         let event = display_update_rx.recv()?;
-
-        // Clear menu bar before processing the event
-        clear_menu_bar(&mut stderr, rows - 1)?;
+        let mut resized = false;
 
         // Match event
         match event {
@@ -175,71 +880,477 @@ fn display_loop(
             DisplayUpdateEvent::KeyInput(event) => {
                 // Handle key input based on state
                 match cerial_state.mode {
-                    CerialMode::Menu => menu_mode(&mut cerial_state, &mut stdout, event)?,
+                    CerialMode::Menu => menu_mode(
+                        &mut cerial_state,
+                        &mut stdout,
+                        event,
+                        &serial_port,
+                        &serial_send_tx,
+                        &display_update_tx,
+                    )?,
                     CerialMode::Input => {
                         insert_mode(&mut cerial_state, &mut stdout, event, &serial_send_tx)?
                     }
-                    _ => {}
+                    CerialMode::Line | CerialMode::At | CerialMode::Nmea | CerialMode::HexInput => {
+                        line_mode(&mut cerial_state, event, &serial_send_tx)?
+                    }
+                    CerialMode::Prompt => prompt_mode(
+                        &mut cerial_state,
+                        event,
+                        &serial_port,
+                        &serial_send_tx,
+                        &display_update_tx,
+                    )?,
                 };
             }
             // On serial input
             DisplayUpdateEvent::SerialInput(data) => {
-                // Display data to terminal
-                stdout.write_all(data.as_slice())?;
-                stdout.flush()?;
+                // Log everything received, before any filtering
+                if let Some(log_file) = &mut log_file {
+                    log_file.write_all(&data)?;
+                }
+
+                // Hold data for the transfer while a ZMODEM transfer is waiting to be accepted
+                let zmodem_pending = matches!(
+                    cerial_state.prompt.as_ref().map(|p| p.kind),
+                    Some(PromptKind::ZmodemReceive) | Some(PromptKind::ZmodemSend)
+                );
+
+                if zmodem_pending {
+                    cerial_state.zmodem_detector.hold(&data);
+                } else {
+                    if let Some(frame_view) = &mut cerial_state.frame_view {
+                        // Show decoded frames in place of the data, one per line
+                        for line in frame_view.lines(&data) {
+                            let line = format!("{}\r\n", line);
+                            display_rx(&mut stdout, &mut cerial_state, line.as_bytes())?;
+                        }
+                    } else {
+                        // Show each finished AT command right after its final result code, and
+                        // each bad NMEA sentence right after the sentence
+                        let mut summaries = Vec::new();
+                        if let Some(at) = &mut cerial_state.at {
+                            summaries.extend(
+                                at.process(&data)
+                                    .into_iter()
+                                    .map(|(end, response)| (end, format!("[AT] {}", response))),
+                            );
+                        }
+                        if let Some(nmea) = &mut cerial_state.nmea {
+                            summaries.extend(
+                                nmea.process(&data)
+                                    .into_iter()
+                                    .map(|(end, msg)| (end, format!("[NMEA] {}", msg))),
+                            );
+                        }
+                        summaries.sort_by_key(|(end, _)| *end);
+
+                        let mut start = 0;
+                        for (end, summary) in summaries {
+                            display_rx(&mut stdout, &mut cerial_state, &data[start..end])?;
+                            let summary = format!("{}\r\n", summary);
+                            display_rx(&mut stdout, &mut cerial_state, summary.as_bytes())?;
+                            start = end;
+                        }
+                        display_rx(&mut stdout, &mut cerial_state, &data[start..])?;
+                    }
+
+                    // Send responses for any trigger rules the data matches
+                    cerial_state.triggers.process(
+                        &data,
+                        &serial_send_tx,
+                        cerial_state.tx_checksum.as_ref(),
+                    );
+
+                    // Offer to start a transfer when the remote starts ZMODEM
+                    if cerial_state.prompt.is_none() && cerial_state.transfer.is_none() {
+                        match cerial_state.zmodem_detector.detect(&data) {
+                            Some(Direction::Receive) => {
+                                cerial_state.open_prompt(PromptKind::ZmodemReceive)
+                            }
+                            Some(Direction::Send) => {
+                                cerial_state.open_prompt(PromptKind::ZmodemSend)
+                            }
+                            None => {}
+                        }
+                    }
+                }
+
+                // Pass data on to an in progress file send
+                if let Some(file_send) = &cerial_state.file_send {
+                    file_send.serial_input_tx.send(data.clone()).ok();
+                }
+
+                // Pass data on to a Modbus command waiting for its response
+                if let Some(modbus) = &cerial_state.modbus {
+                    modbus.serial_input_tx.send(data.clone()).ok();
+                }
+
+                // Pass data on to auto baud detection
+                if let Some(auto_baud) = &cerial_state.auto_baud {
+                    auto_baud.serial_input_tx.send(data.clone()).ok();
+                }
+
+                // Pass data on to a running script
+                if let Some(script) = &cerial_state.script {
+                    script.serial_input_tx.send(data).ok();
+                }
             }
             //On serial telemetry update
             DisplayUpdateEvent::SerialTelemetry(tel) => {
                 // Update current telemetry
                 cerial_state.serial_telemetry = tel;
+                cerial_state.throughput.update(&cerial_state.link_counters);
             }
             // On terminal resize event
             DisplayUpdateEvent::TerminalResize(new_cols, new_rows) => {
-                // Update terminal size
-                _cols = new_cols;
+                // Update terminal size, the panes are redrawn below
+                cols = new_cols;
                 rows = new_rows;
+                resized = true;
             }
-            _ => {}
+            // On serial port error
+            DisplayUpdateEvent::SerialError(err) => {
+                write_rx(&mut stdout, format!("Error {}\r\n", err).as_bytes())?;
+            }
+            // Show partial lines held by the renderer, like prompts
+            DisplayUpdateEvent::Ping => {
+                let rendered = cerial_state.renderer.flush_idle();
+                if cerial_state.scroll_offset == 0 && cerial_state.plot.is_none() {
+                    write_rx(&mut stdout, &rendered)?;
+                }
+
+                // Redraw the plot at most once a ping
+                if cerial_state.plot.as_ref().is_some_and(|plot| plot.dirty) {
+                    cerial_state.redraw = true;
+                }
+
+                // Show frames ended by a gap in the data
+                if let Some(frame_view) = &mut cerial_state.frame_view {
+                    for line in frame_view.flush() {
+                        let line = format!("{}\r\n", line);
+                        display_rx(&mut stdout, &mut cerial_state, line.as_bytes())?;
+                    }
+                }
+
+                // Give up on AT commands that never got a final result code
+                if let Some(response) = cerial_state.at.as_mut().and_then(|at| at.check_timeout()) {
+                    let summary = format!("\r\n[AT] {}\r\n", response);
+                    display_rx(&mut stdout, &mut cerial_state, summary.as_bytes())?;
+                }
+            }
+            // On file send status update
+            DisplayUpdateEvent::FileSend(status) => {
+                if !matches!(status, SendStatus::Progress { .. }) {
+                    cerial_state.file_send = None;
+                }
+                cerial_state.send_status = Some(status);
+            }
+            // On file transfer status update
+            DisplayUpdateEvent::Transfer(status) => {
+                if !matches!(status, TransferStatus::Progress(_)) {
+                    cerial_state.transfer = None;
+                }
+                cerial_state.transfer_status = Some(status);
+            }
+            // On script status update
+            DisplayUpdateEvent::Script(status) => {
+                if !matches!(status, ScriptStatus::Log(_)) {
+                    cerial_state.script = None;
+                }
+                cerial_state.script_status = Some(status);
+            }
+            // On auto baud status update
+            DisplayUpdateEvent::AutoBaud(status) => {
+                if let AutoBaudStatus::Locked(rate) = status {
+                    cerial_state.serial_settings.baud_rate = rate;
+                }
+                if !matches!(status, AutoBaudStatus::Trying(_)) {
+                    cerial_state.auto_baud = None;
+                }
+                cerial_state.auto_baud_status = Some(status);
+            }
+            // On Modbus master command status update
+            DisplayUpdateEvent::Modbus(status) => {
+                if !matches!(status, ModbusStatus::Waiting(_)) {
+                    cerial_state.modbus = None;
+                    let line = format!("{}\r\n", status);
+                    display_rx(&mut stdout, &mut cerial_state, line.as_bytes())?;
+                }
+                cerial_state.modbus_status = Some(status);
+            }
+            // On a script changing the baud rate
+            DisplayUpdateEvent::BaudRate(rate) => {
+                cerial_state.serial_settings.baud_rate = rate;
+            }
+            // On exec child process exit
+            DisplayUpdateEvent::ProcessExit(status) => {
+                cerial_state.process_exit = Some(status);
+            }
+            // Stdin is only read in pipe mode
+            DisplayUpdateEvent::InputClosed => {}
         }
-        // Update menu bar
-        print_menu_bar(&cerial_state, &mut stderr, rows - 1)?;
+        // Redraw everything if the layout changed, or just the receive pane if asked to
+        let new_panes = cerial_state.layout.panes(rows);
+        if new_panes != panes || resized {
+            panes = new_panes;
+            redraw_all(&mut stdout, &mut cerial_state, &panes, cols)?;
+        } else if cerial_state.redraw {
+            redraw_rx(&mut stdout, &mut cerial_state, &panes, cols)?;
+        }
+        cerial_state.redraw = false;
+
+        // Update the transmit pane, status panel, and menu bar
+        draw_panes(&mut stdout, &cerial_state, &panes, cols)?;
     }
 
     // Restore terminal to initial state
+    reset_rx_region(&mut stdout)?;
     disable_raw_mode()?;
     execute!(stdout, LeaveAlternateScreen,)?;
 
     Ok(())
 }
 
+/// Run a file transfer, printing progress to stderr
+fn transfer_command(
+    serial_port: &Arc<Mutex<Box<dyn SerialPort>>>,
+    protocol: Protocol,
+    direction: Direction,
+    paths: &[PathBuf],
+) -> Result<()> {
+    let mut serial_port = serial_port.lock().unwrap();
+    let mut stderr = io::stderr();
+
+    let progress = run_transfer(
+        &mut **serial_port,
+        protocol,
+        direction,
+        paths,
+        Vec::new(),
+        Arc::new(AtomicBool::new(false)),
+        Box::new(|progress| {
+            execute!(
+                stderr,
+                MoveToColumn(0),
+                Clear(ClearType::CurrentLine),
+                Print(progress)
+            )
+            .ok();
+        }),
+    )?;
+
+    eprintln!("\r\n{}", TransferStatus::Complete(progress));
+    Ok(())
+}
+
+/// Send a Modbus command and print the response
+fn modbus_command(serial_port: &Arc<Mutex<Box<dyn SerialPort>>>, command: &str) -> Result<()> {
+    let command: Command = command.parse().map_err(CerialError::ModbusError)?;
+    let mut serial_port = serial_port.lock().unwrap();
+    let response = command
+        .run(&mut **serial_port)
+        .map_err(|msg| CerialError::ModbusError(format!("{}: {}", command, msg)))?;
+    println!("{}", response);
+    Ok(())
+}
+
 fn main() -> Result<()> {
     // Parse args
     let args: CerialArgs = CerialArgs::from_args();
+    if io::stdout().is_tty() {
+        args.reject_pipe_options();
+    }
     let serial_settings = args.clone().into();
+    let send_settings = args.clone().into();
+
+    // Load config
+    let config = Config::load(args.config.as_deref())?;
+    let profile = config.profile(args.profile.as_deref())?;
+    let macros = parse_macros(&profile.macros).map_err(CerialError::ConfigError)?;
+    let triggers = Triggers::parse(&profile.triggers).map_err(CerialError::ConfigError)?;
+    let mut highlight_presets = profile.highlight_presets.clone();
+    highlight_presets.extend(args.highlight.iter().cloned());
+    let highlights = parse_highlights(&profile.highlights, &highlight_presets)
+        .map_err(CerialError::ConfigError)?;
+    let mut decoders = DecoderRegistry::default();
+    for def in &profile.decoders {
+        decoders
+            .register_def(def)
+            .map_err(CerialError::ConfigError)?;
+    }
+    let frame_view = args
+        .decode
+        .as_deref()
+        .map(|name| FrameView::new(&decoders, name, args.raw_hex))
+        .transpose()
+        .map_err(CerialError::ConfigError)?;
+    let tx_checksum = args
+        .tx_checksum
+        .or(profile.tx_checksum)
+        .map(|kind| TxChecksum::new(kind, args.tx_endian.or(profile.tx_endian)));
 
     // Initialize app state
     let cerial_state = CerialState::default()
         .update_serial_settings(serial_settings)
-        .update_serial_dev(args.serial_port.to_str().unwrap());
-
-    // Open serial port
-    let serialport_read = serial::open_serial_port(&cerial_state)?;
-    let serialport_write = serialport_read.clone();
+        .update_send_settings(send_settings)
+        .update_download_dir(args.download_dir.clone())
+        .update_mark_errors(args.mark_errors)
+        .update_baud_rates(args.baud_rates.clone())
+        .update_macros(macros)
+        .update_tx_checksum(tx_checksum)
+        .update_triggers(triggers)
+        .update_renderer(LineRenderer::new(highlights))
+        .update_decoders(decoders)
+        .update_frame_view(frame_view)
+        .update_line_editor(LineEditor::new(
+            [LINE_MODE, "at", "nmea", "hex"]
+                .iter()
+                .map(|mode| (*mode, History::load(args.profile.as_deref(), mode)))
+                .collect(),
+        ));
+    let mut cerial_state = match &args.command {
+        Some(CerialCommand::Exec { command }) => cerial_state.update_exec_command(command.clone()),
+        Some(CerialCommand::Transfer { .. })
+        | Some(CerialCommand::Modbus { .. })
+        | Some(CerialCommand::Run { .. })
+        | None => {
+            let (serial_port, _) = args.require_serial_port();
+            cerial_state.update_serial_dev(serial_port.to_str().unwrap())
+        }
+    };
 
     // Setup Channels
     let (term_display_update_tx, display_update_rx) = channel();
     let serial_display_update_tx = term_display_update_tx.clone();
+    let display_update_tx = term_display_update_tx.clone();
     let (serial_send_tx, serial_send_rx) = channel();
 
+    // Start sending a file if requested
+    if let Some(path) = &args.send {
+        cerial_state.file_send = Some(FileSend::start(
+            path,
+            cerial_state.send_settings.clone(),
+            serial_send_tx.clone(),
+            display_update_tx.clone(),
+        ));
+    }
+
+    // Open serial port
+    let serialport_read = serial::open_serial_port(&cerial_state, &display_update_tx)?;
+    let serialport_write = serialport_read.clone();
+    let serialport = serialport_read.clone();
+
+    // Warn if the driver couldn't set the requested rate, the UI shows this with the port settings
+    // instead since the alternate screen would hide it
+    if cerial_state.exec_command.is_none() && !io::stdout().is_tty() {
+        let requested = cerial_state.serial_settings.baud_rate;
+        if let Ok(actual) = serialport.lock().unwrap().baud_rate() {
+            if let Some(warning) = serial::baud_rate_warning(requested, actual) {
+                eprintln!("{}", warning);
+            }
+        }
+    }
+
+    // Start running a script if requested
+    if let Some(CerialCommand::Run { script }) = &args.command {
+        start_script(
+            &mut cerial_state,
+            script,
+            &serialport,
+            &serial_send_tx,
+            &display_update_tx,
+        );
+    }
+
+    // Start detecting the baud rate if requested
+    if args.auto_baud {
+        start_auto_baud(&mut cerial_state, &serialport, &display_update_tx);
+    }
+
+    // File transfers from the command line run without the UI
+    if let Some(CerialCommand::Transfer {
+        protocol,
+        direction,
+        paths,
+    }) = &args.command
+    {
+        let mut paths = paths.clone();
+        if *direction == Direction::Receive && paths.is_empty() {
+            paths.push(args.download_dir.clone());
+        }
+        return transfer_command(&serialport, *protocol, *direction, &paths);
+    }
+
+    // Modbus commands from the command line run without the UI
+    if let Some(CerialCommand::Modbus { command }) = &args.command {
+        return modbus_command(&serialport, &command.join(" "));
+    }
+
+    // Open the log file
+    let log_file = match &args.log {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+
+    // Without a terminal to draw to, run headless and stream stdin/stdout through the port
+    if !io::stdout().is_tty() {
+        let rx_counters = cerial_state.link_counters.clone();
+        let tx_counters = cerial_state.link_counters.clone();
+        let serial_rx_thread = thread::spawn(move || {
+            serial_rx_thread(serial_display_update_tx, serialport_read, rx_counters)
+        });
+        thread::spawn(move || serial_tx_thread(serial_send_rx, serialport_write, tx_counters));
+        let pipe_send_tx = serial_send_tx.clone();
+        let stdin_display_update_tx = display_update_tx.clone();
+        let stdin_counters = cerial_state.link_counters.clone();
+        thread::spawn(move || {
+            stdin_thread(serial_send_tx, stdin_display_update_tx, stdin_counters)
+        });
+
+        // Drop extra senders so the pipe loop sees the channel close once the RX and stdin
+        // threads exit
+        drop(term_display_update_tx);
+        drop(display_update_tx);
+
+        let mut pipe_settings: PipeSettings = args.into();
+        pipe_settings.frame_view = cerial_state.frame_view;
+        pipe_settings.tx_checksum = cerial_state.tx_checksum;
+        pipe_settings.log_file = log_file;
+        let exit_code = pipe_loop(
+            pipe_settings,
+            display_update_rx,
+            cerial_state.file_send,
+            cerial_state.script,
+            cerial_state.auto_baud,
+            cerial_state.triggers,
+            pipe_send_tx,
+        )?;
+
+        // The stdin thread may be blocked on a read, so exit without joining
+        serial_rx_thread.join().unwrap();
+        std::process::exit(exit_code);
+    }
+
     // Start threads
     let term_event_thread = thread::spawn(move || terminal_event_thread(term_display_update_tx));
-    let serial_rx_thread =
-        thread::spawn(move || serial_rx_thread(serial_display_update_tx, serialport_read));
+    let rx_counters = cerial_state.link_counters.clone();
+    let tx_counters = cerial_state.link_counters.clone();
+    let serial_rx_thread = thread::spawn(move || {
+        serial_rx_thread(serial_display_update_tx, serialport_read, rx_counters)
+    });
     let serial_tx_thread =
-        thread::spawn(move || serial_tx_thread(serial_send_rx, serialport_write));
+        thread::spawn(move || serial_tx_thread(serial_send_rx, serialport_write, tx_counters));
 
     // Begin display loop
-    display_loop(cerial_state, display_update_rx, serial_send_tx)?;
+    display_loop(
+        cerial_state,
+        display_update_rx,
+        display_update_tx,
+        serial_send_tx,
+        serialport,
+        log_file,
+    )?;
 
     // Cleanup
     term_event_thread.join().unwrap();
@@ -0,0 +1,705 @@
+pub mod monitor;
+
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serialport::SerialPort;
+
+use crate::checksum::crc16_modbus;
+use crate::decode::{Field, Value};
+use crate::ui::DisplayUpdateEvent;
+
+/// Longest wait for a slave to respond
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Largest RTU frame, address, PDU, and CRC
+pub const MAX_FRAME: usize = 256;
+
+/// Most coils one request can read
+const MAX_READ_COILS: u16 = 2000;
+
+/// Most registers one request can read
+const MAX_READ_REGISTERS: u16 = 125;
+
+/// Most coils one request can write
+const MAX_WRITE_COILS: usize = 1968;
+
+/// Most registers one request can write
+const MAX_WRITE_REGISTERS: usize = 123;
+
+/// Read coils function code
+const READ_COILS: u8 = 0x01;
+/// Read discrete inputs function code
+const READ_DISCRETE_INPUTS: u8 = 0x02;
+/// Read holding registers function code
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+/// Read input registers function code
+const READ_INPUT_REGISTERS: u8 = 0x04;
+/// Write single coil function code
+const WRITE_SINGLE_COIL: u8 = 0x05;
+/// Write single register function code
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+/// Write multiple coils function code
+const WRITE_MULTIPLE_COILS: u8 = 0x0f;
+/// Write multiple registers function code
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+/// Set on the function code of exception responses
+pub const EXCEPTION: u8 = 0x80;
+
+/// Get the name of a function code
+pub fn function_name(function: u8) -> &'static str {
+    match function & !EXCEPTION {
+        READ_COILS => "read-coils",
+        READ_DISCRETE_INPUTS => "read-discrete",
+        READ_HOLDING_REGISTERS => "read-holding",
+        READ_INPUT_REGISTERS => "read-input",
+        WRITE_SINGLE_COIL => "write-coil",
+        WRITE_SINGLE_REGISTER => "write-register",
+        WRITE_MULTIPLE_COILS => "write-coils",
+        WRITE_MULTIPLE_REGISTERS => "write-registers",
+        _ => "unknown",
+    }
+}
+
+/// Get the name of an exception code
+fn exception_name(code: u8) -> &'static str {
+    match code {
+        0x01 => "illegal function",
+        0x02 => "illegal data address",
+        0x03 => "illegal data value",
+        0x04 => "server device failure",
+        0x05 => "acknowledge",
+        0x06 => "server device busy",
+        0x08 => "memory parity error",
+        0x0a => "gateway path unavailable",
+        0x0b => "gateway target failed to respond",
+        _ => "unknown exception",
+    }
+}
+
+/// Append the CRC to a frame, low byte first
+fn with_crc(mut frame: Vec<u8>) -> Vec<u8> {
+    let crc = crc16_modbus(&frame);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    frame
+}
+
+/// Check a whole frame's CRC
+pub fn crc_ok(frame: &[u8]) -> bool {
+    frame.len() >= 4 && {
+        let (data, crc) = frame.split_at(frame.len() - 2);
+        crc16_modbus(data).to_le_bytes() == crc
+    }
+}
+
+/// Get the length of a request frame from its start, `None` if it can't be told yet
+pub fn request_len(frame: &[u8]) -> Option<usize> {
+    match *frame.get(1)? {
+        READ_COILS..=WRITE_SINGLE_REGISTER => Some(8),
+        WRITE_MULTIPLE_COILS | WRITE_MULTIPLE_REGISTERS => {
+            frame.get(6).map(|count| 9 + *count as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Get the length of a response frame from its start, `None` if it can't be told yet
+pub fn response_len(frame: &[u8]) -> Option<usize> {
+    match *frame.get(1)? {
+        function if function & EXCEPTION != 0 => Some(5),
+        READ_COILS..=READ_INPUT_REGISTERS => frame.get(2).map(|count| 5 + *count as usize),
+        WRITE_SINGLE_COIL | WRITE_SINGLE_REGISTER => Some(8),
+        WRITE_MULTIPLE_COILS | WRITE_MULTIPLE_REGISTERS => Some(8),
+        _ => None,
+    }
+}
+
+/// Read a big endian word
+fn word(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// Get the first `count` bits of packed coil bytes, least significant bit first
+fn unpack_bits(bytes: &[u8], count: usize) -> Vec<Value> {
+    (0..count.min(bytes.len() * 8))
+        .map(|i| Value::Unsigned((bytes[i / 8] >> (i % 8)) as u64 & 1))
+        .collect()
+}
+
+/// Get register values from big endian words
+fn unpack_registers(bytes: &[u8]) -> Vec<Value> {
+    bytes
+        .chunks_exact(2)
+        .map(|pair| Value::Unsigned(word(pair, 0) as u64))
+        .collect()
+}
+
+/// Decode a frame with a valid CRC into fields
+pub fn describe(frame: &[u8], request: bool) -> Vec<Field> {
+    let (unit, function) = (frame[0], frame[1]);
+    let pdu = &frame[2..frame.len() - 2];
+    // Show the code of functions without a name
+    let name = match function_name(function) {
+        "unknown" => format!("{:02x}", function & !EXCEPTION),
+        name => name.to_string(),
+    };
+    let mut fields = vec![
+        Field::new("unit", Value::Unsigned(unit as u64)),
+        Field::new("fn", Value::Text(name)),
+    ];
+    let address = |fields: &mut Vec<Field>| {
+        fields.push(Field::new("addr", Value::Unsigned(word(pdu, 0) as u64)));
+    };
+
+    match (function, request, pdu.len()) {
+        (function, _, 1) if function & EXCEPTION != 0 => {
+            let code = pdu[0];
+            let exception = format!("{:02x} {}", code, exception_name(code));
+            fields.push(Field::new("exception", Value::Text(exception)));
+        }
+        (READ_COILS..=READ_INPUT_REGISTERS, true, 4) => {
+            address(&mut fields);
+            fields.push(Field::new("count", Value::Unsigned(word(pdu, 2) as u64)));
+        }
+        (READ_COILS | READ_DISCRETE_INPUTS, false, len) if len == 1 + pdu[0] as usize => {
+            let bits = unpack_bits(&pdu[1..], pdu[0] as usize * 8);
+            fields.push(Field::new("bits", Value::List(bits)));
+        }
+        (READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS, false, len)
+            if len == 1 + pdu[0] as usize =>
+        {
+            let registers = unpack_registers(&pdu[1..]);
+            fields.push(Field::new("regs", Value::List(registers)));
+        }
+        (WRITE_SINGLE_COIL, _, 4) => {
+            address(&mut fields);
+            let value = if word(pdu, 2) == 0xff00 { "on" } else { "off" };
+            fields.push(Field::new("value", Value::Text(value.to_string())));
+        }
+        (WRITE_SINGLE_REGISTER, _, 4)
+        | (WRITE_MULTIPLE_COILS | WRITE_MULTIPLE_REGISTERS, false, 4) => {
+            address(&mut fields);
+            let name = match function {
+                WRITE_SINGLE_REGISTER => "value",
+                _ => "count",
+            };
+            fields.push(Field::new(name, Value::Unsigned(word(pdu, 2) as u64)));
+        }
+        (WRITE_MULTIPLE_COILS, true, len) if len >= 5 && len == 5 + pdu[4] as usize => {
+            address(&mut fields);
+            let count = word(pdu, 2);
+            fields.push(Field::new("count", Value::Unsigned(count as u64)));
+            let bits = unpack_bits(&pdu[5..], count as usize);
+            fields.push(Field::new("bits", Value::List(bits)));
+        }
+        (WRITE_MULTIPLE_REGISTERS, true, len) if len >= 5 && len == 5 + pdu[4] as usize => {
+            address(&mut fields);
+            fields.push(Field::new("count", Value::Unsigned(word(pdu, 2) as u64)));
+            let registers = unpack_registers(&pdu[5..]);
+            fields.push(Field::new("regs", Value::List(registers)));
+        }
+        _ => fields.push(Field::new("data", Value::Bytes(pdu.to_vec()))),
+    }
+    fields
+}
+
+/// Format fields as `name=value` pairs
+fn format_fields(fields: &[Field]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| format!("{}={}", field.name, field.value))
+        .collect();
+    fields.join(" ")
+}
+
+/// Parse a decimal or `0x` prefixed hex number
+fn parse_number<T: TryFrom<u64>>(s: &str) -> Result<T, String> {
+    let value = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    value
+        .ok()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| format!("Invalid number '{}'", s))
+}
+
+/// Parse a coil value
+fn parse_coil(s: &str) -> Result<bool, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "1" | "on" | "true" => Ok(true),
+        "0" | "off" | "false" => Ok(false),
+        _ => Err(format!("Invalid coil value '{}', expected on or off", s)),
+    }
+}
+
+/// Request sent by the master
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    /// Read coils, discrete inputs, holding registers, or input registers
+    Read {
+        /// Read function code
+        function: u8,
+        /// First address
+        address: u16,
+        /// Number of coils or registers
+        count: u16,
+    },
+    /// Switch a coil on or off
+    WriteCoil {
+        /// Coil address
+        address: u16,
+        /// Switch the coil on
+        on: bool,
+    },
+    /// Write a holding register
+    WriteRegister {
+        /// Register address
+        address: u16,
+        /// Value to write
+        value: u16,
+    },
+    /// Write a run of coils
+    WriteCoils {
+        /// First coil address
+        address: u16,
+        /// Coil states
+        values: Vec<bool>,
+    },
+    /// Write a run of holding registers
+    WriteRegisters {
+        /// First register address
+        address: u16,
+        /// Values to write
+        values: Vec<u16>,
+    },
+}
+
+/// Master command, a request to one slave
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    /// Slave address
+    pub unit: u8,
+    /// Request to send
+    pub request: Request,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    /// Parse `<unit> <function> <address> [values]`, e.g. `1 read-holding 0 10`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let (unit, function, address, values) = match words.as_slice() {
+            [unit, function, address, values @ ..] => (unit, function, address, values),
+            _ => {
+                return Err(
+                    "Expected <unit> <function> <address> [values], e.g. 1 read-holding 0 10"
+                        .to_string(),
+                )
+            }
+        };
+        let unit = parse_number(unit)?;
+        let address = parse_number(address)?;
+
+        let read = |function: u8, max: u16| -> Result<Request, String> {
+            let count = match values {
+                [] => 1,
+                [count] => parse_number(count)?,
+                _ => return Err("Reads take a single count".to_string()),
+            };
+            if count == 0 || count > max {
+                return Err(format!("Count must be 1 to {}", max));
+            }
+            Ok(Request::Read {
+                function,
+                address,
+                count,
+            })
+        };
+        let request = match function.to_ascii_lowercase().as_str() {
+            "read-coils" => read(READ_COILS, MAX_READ_COILS)?,
+            "read-discrete" => read(READ_DISCRETE_INPUTS, MAX_READ_COILS)?,
+            "read-holding" => read(READ_HOLDING_REGISTERS, MAX_READ_REGISTERS)?,
+            "read-input" => read(READ_INPUT_REGISTERS, MAX_READ_REGISTERS)?,
+            "write-coil" | "write-register" if values.len() != 1 => {
+                return Err(format!("{} takes a single value", function))
+            }
+            "write-coil" => Request::WriteCoil {
+                address,
+                on: parse_coil(values[0])?,
+            },
+            "write-register" => Request::WriteRegister {
+                address,
+                value: parse_number(values[0])?,
+            },
+            "write-coils" | "write-registers" if values.is_empty() => {
+                return Err(format!("{} needs at least one value", function))
+            }
+            "write-coils" if values.len() > MAX_WRITE_COILS => {
+                return Err(format!(
+                    "write-coils takes at most {} values",
+                    MAX_WRITE_COILS
+                ))
+            }
+            "write-registers" if values.len() > MAX_WRITE_REGISTERS => {
+                return Err(format!(
+                    "write-registers takes at most {} values",
+                    MAX_WRITE_REGISTERS
+                ))
+            }
+            "write-coils" => Request::WriteCoils {
+                address,
+                values: values
+                    .iter()
+                    .map(|v| parse_coil(v))
+                    .collect::<Result<_, _>>()?,
+            },
+            "write-registers" => Request::WriteRegisters {
+                address,
+                values: values
+                    .iter()
+                    .map(|v| parse_number(v))
+                    .collect::<Result<_, _>>()?,
+            },
+            _ => {
+                return Err(format!(
+                    "Unknown function '{}', expected read-coils, read-discrete, read-holding, \
+                     read-input, write-coil, write-register, write-coils, or write-registers",
+                    function
+                ))
+            }
+        };
+
+        Ok(Self { unit, request })
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unit {} {}", self.unit, function_name(self.function()))?;
+        match &self.request {
+            Request::Read { address, count, .. } => write!(f, " {} {}", address, count),
+            Request::WriteCoil { address, on } => {
+                write!(f, " {} {}", address, if *on { "on" } else { "off" })
+            }
+            Request::WriteRegister { address, value } => write!(f, " {} {}", address, value),
+            Request::WriteCoils { address, values } => {
+                let values: Vec<&str> = values
+                    .iter()
+                    .map(|on| if *on { "1" } else { "0" })
+                    .collect();
+                write!(f, " {} {}", address, values.join(","))
+            }
+            Request::WriteRegisters { address, values } => {
+                let values: Vec<String> = values.iter().map(u16::to_string).collect();
+                write!(f, " {} {}", address, values.join(","))
+            }
+        }
+    }
+}
+
+impl Command {
+    /// Get the function code
+    fn function(&self) -> u8 {
+        match &self.request {
+            Request::Read { function, .. } => *function,
+            Request::WriteCoil { .. } => WRITE_SINGLE_COIL,
+            Request::WriteRegister { .. } => WRITE_SINGLE_REGISTER,
+            Request::WriteCoils { .. } => WRITE_MULTIPLE_COILS,
+            Request::WriteRegisters { .. } => WRITE_MULTIPLE_REGISTERS,
+        }
+    }
+
+    /// Get the request frame, with its CRC
+    ///
+    /// Fails if a write has more values than fit in a frame
+    pub fn frame(&self) -> Result<Vec<u8>, String> {
+        let byte_count = |bytes: usize| {
+            u8::try_from(bytes)
+                .map_err(|_| format!("{} bytes of values don't fit in a frame", bytes))
+        };
+
+        let mut frame = vec![self.unit, self.function()];
+        match &self.request {
+            Request::Read { address, count, .. } => {
+                frame.extend_from_slice(&address.to_be_bytes());
+                frame.extend_from_slice(&count.to_be_bytes());
+            }
+            Request::WriteCoil { address, on } => {
+                frame.extend_from_slice(&address.to_be_bytes());
+                frame.extend_from_slice(if *on { &[0xff, 0x00] } else { &[0x00, 0x00] });
+            }
+            Request::WriteRegister { address, value } => {
+                frame.extend_from_slice(&address.to_be_bytes());
+                frame.extend_from_slice(&value.to_be_bytes());
+            }
+            Request::WriteCoils { address, values } => {
+                let mut packed = vec![0u8; values.len().div_ceil(8)];
+                for (i, _) in values.iter().enumerate().filter(|(_, on)| **on) {
+                    packed[i / 8] |= 1 << (i % 8);
+                }
+                frame.extend_from_slice(&address.to_be_bytes());
+                frame.extend_from_slice(&(values.len() as u16).to_be_bytes());
+                frame.push(byte_count(packed.len())?);
+                frame.extend(packed);
+            }
+            Request::WriteRegisters { address, values } => {
+                frame.extend_from_slice(&address.to_be_bytes());
+                frame.extend_from_slice(&(values.len() as u16).to_be_bytes());
+                frame.push(byte_count(values.len() * 2)?);
+                frame.extend(values.iter().flat_map(|value| value.to_be_bytes()));
+            }
+        }
+        Ok(with_crc(frame))
+    }
+
+    /// Check received data for a complete response
+    ///
+    /// Returns `None` until the whole response has been received. Data from other slaves is
+    /// skipped, so a shared bus can be used.
+    fn check_response(&self, received: &mut Vec<u8>) -> Option<Result<String, String>> {
+        loop {
+            // Skip anything that can't be the start of our response
+            let start = received
+                .windows(2)
+                .position(|w| w[0] == self.unit && w[1] & !EXCEPTION == self.function())?;
+            received.drain(..start);
+
+            let len = response_len(received)?;
+            if received.len() < len {
+                return None;
+            }
+            if !crc_ok(&received[..len]) {
+                received.drain(..1);
+                continue;
+            }
+
+            let frame: Vec<u8> = received.drain(..len).collect();
+            let mut fields = describe(&frame, false);
+            // Responses pad coils out to whole bytes, only show the ones asked for
+            if let Request::Read { count, .. } = self.request {
+                for field in &mut fields {
+                    if let (Value::List(bits), "bits") = (&mut field.value, field.name.as_str()) {
+                        bits.truncate(count as usize);
+                    }
+                }
+            }
+            return Some(match frame[1] & EXCEPTION {
+                0 => Ok(format_fields(&fields[2..])),
+                _ => Err(format_fields(&fields[2..])),
+            });
+        }
+    }
+
+    /// Send the command on a port and wait for the response
+    pub fn run(&self, serial_port: &mut dyn SerialPort) -> Result<String, String> {
+        serial_port
+            .write_all(&self.frame()?)
+            .map_err(|e| e.to_string())?;
+
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        let mut received = Vec::new();
+        let mut buffer = [0; MAX_FRAME];
+        while Instant::now() < deadline {
+            match serial_port.read(&mut buffer) {
+                Ok(count) => received.extend_from_slice(&buffer[..count]),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e.to_string()),
+            }
+            if let Some(result) = self.check_response(&mut received) {
+                return result;
+            }
+        }
+        Err("no response".to_string())
+    }
+}
+
+/// Modbus master command status
+#[derive(Debug, Clone)]
+pub enum ModbusStatus {
+    /// Waiting for the response to a command
+    Waiting(String),
+    /// Response received
+    Response(String, String),
+    /// Exception response, timeout, or cancelled
+    Failed(String, String),
+}
+
+impl Display for ModbusStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModbusStatus::Waiting(command) => write!(f, "Modbus: waiting for {}", command),
+            ModbusStatus::Response(command, response) => {
+                write!(f, "Modbus: {}: {}", command, response)
+            }
+            ModbusStatus::Failed(command, msg) => write!(f, "Modbus: {} failed: {}", command, msg),
+        }
+    }
+}
+
+/// Handle to a master command waiting for its response
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    /// Forwards received serial data to the transaction thread
+    pub serial_input_tx: Sender<Vec<u8>>,
+    /// Set to stop waiting
+    cancel: Arc<AtomicBool>,
+}
+
+impl Transaction {
+    /// Send a command and wait for its response in a new thread
+    pub fn start(
+        command: Command,
+        serial_send_tx: Sender<Vec<u8>>,
+        display_update_tx: Sender<DisplayUpdateEvent>,
+    ) -> Self {
+        let (serial_input_tx, serial_input_rx) = channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let thread_cancel = cancel.clone();
+
+        thread::spawn(move || {
+            let name = command.to_string();
+            display_update_tx
+                .send(DisplayUpdateEvent::Modbus(ModbusStatus::Waiting(
+                    name.clone(),
+                )))
+                .ok();
+            let status =
+                match wait_response(&command, serial_send_tx, serial_input_rx, thread_cancel) {
+                    Ok(response) => ModbusStatus::Response(name, response),
+                    Err(msg) => ModbusStatus::Failed(name, msg),
+                };
+            display_update_tx
+                .send(DisplayUpdateEvent::Modbus(status))
+                .ok();
+        });
+
+        Self {
+            serial_input_tx,
+            cancel,
+        }
+    }
+
+    /// Stop waiting for the response
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Send a command through the serial TX thread and wait for its response
+fn wait_response(
+    command: &Command,
+    serial_send_tx: Sender<Vec<u8>>,
+    serial_input_rx: Receiver<Vec<u8>>,
+    cancel: Arc<AtomicBool>,
+) -> Result<String, String> {
+    serial_send_tx
+        .send(command.frame()?)
+        .map_err(|_| "serial port closed".to_string())?;
+
+    let deadline = Instant::now() + RESPONSE_TIMEOUT;
+    let mut received = Vec::new();
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Err("cancelled".to_string());
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("no response".to_string());
+        }
+        match serial_input_rx.recv_timeout(remaining.min(Duration::from_millis(100))) {
+            Ok(data) => received.extend(data),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Err("serial port closed".to_string()),
+        }
+        if let Some(result) = command.check_response(&mut received) {
+            return result;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(command: &str) -> Vec<u8> {
+        command.parse::<Command>().unwrap().frame().unwrap()
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc16_modbus(b"123456789"), 0x4b37);
+        let frame = frame("1 read-holding 0 10");
+        assert_eq!(frame, [0x01, 0x03, 0x00, 0x00, 0x00, 0x0a, 0xc5, 0xcd]);
+        assert!(crc_ok(&frame));
+
+        let mut bad = frame.clone();
+        bad[5] ^= 0x01;
+        assert!(!crc_ok(&bad));
+        assert!(!crc_ok(&frame[..3]));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "0x11 write-register 1 0x0300".parse(),
+            Ok(Command {
+                unit: 0x11,
+                request: Request::WriteRegister {
+                    address: 1,
+                    value: 0x0300
+                },
+            })
+        );
+        assert_eq!(
+            frame("17 write-coils 19 1 0 1 1 0 0 1 1 1 0")[..9],
+            [0x11, 0x0f, 0x00, 0x13, 0x00, 0x0a, 0x02, 0xcd, 0x01]
+        );
+        assert_eq!(frame("1 write-coil 172 on")[2..6], [0x00, 0xac, 0xff, 0x00]);
+
+        for bad in [
+            "1 read-holding",
+            "1 read-holding 0 0",
+            "1 read-holding 0 1 2",
+            "1 write-coil 0 maybe",
+            "1 write-register 0 65536",
+            "1 write-registers 0",
+            "256 read-coils 0",
+            "1 read-everything 0",
+        ] {
+            assert!(bad.parse::<Command>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let values = |count: usize| vec!["1"; count].join(" ");
+        let command = |function: &str, values: &str| format!("1 {} 0 {}", function, values);
+
+        assert!(command("read-coils", "2000").parse::<Command>().is_ok());
+        assert!(command("read-coils", "2001").parse::<Command>().is_err());
+        assert!(command("read-input", "125").parse::<Command>().is_ok());
+        assert!(command("read-input", "126").parse::<Command>().is_err());
+
+        let coils = frame(&command("write-coils", &values(1968)));
+        assert_eq!(coils[6], 246);
+        assert!(command("write-coils", &values(1969))
+            .parse::<Command>()
+            .is_err());
+
+        let registers = frame(&command("write-registers", &values(123)));
+        assert_eq!(registers[6], 246);
+        assert!(command("write-registers", &values(124))
+            .parse::<Command>()
+            .is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::{Duration, Instant};
+
+use super::{crc_ok, describe, request_len, response_len, EXCEPTION, MAX_FRAME};
+use crate::decode::{Decoder, Field, Frame, Value};
+
+/// Gap in received data that ends a frame
+///
+/// RTU frames are separated by 3.5 character times of silence, under 4 ms at 9600 baud. Data is
+/// only timestamped as it's read, so a longer gap is used and frames are mostly found by their
+/// length and CRC instead.
+const SILENCE: Duration = Duration::from_millis(20);
+
+/// Shortest RTU frame, address, function, and CRC
+const MIN_FRAME: usize = 4;
+
+/// Modbus RTU bus monitor
+///
+/// Requests and responses have different layouts for the same function code, so the frame after a
+/// request to a unit is taken to be its response.
+#[derive(Debug, Clone, Default)]
+pub struct ModbusDecoder {
+    /// Bytes received and not yet decoded
+    buffer: Vec<u8>,
+    /// Bytes skipped looking for a valid frame
+    skipped: Vec<u8>,
+    /// When data was last received
+    last_rx: Option<Instant>,
+    /// Unit and function of the last request, waiting for its response
+    pending: Option<(u8, u8)>,
+}
+
+impl ModbusDecoder {
+    /// Take the next frame from the start of the buffer if it's all been received
+    fn next_frame(&mut self) -> Option<Frame> {
+        if self.buffer.len() < MIN_FRAME {
+            return None;
+        }
+        let (unit, function) = (self.buffer[0], self.buffer[1]);
+
+        // Try the layout the bus is expecting first, exceptions are always responses
+        let response =
+            function & EXCEPTION != 0 || self.pending == Some((unit, function & !EXCEPTION));
+        let mut candidates = [
+            (response_len(&self.buffer), false),
+            (request_len(&self.buffer), true),
+        ];
+        if !response {
+            candidates.reverse();
+        }
+
+        let mut waiting = false;
+        for (len, request) in candidates {
+            match len {
+                Some(len) if len > MAX_FRAME => {}
+                Some(len) if len > self.buffer.len() => waiting = true,
+                Some(len) if crc_ok(&self.buffer[..len]) => return Some(self.take(len, request)),
+                Some(_) => {}
+                // Unknown functions are found by their CRC alone
+                None => {
+                    let max = self.buffer.len().min(MAX_FRAME);
+                    if let Some(len) = (MIN_FRAME..=max).find(|len| crc_ok(&self.buffer[..*len])) {
+                        return Some(self.take(len, !response));
+                    }
+                }
+            }
+        }
+
+        // Skip a byte if the start of the buffer can't be a frame
+        if !waiting && self.buffer.len() >= MAX_FRAME {
+            self.skipped.push(self.buffer.remove(0));
+        }
+        None
+    }
+
+    /// Take a frame with a valid CRC from the start of the buffer
+    fn take(&mut self, len: usize, request: bool) -> Frame {
+        let raw: Vec<u8> = self.buffer.drain(..len).collect();
+        self.pending = if request {
+            Some((raw[0], raw[1]))
+        } else {
+            None
+        };
+
+        let kind = if request { "request" } else { "response" };
+        let mut fields = vec![Field::new("type", Value::Text(kind.to_string()))];
+        fields.extend(describe(&raw, request));
+        Frame {
+            fields,
+            raw,
+            error: None,
+        }
+    }
+
+    /// Report skipped bytes as a bad frame
+    fn take_skipped(&mut self) -> Option<Frame> {
+        if self.skipped.is_empty() {
+            return None;
+        }
+        let raw = std::mem::take(&mut self.skipped);
+        Some(Frame::error(raw, "bad CRC or incomplete frame".to_string()))
+    }
+
+    /// End the current frame, skipping bytes until the rest of the buffer decodes
+    fn end_frame(&mut self) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        while !self.buffer.is_empty() {
+            match self.next_frame() {
+                Some(frame) => {
+                    frames.extend(self.take_skipped());
+                    frames.push(frame);
+                }
+                None => self.skipped.push(self.buffer.remove(0)),
+            }
+        }
+        frames.extend(self.take_skipped());
+        frames
+    }
+}
+
+impl Decoder for ModbusDecoder {
+    fn decode(&mut self, data: &[u8]) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let now = Instant::now();
+        if self
+            .last_rx
+            .is_some_and(|last_rx| now.duration_since(last_rx) > SILENCE)
+        {
+            frames.extend(self.end_frame());
+        }
+        self.last_rx = Some(now);
+        self.buffer.extend_from_slice(data);
+
+        while self.buffer.len() >= MIN_FRAME {
+            let skipped = self.skipped.len();
+            match self.next_frame() {
+                Some(frame) => {
+                    frames.extend(self.take_skipped());
+                    frames.push(frame);
+                }
+                None if self.skipped.len() > skipped => {}
+                None => break,
+            }
+        }
+        frames
+    }
+
+    fn box_clone(&self) -> Box<dyn Decoder> {
+        Box::new(self.clone())
+    }
+
+    fn flush(&mut self) -> Vec<Frame> {
+        match self.last_rx {
+            Some(last_rx) if last_rx.elapsed() > SILENCE => self.end_frame(),
+            _ => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use crate::checksum::{fletcher8, xor8};
+use crate::macros::parse_hex_bytes;
+
+/// Longest sentence kept, NMEA allows 82 characters but some receivers send longer ones
+const MAX_LINE: usize = 256;
+
+/// Sync characters every UBX frame starts with
+const UBX_SYNC: [u8; 2] = [0xb5, 0x62];
+
+/// Satellite in view, from GSV
+#[derive(Debug, Clone, PartialEq)]
+struct Satellite {
+    /// Satellite ID
+    prn: u16,
+    /// Signal to noise ratio in dB-Hz, if it's being tracked
+    snr: Option<u8>,
+}
+
+/// Receiver state decoded from GGA, RMC, and GSA sentences
+#[derive(Debug, Clone, Default)]
+struct Fix {
+    /// UTC time, `hh:mm:ss`
+    time: Option<String>,
+    /// UTC date, `yyyy-mm-dd`
+    date: Option<String>,
+    /// Latitude in degrees, negative is south
+    latitude: Option<f64>,
+    /// Longitude in degrees, negative is west
+    longitude: Option<f64>,
+    /// Altitude above mean sea level in metres
+    altitude: Option<f64>,
+    /// GGA fix quality, 0 is no fix
+    quality: Option<u8>,
+    /// GSA fix type, 1 no fix, 2 2D, or 3 3D
+    fix_type: Option<u8>,
+    /// RMC status is valid
+    valid: Option<bool>,
+    /// Satellites used, from GGA
+    used: Option<u32>,
+    /// Speed over ground in knots
+    speed: Option<f64>,
+    /// Course over ground in degrees
+    course: Option<f64>,
+    /// Position dilution of precision
+    pdop: Option<f64>,
+    /// Horizontal dilution of precision
+    hdop: Option<f64>,
+    /// Vertical dilution of precision
+    vdop: Option<f64>,
+}
+
+impl Fix {
+    /// Describe the fix, e.g. `3D fix (DGPS)`
+    fn describe(&self) -> String {
+        // GGA has the fix quality, fall back to the RMC status
+        let fixed = match (self.quality, self.valid) {
+            (Some(quality), _) => quality > 0,
+            (None, Some(valid)) => valid,
+            (None, None) => false,
+        };
+        if !fixed || self.fix_type == Some(1) {
+            return "no fix".to_string();
+        }
+
+        let kind = match self.fix_type {
+            Some(2) => "2D fix",
+            Some(3) => "3D fix",
+            _ => "fix",
+        };
+        let quality = match self.quality {
+            Some(1) => Some("GPS"),
+            Some(2) => Some("DGPS"),
+            Some(4) => Some("RTK fixed"),
+            Some(5) => Some("RTK float"),
+            Some(6) => Some("dead reckoning"),
+            _ => None,
+        };
+        match quality {
+            Some(quality) => format!("{} ({})", kind, quality),
+            None => kind.to_string(),
+        }
+    }
+}
+
+/// Checks NMEA 0183 sentences and decodes GGA, RMC, GSA, and GSV into the receiver state
+#[derive(Debug, Clone, Default)]
+pub struct NmeaSession {
+    /// Current received line
+    line: Vec<u8>,
+    /// Receiver state
+    fix: Fix,
+    /// Satellites in view by talker, from complete GSV groups
+    in_view: BTreeMap<String, Vec<Satellite>>,
+    /// GSV group being received by talker
+    gsv: BTreeMap<String, Vec<Satellite>>,
+    /// Satellites used in the fix by talker and system, from GSA
+    used: BTreeMap<String, Vec<u16>>,
+    /// Sentences with a good checksum
+    good: u64,
+    /// Sentences with a bad or missing checksum
+    bad: u64,
+    /// Last bad sentence or command that couldn't be sent
+    last_error: Option<String>,
+}
+
+impl NmeaSession {
+    /// Process received data, returning bad sentences with the offset just past the line they end
+    /// on
+    pub fn process(&mut self, data: &[u8]) -> Vec<(usize, String)> {
+        let mut events = Vec::new();
+        for (i, &byte) in data.iter().enumerate() {
+            match byte {
+                b'\n' => {
+                    let line = String::from_utf8_lossy(&self.line).trim().to_string();
+                    self.line.clear();
+                    if let Err(msg) = self.process_line(&line) {
+                        self.bad += 1;
+                        self.last_error = Some(msg.clone());
+                        events.push((i + 1, msg));
+                    }
+                }
+                _ if self.line.len() < MAX_LINE => self.line.push(byte),
+                _ => {}
+            }
+        }
+        events
+    }
+
+    /// Check a received line and decode it if it's a sentence
+    fn process_line(&mut self, line: &str) -> Result<(), String> {
+        // Other lines, like log messages or binary UBX frames, aren't sentences
+        let sentence = match line.strip_prefix(['$', '!']) {
+            Some(sentence) => sentence,
+            None => return Ok(()),
+        };
+
+        let (body, checksum) = sentence
+            .rsplit_once('*')
+            .ok_or_else(|| format!("missing checksum: {}", line))?;
+        let expected = xor8(body.as_bytes());
+        match u8::from_str_radix(checksum, 16) {
+            Ok(checksum) if checksum == expected => {}
+            _ => {
+                return Err(format!(
+                    "bad checksum {}, expected {:02X}: {}",
+                    checksum, expected, line
+                ))
+            }
+        }
+        self.good += 1;
+
+        let fields: Vec<&str> = body.split(',').collect();
+        let address = fields[0];
+        // Proprietary sentences start with `P` and have no talker
+        if address.starts_with('P') || address.len() != 5 || !address.is_ascii() {
+            return Ok(());
+        }
+        let (talker, kind) = address.split_at(2);
+        match kind {
+            "GGA" => self.gga(&fields),
+            "RMC" => self.rmc(&fields),
+            "GSA" => self.gsa(talker, &fields),
+            "GSV" => self.gsv(talker, &fields),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Decode a GGA sentence, time, position, fix quality, and altitude
+    fn gga(&mut self, fields: &[&str]) {
+        let fix = &mut self.fix;
+        fix.time = time(field_str(fields, 1)).or(fix.time.take());
+        fix.latitude = coordinate(field_str(fields, 2), field_str(fields, 3));
+        fix.longitude = coordinate(field_str(fields, 4), field_str(fields, 5));
+        fix.quality = field(fields, 6);
+        fix.used = field(fields, 7);
+        fix.hdop = field(fields, 8);
+        fix.altitude = field(fields, 9);
+    }
+
+    /// Decode an RMC sentence, time, date, position, speed, and course
+    fn rmc(&mut self, fields: &[&str]) {
+        let fix = &mut self.fix;
+        fix.time = time(field_str(fields, 1)).or(fix.time.take());
+        fix.valid = Some(field_str(fields, 2) == "A");
+        fix.latitude = coordinate(field_str(fields, 3), field_str(fields, 4));
+        fix.longitude = coordinate(field_str(fields, 5), field_str(fields, 6));
+        fix.speed = field(fields, 7);
+        fix.course = field(fields, 8);
+        fix.date = date(field_str(fields, 9)).or(fix.date.take());
+    }
+
+    /// Decode a GSA sentence, fix type, satellites used, and dilution of precision
+    ///
+    /// Multi-GNSS receivers send one per system, told apart by the system ID added in NMEA 4.10
+    fn gsa(&mut self, talker: &str, fields: &[&str]) {
+        let fix = &mut self.fix;
+        fix.fix_type = field(fields, 2);
+        fix.pdop = field(fields, 15);
+        fix.hdop = field(fields, 16);
+        fix.vdop = field(fields, 17);
+
+        let system = format!("{}{}", talker, field_str(fields, 18));
+        let prns = (3..15).filter_map(|i| field(fields, i)).collect();
+        self.used.insert(system, prns);
+    }
+
+    /// Decode a GSV sentence, part of a group listing up to four satellites in view each
+    fn gsv(&mut self, talker: &str, fields: &[&str]) {
+        let (total, number): (u8, u8) = match (field(fields, 1), field(fields, 2)) {
+            (Some(total), Some(number)) => (total, number),
+            _ => return,
+        };
+
+        let group = self.gsv.entry(talker.to_string()).or_default();
+        if number == 1 {
+            group.clear();
+        }
+        // Satellites are PRN, elevation, azimuth, SNR, a signal ID may follow the last one
+        for satellite in fields.get(4..).unwrap_or_default().chunks_exact(4) {
+            if let Some(prn) = field(satellite, 0) {
+                group.push(Satellite {
+                    prn,
+                    snr: field(satellite, 3),
+                });
+            }
+        }
+        if number == total {
+            let group = self.gsv.remove(talker).unwrap_or_default();
+            self.in_view.insert(talker.to_string(), group);
+        }
+    }
+
+    /// Record a command that couldn't be sent
+    pub fn set_error(&mut self, msg: String) {
+        self.last_error = Some(msg);
+    }
+
+    /// Get the status panel lines, fix and position, time and satellites, then signal levels
+    pub fn status_lines(&self) -> Vec<String> {
+        let fix = &self.fix;
+        let mut position = format!("GPS: {}", fix.describe());
+        if let (Some(latitude), Some(longitude)) = (fix.latitude, fix.longitude) {
+            position.push_str(&format!(
+                " | {:.6} {} {:.6} {}",
+                latitude.abs(),
+                if latitude < 0.0 { 'S' } else { 'N' },
+                longitude.abs(),
+                if longitude < 0.0 { 'W' } else { 'E' }
+            ));
+        }
+        if let Some(altitude) = fix.altitude {
+            position.push_str(&format!(" | Alt {:.1} m", altitude));
+        }
+        if let Some(speed) = fix.speed {
+            position.push_str(&format!(" | {:.1} kn", speed));
+            if let Some(course) = fix.course {
+                position.push_str(&format!(" {:.1} deg", course));
+            }
+        }
+
+        let in_view: usize = self.in_view.values().map(Vec::len).sum();
+        let mut time = format!(
+            "Time: {} {} UTC | Satellites: {} used, {} in view",
+            fix.date.as_deref().unwrap_or("----------"),
+            fix.time.as_deref().unwrap_or("--:--:--"),
+            fix.used
+                .map_or_else(|| "-".to_string(), |used| used.to_string()),
+            in_view
+        );
+        let dops = [("P", fix.pdop), ("H", fix.hdop), ("V", fix.vdop)];
+        if dops.iter().any(|(_, dop)| dop.is_some()) {
+            time.push_str(" | DOP");
+            for (name, dop) in dops.iter() {
+                if let Some(dop) = dop {
+                    time.push_str(&format!(" {} {:.1}", name, dop));
+                }
+            }
+        }
+
+        let mut sentences = format!("Sentences: {} ok, {} bad", self.good, self.bad);
+        if let Some(error) = &self.last_error {
+            sentences.push_str(&format!(" | Last error: {}", error));
+        }
+
+        // Signal levels by talker, satellites used in the fix are marked with `*`
+        let mut signals = String::from("SNR:");
+        for (talker, satellites) in &self.in_view {
+            signals.push_str(&format!(" {}", talker));
+            for satellite in satellites {
+                let used = self.used.values().any(|prns| prns.contains(&satellite.prn));
+                signals.push_str(&format!(
+                    " {}:{}{}",
+                    satellite.prn,
+                    satellite
+                        .snr
+                        .map_or_else(|| "-".to_string(), |snr| snr.to_string()),
+                    if used { "*" } else { "" }
+                ));
+            }
+        }
+
+        vec![position, time, sentences, signals]
+    }
+}
+
+/// Get a field, or an empty string if the sentence is too short
+fn field_str<'a>(fields: &[&'a str], i: usize) -> &'a str {
+    fields.get(i).copied().unwrap_or_default()
+}
+
+/// Parse a field, `None` if it's empty or invalid
+fn field<T: FromStr>(fields: &[&str], i: usize) -> Option<T> {
+    field_str(fields, i).parse().ok()
+}
+
+/// Convert `ddmm.mmmm` and a hemisphere to degrees
+fn coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let value: f64 = value.parse().ok()?;
+    let degrees = (value / 100.0).trunc();
+    let degrees = degrees + (value - degrees * 100.0) / 60.0;
+    match hemisphere {
+        "N" | "E" => Some(degrees),
+        "S" | "W" => Some(-degrees),
+        _ => None,
+    }
+}
+
+/// Convert `hhmmss.ss` to `hh:mm:ss`
+fn time(value: &str) -> Option<String> {
+    let digits = value
+        .get(..6)
+        .filter(|d| d.bytes().all(|b| b.is_ascii_digit()))?;
+    Some(format!(
+        "{}:{}:{}",
+        &digits[..2],
+        &digits[2..4],
+        &digits[4..]
+    ))
+}
+
+/// Convert `ddmmyy` to `yyyy-mm-dd`
+fn date(value: &str) -> Option<String> {
+    let digits = value
+        .get(..6)
+        .filter(|d| d.bytes().all(|b| b.is_ascii_digit()))?;
+    Some(format!(
+        "20{}-{}-{}",
+        &digits[4..],
+        &digits[2..4],
+        &digits[..2]
+    ))
+}
+
+/// Build a command to send to a receiver
+///
+/// `ubx <class> <id> [payload]` in hex builds a UBX frame with its header, length, and checksum.
+/// Anything else is a sentence, with or without its `$` and checksum, e.g. `PMTK220,1000`, and is
+/// sent with its checksum and `\r\n`.
+pub fn command(line: &str) -> Result<Vec<u8>, String> {
+    let line = line.trim();
+    let mut words = line.split_whitespace();
+    if words
+        .next()
+        .is_some_and(|word| word.eq_ignore_ascii_case("ubx"))
+    {
+        let message = parse_hex_bytes(&words.collect::<String>())?;
+        if message.len() < 2 {
+            return Err("UBX commands need a class and ID, e.g. ubx 06 08 ...".to_string());
+        }
+        let payload = &message[2..];
+        let length =
+            u16::try_from(payload.len()).map_err(|_| "UBX payload is too long".to_string())?;
+
+        let mut frame = UBX_SYNC.to_vec();
+        frame.extend_from_slice(&message[..2]);
+        frame.extend_from_slice(&length.to_le_bytes());
+        frame.extend_from_slice(payload);
+        let (ck_a, ck_b) = fletcher8(&frame[2..]);
+        frame.extend_from_slice(&[ck_a, ck_b]);
+        return Ok(frame);
+    }
+
+    // Keep AIS style `!` sentences, anything else starts with `$`
+    let (start, sentence) = match line.strip_prefix('!') {
+        Some(sentence) => ('!', sentence),
+        None => ('$', line.trim_start_matches('$')),
+    };
+    // Any checksum typed in is replaced
+    let body = sentence.split('*').next().unwrap_or_default();
+    if body.is_empty() {
+        return Err(
+            "Enter a sentence, e.g. PMTK220,1000, or ubx <class> <id> [payload]".to_string(),
+        );
+    }
+    Ok(format!("{}{}*{:02X}\r\n", start, body, xor8(body.as_bytes())).into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GGA: &str = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+
+    /// Process lines, returning the errors
+    fn process(session: &mut NmeaSession, lines: &[&str]) -> Vec<String> {
+        let data: String = lines.iter().map(|line| format!("{}\r\n", line)).collect();
+        session
+            .process(data.as_bytes())
+            .into_iter()
+            .map(|(_, msg)| msg)
+            .collect()
+    }
+
+    #[test]
+    fn checksums() {
+        let mut session = NmeaSession::default();
+        assert!(process(&mut session, &[GGA]).is_empty());
+        assert_eq!(session.fix.describe(), "fix (GPS)");
+
+        let errors = process(
+            &mut session,
+            &[
+                &GGA.replace("*47", "*48"),
+                "$GPGGA,123519,4807.038,N",
+                "$GPGGA,123519*ZZ",
+            ],
+        );
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("bad checksum 48, expected 47"));
+        assert!(errors[1].starts_with("missing checksum"));
+        assert_eq!((session.good, session.bad), (1, 3));
+    }
+
+    #[test]
+    fn only_lines_starting_a_sentence() {
+        let mut session = NmeaSession::default();
+        let log = format!("log: {}", GGA.replace("*47", "*00"));
+        assert!(process(&mut session, &["", "hello $ world", &log]).is_empty());
+        assert_eq!((session.good, session.bad), (0, 0));
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(command("PMTK220,1000").unwrap(), b"$PMTK220,1000*1F\r\n");
+        assert_eq!(
+            command("$PMTK220,1000*00").unwrap(),
+            b"$PMTK220,1000*1F\r\n"
+        );
+
+        // Commands are checked the same way as received sentences
+        let ais = command("!AIVDM,1,1,,A,13aEOK?P00PD2wVMdLDRhgvL289?,0").unwrap();
+        let ais = String::from_utf8(ais).unwrap();
+        assert!(ais.starts_with('!'));
+        let mut session = NmeaSession::default();
+        assert!(process(&mut session, &[ais.trim()]).is_empty());
+        assert_eq!(session.good, 1);
+
+        assert_eq!(
+            command("ubx 06 08").unwrap(),
+            [0xb5, 0x62, 0x06, 0x08, 0x00, 0x00, 0x0e, 0x30]
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::bytes::Regex;
+
+use crate::app::error::Result;
+use crate::baud::{AutoBaud, AutoBaudStatus};
+use crate::checksum::TxChecksum;
+use crate::decode::FrameView;
+use crate::script::{Script, ScriptStatus};
+use crate::send::{FileSend, SendStatus};
+use crate::serial::LinkCounters;
+use crate::trigger::Triggers;
+use crate::ui::DisplayUpdateEvent;
+
+/// Number of received bytes kept for matching the exit pattern
+const MATCH_WINDOW_SIZE: usize = 4096;
+
+/// How long the port must be quiet after stdin closes before pipe mode exits, so replies to the
+/// last data sent are still written out
+const LINGER: Duration = Duration::from_millis(500);
+
+/// Exit code when the exit pattern was matched or pipe mode ended normally
+pub const EXIT_SUCCESS: i32 = 0;
+/// Exit code when the exit timeout elapsed before the exit pattern was matched
+pub const EXIT_TIMEOUT: i32 = 124;
+/// Exit code when a script failed
+pub const EXIT_SCRIPT_FAILED: i32 = 1;
+
+/// Pipe mode output and exit conditions
+#[derive(Debug, Default)]
+pub struct PipeSettings {
+    /// Exit once received data matches this pattern
+    pub until: Option<Regex>,
+    /// Exit after this much time has elapsed
+    pub timeout: Option<Duration>,
+    /// Write decoded frames, one per line, in place of the received data
+    pub frame_view: Option<FrameView>,
+    /// Append received data to this file
+    pub log_file: Option<File>,
+    /// Checksum appended to hex trigger responses without their own
+    pub tx_checksum: Option<TxChecksum>,
+}
+
+/// Stdin Thread
+///
+/// Streams stdin to the serial port until stdin is closed, then waits for it all to be written
+/// and sends `InputClosed` to the pipe loop. Stdin that closes without any data, like
+/// `/dev/null`, isn't input to wait for replies to, so nothing is sent and pipe mode keeps
+/// running.
+pub fn stdin_thread(
+    serial_send_tx: Sender<Vec<u8>>,
+    display_update_tx: Sender<DisplayUpdateEvent>,
+    counters: Arc<LinkCounters>,
+) {
+    let mut stdin = io::stdin();
+    let mut buffer = vec![0; 128];
+    let sent_before = counters.tx.load(Ordering::Relaxed);
+    let mut queued = 0;
+
+    loop {
+        match stdin.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => {
+                if serial_send_tx.send(buffer[..count].to_vec()).is_err() {
+                    return;
+                }
+                queued += count as u64;
+            }
+        }
+    }
+
+    if queued == 0 {
+        return;
+    }
+
+    // An empty send fails once the TX thread has stopped, so don't wait on it then
+    while counters.tx.load(Ordering::Relaxed) < sent_before + queued {
+        if serial_send_tx.send(Vec::new()).is_err() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    display_update_tx.send(DisplayUpdateEvent::InputClosed).ok();
+}
+
+/// Write out any frame still waiting for a gap in the data to end it
+fn flush_frames(frame_view: &mut Option<FrameView>, stdout: &mut io::Stdout) -> Result<()> {
+    if let Some(frame_view) = frame_view {
+        for line in frame_view.flush() {
+            writeln!(stdout, "{}", line)?;
+        }
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+/// Non-interactive display loop used when stdout is not a terminal
+///
+/// Serial data is written to stdout as is, or as decoded frames, with no raw mode or menu bar.
+/// Script output goes to stderr and pipe mode ends when the script does. The rate auto baud
+/// detection locks onto is reported on stderr. Trigger rule responses are sent with
+/// `serial_send_tx`. Returns the process exit code.
+pub fn pipe_loop(
+    pipe_settings: PipeSettings,
+    display_update_rx: Receiver<DisplayUpdateEvent>,
+    mut file_send: Option<FileSend>,
+    script: Option<Script>,
+    mut auto_baud: Option<AutoBaud>,
+    mut triggers: Triggers,
+    serial_send_tx: Sender<Vec<u8>>,
+) -> Result<i32> {
+    let mut frame_view = pipe_settings.frame_view;
+    let mut log_file = pipe_settings.log_file;
+    let mut stdout = io::stdout();
+    let deadline = pipe_settings.timeout.map(|t| Instant::now() + t);
+    let mut match_window: Vec<u8> = Vec::new();
+    // Set once stdin has closed and nothing else decides when to exit
+    let mut linger: Option<Instant> = None;
+
+    loop {
+        // Wait for the next event, or until the timeout or linger elapses
+        let wake = match (deadline, linger) {
+            (Some(deadline), Some(linger)) => Some(deadline.min(linger)),
+            (deadline, linger) => deadline.or(linger),
+        };
+        let event = match wake {
+            Some(wake) => {
+                let remaining = wake.saturating_duration_since(Instant::now());
+                match display_update_rx.recv_timeout(remaining) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => {
+                        flush_frames(&mut frame_view, &mut stdout)?;
+                        // Timing out is only a failure if we were waiting for a pattern
+                        return Ok(if pipe_settings.until.is_some() && linger.is_none() {
+                            EXIT_TIMEOUT
+                        } else {
+                            EXIT_SUCCESS
+                        });
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        flush_frames(&mut frame_view, &mut stdout)?;
+                        return Ok(EXIT_SUCCESS);
+                    }
+                }
+            }
+            None => match display_update_rx.recv() {
+                Ok(event) => event,
+                Err(_) => {
+                    flush_frames(&mut frame_view, &mut stdout)?;
+                    return Ok(EXIT_SUCCESS);
+                }
+            },
+        };
+
+        match event {
+            DisplayUpdateEvent::SerialInput(data) => {
+                if let Some(log_file) = &mut log_file {
+                    log_file.write_all(&data)?;
+                }
+
+                match &mut frame_view {
+                    Some(frame_view) => {
+                        for line in frame_view.lines(&data) {
+                            writeln!(stdout, "{}", line)?;
+                        }
+                    }
+                    None => stdout.write_all(data.as_slice())?,
+                }
+                stdout.flush()?;
+
+                // Keep waiting while replies are still arriving
+                if let Some(linger) = &mut linger {
+                    *linger = Instant::now() + LINGER;
+                }
+
+                if let Some(file_send) = &file_send {
+                    file_send.serial_input_tx.send(data.clone()).ok();
+                }
+
+                triggers.process(&data, &serial_send_tx, pipe_settings.tx_checksum.as_ref());
+
+                if let Some(script) = &script {
+                    script.serial_input_tx.send(data.clone()).ok();
+                }
+
+                if let Some(auto_baud) = &auto_baud {
+                    auto_baud.serial_input_tx.send(data.clone()).ok();
+                }
+
+                if let Some(until) = &pipe_settings.until {
+                    match_window.extend_from_slice(&data);
+                    if until.is_match(&match_window) {
+                        return Ok(EXIT_SUCCESS);
+                    }
+
+                    // Only keep the end of the data so patterns split across reads still match
+                    if match_window.len() > MATCH_WINDOW_SIZE {
+                        match_window.drain(..match_window.len() - MATCH_WINDOW_SIZE);
+                    }
+                }
+            }
+            // Exit once the file is sent and replies stop, unless waiting for a pattern or the exit
+            // timeout
+            DisplayUpdateEvent::FileSend(SendStatus::Complete) => {
+                if pipe_settings.until.is_none() && deadline.is_none() {
+                    linger = Some(Instant::now() + LINGER);
+                }
+                file_send = None;
+            }
+            // Report file send failures
+            DisplayUpdateEvent::FileSend(SendStatus::Failed(msg)) => {
+                eprintln!("Send failed: {}", msg);
+                file_send = None;
+            }
+            // Report script output, and exit when the script finishes
+            DisplayUpdateEvent::Script(ScriptStatus::Log(msg)) => eprintln!("{}", msg),
+            DisplayUpdateEvent::Script(ScriptStatus::Complete) => return Ok(EXIT_SUCCESS),
+            DisplayUpdateEvent::Script(status) => {
+                eprintln!("{}", status);
+                return Ok(EXIT_SCRIPT_FAILED);
+            }
+            // Report the detected baud rate
+            DisplayUpdateEvent::AutoBaud(AutoBaudStatus::Trying(_)) => {}
+            DisplayUpdateEvent::AutoBaud(status) => {
+                eprintln!("{}", status);
+                auto_baud = None;
+            }
+            // Fail on serial port errors
+            DisplayUpdateEvent::SerialError(err) => return Err(err.into()),
+            // Pass through the exit code of an exec child process
+            DisplayUpdateEvent::ProcessExit(status) => return Ok(status.code().unwrap_or(1)),
+            // Finish once replies stop, unless something else decides when to exit
+            DisplayUpdateEvent::InputClosed
+                if pipe_settings.until.is_none()
+                    && deadline.is_none()
+                    && file_send.is_none()
+                    && script.is_none()
+                    && auto_baud.is_none() =>
+            {
+                linger = Some(Instant::now() + LINGER);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+
+use crossterm::style::{Color, ResetColor, SetForegroundColor};
+
+/// Longest line parsed, longer lines are cut
+const MAX_LINE: usize = 1024;
+
+/// Samples kept per series, enough to fill a wide terminal with braille dots
+const MAX_SAMPLES: usize = 2048;
+
+/// Colors series are drawn in, series after the last one aren't plotted
+const COLORS: &[Color] = &[
+    Color::Cyan,
+    Color::Yellow,
+    Color::Magenta,
+    Color::Green,
+    Color::Red,
+    Color::Blue,
+    Color::White,
+    Color::DarkYellow,
+];
+
+/// Parse numeric fields from a line of text
+///
+/// Fields are separated by commas, semicolons, tabs, or spaces, like the Arduino Serial Plotter.
+/// Labeled fields are `name:value` or `name=value`, unlabeled ones are named by their position
+/// among the unlabeled fields, starting at `1`. Fields that aren't numbers are skipped.
+pub fn parse_fields(line: &str) -> Vec<(String, f64)> {
+    // Join labels to their values so `temp: 23.4` is one field
+    let mut joined = String::with_capacity(line.len());
+    let mut chars = line.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ':' || c == '=' {
+            while joined.ends_with(' ') {
+                joined.pop();
+            }
+            joined.push(c);
+            while chars.next_if_eq(&' ').is_some() {}
+        } else {
+            joined.push(c);
+        }
+    }
+
+    let mut fields = Vec::new();
+    let mut unlabeled = 0;
+    for token in joined
+        .split([',', ';', '\t', ' '])
+        .filter(|token| !token.is_empty())
+    {
+        match token.split_once([':', '=']) {
+            Some((name, value)) => {
+                if let Ok(value) = value.parse::<f64>() {
+                    if !name.is_empty() && value.is_finite() {
+                        fields.push((name.to_string(), value));
+                    }
+                }
+            }
+            None => {
+                if let Ok(value) = token.parse::<f64>() {
+                    unlabeled += 1;
+                    if value.is_finite() {
+                        fields.push((unlabeled.to_string(), value));
+                    }
+                }
+            }
+        }
+    }
+    fields
+}
+
+/// How the plot is drawn
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PlotStyle {
+    /// Braille characters, 2x4 dots per cell
+    Braille,
+    /// Half blocks, 1x2 dots per cell, for fonts without braille
+    Block,
+}
+
+impl PlotStyle {
+    /// Dots per cell across and down
+    fn dots(&self) -> (usize, usize) {
+        match self {
+            PlotStyle::Braille => (2, 4),
+            PlotStyle::Block => (1, 2),
+        }
+    }
+
+    /// Get the bit of a dot in a cell
+    fn bit(&self, x: usize, y: usize) -> u8 {
+        match self {
+            PlotStyle::Braille => [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]][y][x],
+            PlotStyle::Block => [0x01, 0x02][y],
+        }
+    }
+
+    /// Get the character for a cell's dots
+    fn glyph(&self, bits: u8) -> char {
+        match self {
+            PlotStyle::Braille => std::char::from_u32(0x2800 + bits as u32).unwrap_or(' '),
+            PlotStyle::Block => match bits {
+                0x01 => '▀',
+                0x02 => '▄',
+                0x03 => '█',
+                _ => ' ',
+            },
+        }
+    }
+}
+
+impl Display for PlotStyle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlotStyle::Braille => write!(f, "braille"),
+            PlotStyle::Block => write!(f, "block"),
+        }
+    }
+}
+
+/// Named series of samples
+#[derive(Debug, Clone)]
+struct Series {
+    /// Field name
+    name: String,
+    /// Samples, `None` where a line didn't have this field
+    values: VecDeque<Option<f64>>,
+}
+
+/// Collects numeric fields from received lines and draws them as scrolling charts
+#[derive(Debug, Clone)]
+pub struct Plotter {
+    /// How the plot is drawn
+    pub style: PlotStyle,
+    /// Current received line
+    line: Vec<u8>,
+    /// Series in the order they were first seen
+    series: Vec<Series>,
+    /// Lines with numeric fields so far
+    samples: u64,
+    /// New samples arrived since the plot was last drawn
+    pub dirty: bool,
+}
+
+impl Plotter {
+    /// Create an empty plotter
+    pub fn new(style: PlotStyle) -> Self {
+        Self {
+            style,
+            line: Vec::new(),
+            series: Vec::new(),
+            samples: 0,
+            dirty: true,
+        }
+    }
+
+    /// Process received data, each line with numeric fields adds a sample to every series
+    pub fn process(&mut self, data: &[u8]) {
+        for &byte in data {
+            match byte {
+                b'\n' => {
+                    let line = String::from_utf8_lossy(&self.line).to_string();
+                    self.line.clear();
+                    self.add_line(&line);
+                }
+                _ if self.line.len() < MAX_LINE => self.line.push(byte),
+                _ => {}
+            }
+        }
+    }
+
+    /// Add the fields of a line as one sample
+    fn add_line(&mut self, line: &str) {
+        let fields = parse_fields(line);
+        if fields.is_empty() {
+            return;
+        }
+
+        // New series start out empty for the samples before them
+        let len = self.series.first().map_or(0, |series| series.values.len());
+        for (name, _) in &fields {
+            if self.series.len() < COLORS.len() && !self.series.iter().any(|s| &s.name == name) {
+                self.series.push(Series {
+                    name: name.clone(),
+                    values: std::iter::repeat_n(None, len).collect(),
+                });
+            }
+        }
+
+        for series in &mut self.series {
+            let value = fields
+                .iter()
+                .find(|(name, _)| *name == series.name)
+                .map(|(_, value)| *value);
+            series.values.push_back(value);
+            if series.values.len() > MAX_SAMPLES {
+                series.values.pop_front();
+            }
+        }
+        self.samples += 1;
+        self.dirty = true;
+    }
+
+    /// Draw the plot, one string per row with color escapes
+    ///
+    /// The top row is a legend with each series' latest value, the rest is the chart with the
+    /// value axis on the left, scaled to fit the samples on screen.
+    pub fn render(&mut self, cols: usize, rows: usize) -> Vec<String> {
+        self.dirty = false;
+        let mut lines = vec![self.legend(cols)];
+        let height = rows.saturating_sub(1);
+        if height == 0 {
+            return lines;
+        }
+
+        // Scale to the samples that fit, widening the axis until its labels fit
+        let (dots_x, dots_y) = self.style.dots();
+        let visible = |width: usize| width * dots_x;
+        let mut axis = 0;
+        let (width, min, max, labels) = loop {
+            let width = cols.saturating_sub(axis + 2);
+            let (min, max) = self.range(visible(width));
+            let labels = [label(max), label((min + max) / 2.0), label(min)];
+            let needed = labels.iter().map(String::len).max().unwrap_or(0);
+            if needed <= axis {
+                break (width, min, max, labels);
+            }
+            axis = needed;
+        };
+
+        // Cells of dot bits, colored by the last series drawn in them
+        let mut cells = vec![vec![(0u8, None); width]; height];
+        let dot_rows = height * dots_y;
+        let to_row = |value: f64| {
+            let scaled = (value - min) / (max - min) * (dot_rows - 1) as f64;
+            dot_rows - 1 - (scaled.round() as usize).min(dot_rows - 1)
+        };
+        for (index, series) in self.series.iter().enumerate() {
+            let skip = series.values.len().saturating_sub(visible(width));
+            let mut last: Option<usize> = None;
+            for (x, value) in series.values.iter().skip(skip).enumerate() {
+                let y = match value {
+                    Some(value) => to_row(*value),
+                    None => {
+                        last = None;
+                        continue;
+                    }
+                };
+                // Join each sample to the one before it so steep changes stay connected
+                let (top, bottom) = match last {
+                    Some(last) => (last.min(y), last.max(y)),
+                    None => (y, y),
+                };
+                for dot_y in top..=bottom {
+                    let cell = &mut cells[dot_y / dots_y][x / dots_x];
+                    cell.0 |= self.style.bit(x % dots_x, dot_y % dots_y);
+                    cell.1 = Some(index);
+                }
+                last = Some(y);
+            }
+        }
+
+        for (row, cells) in cells.iter().enumerate() {
+            let label = match row {
+                0 => &labels[0],
+                _ if row == height / 2 && height > 2 => &labels[1],
+                _ if row == height - 1 => &labels[2],
+                _ => "",
+            };
+            let mut line = format!("{:>width$} ", label, width = axis);
+            line.push_str(&format!("{}│", SetForegroundColor(Color::DarkGrey)));
+            let mut color = None;
+            for (bits, series) in cells {
+                let cell_color = series.map(|index| COLORS[index]);
+                if *bits != 0 && cell_color != color {
+                    line.push_str(&SetForegroundColor(cell_color.unwrap()).to_string());
+                    color = cell_color;
+                }
+                line.push(self.style.glyph(*bits));
+            }
+            line.push_str(&ResetColor.to_string());
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Get the legend, each series' color, name, and latest value, cut to `cols` characters
+    fn legend(&self, cols: usize) -> String {
+        if self.series.is_empty() {
+            return "Plot: waiting for lines with numbers, e.g. temp:23.4,hum:40"
+                .chars()
+                .take(cols)
+                .collect();
+        }
+
+        let mut legend: String = format!("Plot ({}, {} samples):", self.style, self.samples)
+            .chars()
+            .take(cols)
+            .collect();
+        let mut used = legend.chars().count();
+        for (series, color) in self.series.iter().zip(COLORS) {
+            let value = series
+                .values
+                .iter()
+                .rev()
+                .flatten()
+                .next()
+                .map_or_else(|| "-".to_string(), |value| label(*value));
+            let text = format!(" {} {}", series.name, value);
+            used += text.chars().count() + 2;
+            if used > cols {
+                break;
+            }
+            legend.push_str(&format!(
+                " {}■{}{}",
+                SetForegroundColor(*color),
+                ResetColor,
+                text
+            ));
+        }
+        legend
+    }
+
+    /// Get the range of the last `count` samples of every series, never empty
+    fn range(&self, count: usize) -> (f64, f64) {
+        let (min, max) = self
+            .series
+            .iter()
+            .flat_map(|series| {
+                let skip = series.values.len().saturating_sub(count);
+                series.values.iter().skip(skip).flatten()
+            })
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+                (min.min(*value), max.max(*value))
+            });
+
+        if min > max {
+            (0.0, 1.0)
+        } else if min == max {
+            (min - 1.0, max + 1.0)
+        } else {
+            (min, max)
+        }
+    }
+}
+
+/// Format an axis or legend value without trailing zeros
+fn label(value: f64) -> String {
+    let s = format!("{:.3}", value);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    match s {
+        "-0" => "0".to_string(),
+        s => s.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fields with owned names, to compare with what was parsed
+    fn named(fields: &[(&str, f64)]) -> Vec<(String, f64)> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect()
+    }
+
+    #[test]
+    fn unlabeled() {
+        assert_eq!(
+            parse_fields("1, 2.5;-3\t4e2 5"),
+            named(&[
+                ("1", 1.0),
+                ("2", 2.5),
+                ("3", -3.0),
+                ("4", 400.0),
+                ("5", 5.0)
+            ])
+        );
+        assert!(parse_fields("  ok  ").is_empty());
+        assert!(parse_fields("").is_empty());
+    }
+
+    #[test]
+    fn labeled() {
+        assert_eq!(
+            parse_fields("temp: 23.4 humidity = 51"),
+            named(&[("temp", 23.4), ("humidity", 51.0)])
+        );
+        assert_eq!(
+            parse_fields("a:1,b=2;c : 3"),
+            named(&[("a", 1.0), ("b", 2.0), ("c", 3.0)])
+        );
+        // Unlabeled fields are numbered among themselves
+        assert_eq!(
+            parse_fields("x:1 7 y:2 8"),
+            named(&[("x", 1.0), ("1", 7.0), ("y", 2.0), ("2", 8.0)])
+        );
+    }
+
+    #[test]
+    fn skips_non_numbers() {
+        assert_eq!(
+            parse_fields(":4 state:idle count:3 x:"),
+            named(&[("count", 3.0)])
+        );
+        // Non-finite values are skipped but still take their position
+        assert_eq!(
+            parse_fields("1 nan inf 2"),
+            named(&[("1", 1.0), ("4", 2.0)])
+        );
+        assert_eq!(parse_fields("a:NaN b:-inf c:5"), named(&[("c", 5.0)]));
+    }
+}
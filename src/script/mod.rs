@@ -0,0 +1,501 @@
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::bytes::Regex;
+use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, INT};
+use serialport::SerialPort;
+
+use crate::ui::DisplayUpdateEvent;
+
+/// Timeout used by `expect` when none is given
+const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_millis(5000);
+
+/// How often blocking script calls check for cancellation
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Most received data kept for `expect` to match, older data is dropped
+const EXPECT_WINDOW_SIZE: usize = 4096;
+
+/// Result type of functions exposed to scripts
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Script status
+#[derive(Debug, Clone)]
+pub enum ScriptStatus {
+    /// Message logged by the script
+    Log(String),
+    /// Script finished successfully
+    Complete,
+    /// Script failed or was cancelled
+    Failed(String),
+}
+
+impl Display for ScriptStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScriptStatus::Log(msg) => write!(f, "Script: {}", msg),
+            ScriptStatus::Complete => write!(f, "Script complete"),
+            ScriptStatus::Failed(msg) => write!(f, "Script failed: {}", msg),
+        }
+    }
+}
+
+/// Handle to a running script
+#[derive(Debug, Clone)]
+pub struct Script {
+    /// Forwards received serial data to the script thread for `expect`
+    pub serial_input_tx: Sender<Vec<u8>>,
+    /// Set to cancel the script
+    cancel: Arc<AtomicBool>,
+}
+
+impl Script {
+    /// Start running a script in a new thread
+    pub fn start(
+        path: &Path,
+        serial_port: Arc<Mutex<Box<dyn SerialPort>>>,
+        serial_send_tx: Sender<Vec<u8>>,
+        display_update_tx: Sender<DisplayUpdateEvent>,
+    ) -> Self {
+        let (serial_input_tx, serial_input_rx) = channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let thread_cancel = cancel.clone();
+        let path = path.to_path_buf();
+
+        thread::spawn(move || {
+            let context = ScriptContext {
+                serial_port,
+                serial_send_tx,
+                serial_input_rx,
+                received: Vec::new(),
+                display_update_tx: display_update_tx.clone(),
+                cancel: thread_cancel,
+            };
+
+            let status = match run_script(path, context) {
+                Ok(()) => ScriptStatus::Complete,
+                Err(msg) => ScriptStatus::Failed(msg),
+            };
+            display_update_tx
+                .send(DisplayUpdateEvent::Script(status))
+                .ok();
+        });
+
+        Self {
+            serial_input_tx,
+            cancel,
+        }
+    }
+
+    /// Cancel the script
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// State shared by the functions exposed to a script
+struct ScriptContext {
+    /// Serial port, used to change port settings
+    serial_port: Arc<Mutex<Box<dyn SerialPort>>>,
+    /// Channel to the serial TX thread
+    serial_send_tx: Sender<Vec<u8>>,
+    /// Received serial data
+    serial_input_rx: Receiver<Vec<u8>>,
+    /// Data received since the last `expect` match
+    received: Vec<u8>,
+    /// Channel to report script output on
+    display_update_tx: Sender<DisplayUpdateEvent>,
+    /// Cancel flag
+    cancel: Arc<AtomicBool>,
+}
+
+impl ScriptContext {
+    /// Send data to the serial port
+    fn send(&self, data: Vec<u8>) -> ScriptResult<()> {
+        self.check_cancelled()?;
+        self.serial_send_tx
+            .send(data)
+            .map_err(|e| e.to_string().into())
+    }
+
+    /// Wait for received data to match a pattern, returning the matched text
+    ///
+    /// Data up to the end of the match is consumed, so the next call only sees data received
+    /// after it
+    fn expect(&mut self, pattern: &str, timeout: Duration) -> ScriptResult<String> {
+        let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            // Take everything already received before checking for a match
+            while let Ok(data) = self.serial_input_rx.try_recv() {
+                self.receive(&data);
+            }
+
+            if let Some(m) = regex.find(&self.received) {
+                let matched = String::from_utf8_lossy(m.as_bytes()).to_string();
+                let end = m.end();
+                self.received.drain(..end);
+                return Ok(matched);
+            }
+
+            self.check_cancelled()?;
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.as_millis() == 0 {
+                return Err(format!("Timed out waiting for '{}'", pattern).into());
+            }
+
+            match self
+                .serial_input_rx
+                .recv_timeout(remaining.min(CANCEL_POLL_INTERVAL))
+            {
+                Ok(data) => self.receive(&data),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err("Serial port closed".into()),
+            }
+        }
+    }
+
+    /// Add received data for `expect` to match
+    fn receive(&mut self, data: &[u8]) {
+        self.received.extend_from_slice(data);
+        if self.received.len() > EXPECT_WINDOW_SIZE {
+            self.received
+                .drain(..self.received.len() - EXPECT_WINDOW_SIZE);
+        }
+    }
+
+    /// Sleep, waking up periodically to check for cancellation
+    fn sleep(&self, duration: Duration) -> ScriptResult<()> {
+        let deadline = Instant::now() + duration;
+
+        loop {
+            self.check_cancelled()?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.as_millis() == 0 {
+                return Ok(());
+            }
+            thread::sleep(remaining.min(CANCEL_POLL_INTERVAL));
+        }
+    }
+
+    /// Change the baud rate of the serial port
+    fn set_baud(&self, baud_rate: INT) -> ScriptResult<()> {
+        let baud_rate =
+            u32::try_from(baud_rate).map_err(|_| format!("Invalid baud rate {}", baud_rate))?;
+        self.serial_port
+            .lock()
+            .map_err(|_| "Serial port unavailable".to_string())?
+            .set_baud_rate(baud_rate)
+            .map_err(|e| e.to_string())?;
+        self.display_update_tx
+            .send(DisplayUpdateEvent::BaudRate(baud_rate))
+            .ok();
+        Ok(())
+    }
+
+    /// Set the DTR line
+    fn set_dtr(&self, level: bool) -> ScriptResult<()> {
+        self.serial_port
+            .lock()
+            .map_err(|_| "Serial port unavailable".to_string())?
+            .write_data_terminal_ready(level)
+            .map_err(|e| e.to_string().into())
+    }
+
+    /// Report a message from the script
+    fn log(&self, msg: String) {
+        self.display_update_tx
+            .send(DisplayUpdateEvent::Script(ScriptStatus::Log(msg)))
+            .ok();
+    }
+
+    /// Check if the script has been cancelled
+    fn check_cancelled(&self) -> ScriptResult<()> {
+        if self.cancel.load(Ordering::Relaxed) {
+            Err("Cancelled".into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Convert a script timeout in milliseconds to a duration
+fn millis(ms: INT) -> Duration {
+    Duration::from_millis(ms.max(0) as u64)
+}
+
+/// Build the script engine, exposing the serial API
+fn build_engine(context: Rc<RefCell<ScriptContext>>) -> Engine {
+    let mut engine = Engine::new();
+
+    let ctx = context.clone();
+    engine.register_fn("send", move |text: &str| {
+        ctx.borrow().send(text.as_bytes().to_vec())
+    });
+    let ctx = context.clone();
+    engine.register_fn("send", move |data: Blob| ctx.borrow().send(data));
+    let ctx = context.clone();
+    engine.register_fn("send", move |data: Array| {
+        let data = data
+            .into_iter()
+            .map(|byte| {
+                byte.as_int()
+                    .ok()
+                    .and_then(|byte| u8::try_from(byte).ok())
+                    .ok_or_else(|| format!("Invalid byte {}", byte))
+            })
+            .collect::<Result<Vec<u8>, String>>()?;
+        ctx.borrow().send(data)
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("expect", move |pattern: &str| {
+        ctx.borrow_mut().expect(pattern, DEFAULT_EXPECT_TIMEOUT)
+    });
+    let ctx = context.clone();
+    engine.register_fn("expect", move |pattern: &str, timeout: INT| {
+        ctx.borrow_mut().expect(pattern, millis(timeout))
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("sleep", move |ms: INT| ctx.borrow().sleep(millis(ms)));
+
+    let ctx = context.clone();
+    engine.register_fn("set_baud", move |baud_rate: INT| {
+        ctx.borrow().set_baud(baud_rate)
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("set_dtr", move |level: bool| ctx.borrow().set_dtr(level));
+
+    let ctx = context.clone();
+    engine.register_fn("log", move |msg: &str| ctx.borrow().log(msg.to_string()));
+
+    engine.register_fn("assert", |condition: bool| -> ScriptResult<()> {
+        if condition {
+            Ok(())
+        } else {
+            Err("Assertion failed".into())
+        }
+    });
+    engine.register_fn("assert", |condition: bool, msg: &str| -> ScriptResult<()> {
+        if condition {
+            Ok(())
+        } else {
+            Err(format!("Assertion failed: {}", msg).into())
+        }
+    });
+
+    // print and debug go to the same place as log
+    let ctx = context.clone();
+    engine.on_print(move |msg| ctx.borrow().log(msg.to_string()));
+    let ctx = context.clone();
+    engine.on_debug(move |msg, _, _| ctx.borrow().log(msg.to_string()));
+
+    // Stop long running scripts that don't call into the API when cancelled
+    let cancel = context.borrow().cancel.clone();
+    engine.on_progress(move |_| {
+        if cancel.load(Ordering::Relaxed) {
+            Some(Dynamic::from("Cancelled"))
+        } else {
+            None
+        }
+    });
+
+    engine
+}
+
+/// Run a script file to completion
+fn run_script(path: PathBuf, context: ScriptContext) -> Result<(), String> {
+    let engine = build_engine(Rc::new(RefCell::new(context)));
+
+    engine.run_file(path).map_err(|e| match *e {
+        EvalAltResult::ErrorRuntime(msg, pos) if pos.is_none() => msg.to_string(),
+        EvalAltResult::ErrorRuntime(msg, pos) => format!("{} at {}", msg, pos),
+        EvalAltResult::ErrorTerminated(_, _) => "Cancelled".to_string(),
+        e => e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::loopback::LoopbackPort;
+    use serialport::SerialPortSettings;
+    use std::fs;
+
+    /// Channels connected to a test script context
+    struct Link {
+        /// Feeds received data to the script
+        input: Sender<Vec<u8>>,
+        /// Data the script sent
+        sent: Receiver<Vec<u8>>,
+        /// Script output
+        display: Receiver<DisplayUpdateEvent>,
+    }
+
+    /// Script context on a loopback port, with the channels feeding and draining it
+    fn context() -> (ScriptContext, Link) {
+        let port = LoopbackPort::open("loop://", &SerialPortSettings::default()).unwrap();
+        let (serial_input_tx, serial_input_rx) = channel();
+        let (serial_send_tx, serial_send_rx) = channel();
+        let (display_update_tx, display_update_rx) = channel();
+        let context = ScriptContext {
+            serial_port: Arc::new(Mutex::new(Box::new(port))),
+            serial_send_tx,
+            serial_input_rx,
+            received: Vec::new(),
+            display_update_tx,
+            cancel: Arc::new(AtomicBool::new(false)),
+        };
+        let link = Link {
+            input: serial_input_tx,
+            sent: serial_send_rx,
+            display: display_update_rx,
+        };
+        (context, link)
+    }
+
+    /// Get the message of a failed script call
+    fn error<T: std::fmt::Debug>(res: ScriptResult<T>) -> String {
+        match *res.unwrap_err() {
+            EvalAltResult::ErrorRuntime(msg, _) => msg.to_string(),
+            e => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn expect_consumes_match() {
+        let (mut ctx, link) = context();
+        link.input.send(b"boot\r\nOK\r\nready OK".to_vec()).unwrap();
+
+        assert_eq!(ctx.expect("O.", DEFAULT_EXPECT_TIMEOUT).unwrap(), "OK");
+        assert_eq!(ctx.received, b"\r\nready OK");
+        assert_eq!(ctx.expect("\\w+", DEFAULT_EXPECT_TIMEOUT).unwrap(), "ready");
+        assert_eq!(ctx.expect("OK", DEFAULT_EXPECT_TIMEOUT).unwrap(), "OK");
+
+        // Text before a match can't be matched again
+        let res = ctx.expect("boot", Duration::from_millis(10));
+        assert!(error(res).contains("Timed out waiting for 'boot'"));
+    }
+
+    #[test]
+    fn expect_split_match() {
+        let (mut ctx, link) = context();
+        link.input.send(b"log".to_vec()).unwrap();
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            link.input.send(b"in: ".to_vec()).unwrap();
+        });
+
+        assert_eq!(
+            ctx.expect("login: ", DEFAULT_EXPECT_TIMEOUT).unwrap(),
+            "login: "
+        );
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn expect_window() {
+        let (mut ctx, link) = context();
+        link.input.send(b"START".to_vec()).unwrap();
+        link.input.send(vec![b'x'; EXPECT_WINDOW_SIZE]).unwrap();
+        link.input.send(b"END".to_vec()).unwrap();
+
+        // The oldest data is dropped once the window is full
+        assert!(ctx.expect("START", Duration::from_millis(10)).is_err());
+        assert_eq!(ctx.received.len(), EXPECT_WINDOW_SIZE);
+        assert!(ctx.received.ends_with(b"xEND"));
+        assert_eq!(
+            ctx.expect("x+END", DEFAULT_EXPECT_TIMEOUT).unwrap().len(),
+            EXPECT_WINDOW_SIZE
+        );
+    }
+
+    #[test]
+    fn expect_timeout() {
+        let (mut ctx, _link) = context();
+        let start = Instant::now();
+        let res = ctx.expect("OK", Duration::from_millis(150));
+        assert_eq!(error(res), "Timed out waiting for 'OK'");
+        assert!(start.elapsed() >= Duration::from_millis(150));
+
+        assert!(ctx.expect("(", DEFAULT_EXPECT_TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn expect_cancelled() {
+        let (mut ctx, _link) = context();
+        let cancel = ctx.cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            cancel.store(true, Ordering::Relaxed);
+        });
+
+        let start = Instant::now();
+        let res = ctx.expect("OK", DEFAULT_EXPECT_TIMEOUT);
+        assert_eq!(error(res), "Cancelled");
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(ctx.send(b"x".to_vec()).is_err());
+    }
+
+    #[test]
+    fn expect_port_closed() {
+        let (mut ctx, link) = context();
+        drop(link.input);
+        let res = ctx.expect("OK", DEFAULT_EXPECT_TIMEOUT);
+        assert_eq!(error(res), "Serial port closed");
+    }
+
+    #[test]
+    fn poisoned_port() {
+        let (ctx, _link) = context();
+        let serial_port = ctx.serial_port.clone();
+        thread::spawn(move || {
+            let _port = serial_port.lock().unwrap();
+            panic!("poison the port lock");
+        })
+        .join()
+        .ok();
+
+        assert_eq!(error(ctx.set_dtr(false)), "Serial port unavailable");
+        assert_eq!(error(ctx.set_baud(9600)), "Serial port unavailable");
+    }
+
+    #[test]
+    fn run() {
+        let (ctx, link) = context();
+        link.input.send(b"AT\r\r\nOK\r\n".to_vec()).unwrap();
+        let path = std::env::temp_dir().join(format!("cerial-script-{}.rhai", std::process::id()));
+        fs::write(
+            &path,
+            "send(\"AT\\r\"); assert(expect(\"OK|ERROR\") == \"OK\"); set_baud(9600); log(\"done\");",
+        )
+        .unwrap();
+
+        let res = run_script(path.clone(), ctx);
+        fs::remove_file(&path).ok();
+        res.unwrap();
+        assert_eq!(link.sent.try_recv().unwrap(), b"AT\r");
+        let events: Vec<String> = link
+            .display
+            .try_iter()
+            .map(|event| match event {
+                DisplayUpdateEvent::BaudRate(baud_rate) => baud_rate.to_string(),
+                DisplayUpdateEvent::Script(status) => status.to_string(),
+                _ => String::new(),
+            })
+            .collect();
+        assert_eq!(events, ["9600", "Script: done"]);
+    }
+}
@@ -0,0 +1,258 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::bytes::Regex;
+
+use crate::ui::DisplayUpdateEvent;
+
+/// Line ending used when sending a file
+#[derive(Debug, Copy, Clone, Default)]
+pub enum LineEnding {
+    /// Line feed
+    #[default]
+    Lf,
+    /// Carriage return
+    Cr,
+    /// Carriage return followed by a line feed
+    CrLf,
+}
+
+impl LineEnding {
+    /// Get the bytes sent at the end of a line
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::Cr => b"\r",
+            LineEnding::CrLf => b"\r\n",
+        }
+    }
+}
+
+/// File send pacing settings
+#[derive(Debug, Clone, Default)]
+pub struct SendSettings {
+    /// Delay after each character
+    pub char_delay: Duration,
+    /// Delay after each line
+    pub line_delay: Duration,
+    /// Line ending to send in place of the file's line endings
+    pub line_ending: LineEnding,
+    /// Wait for received data to match this pattern before sending the next line
+    pub prompt: Option<Regex>,
+    /// How long to wait for the prompt before giving up
+    pub prompt_timeout: Duration,
+}
+
+/// File send status
+#[derive(Debug, Clone)]
+pub enum SendStatus {
+    /// Send is in progress
+    Progress {
+        /// Name of the file being sent
+        file_name: String,
+        /// Bytes of the file sent so far
+        sent: usize,
+        /// Total size of the file
+        total: usize,
+    },
+    /// Send finished successfully
+    Complete,
+    /// Send failed or was cancelled
+    Failed(String),
+}
+
+impl Display for SendStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendStatus::Progress {
+                file_name,
+                sent,
+                total,
+            } => {
+                let percent = if *total == 0 { 100 } else { sent * 100 / total };
+                write!(f, "Sending {} {}%", file_name, percent)
+            }
+            SendStatus::Complete => write!(f, "Send complete"),
+            SendStatus::Failed(msg) => write!(f, "Send failed: {}", msg),
+        }
+    }
+}
+
+/// Handle to an in progress file send
+#[derive(Debug, Clone)]
+pub struct FileSend {
+    /// Forwards received serial data to the send thread for prompt matching
+    pub serial_input_tx: Sender<Vec<u8>>,
+    /// Set to cancel the send
+    cancel: Arc<AtomicBool>,
+}
+
+impl FileSend {
+    /// Start sending a file in a new thread
+    pub fn start(
+        path: &Path,
+        settings: SendSettings,
+        serial_send_tx: Sender<Vec<u8>>,
+        display_update_tx: Sender<DisplayUpdateEvent>,
+    ) -> Self {
+        let (serial_input_tx, serial_input_rx) = channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let thread_cancel = cancel.clone();
+        let path = path.to_path_buf();
+
+        thread::spawn(move || {
+            let sender = FileSender {
+                settings,
+                serial_send_tx,
+                serial_input_rx,
+                display_update_tx: display_update_tx.clone(),
+                cancel: thread_cancel,
+            };
+
+            let status = match sender.send_file(path) {
+                Ok(()) => SendStatus::Complete,
+                Err(msg) => SendStatus::Failed(msg),
+            };
+            display_update_tx
+                .send(DisplayUpdateEvent::FileSend(status))
+                .ok();
+        });
+
+        Self {
+            serial_input_tx,
+            cancel,
+        }
+    }
+
+    /// Cancel the send
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// State of the file send thread
+struct FileSender {
+    /// Pacing settings
+    settings: SendSettings,
+    /// Channel to the serial TX thread
+    serial_send_tx: Sender<Vec<u8>>,
+    /// Received serial data
+    serial_input_rx: Receiver<Vec<u8>>,
+    /// Channel to report progress on
+    display_update_tx: Sender<DisplayUpdateEvent>,
+    /// Cancel flag
+    cancel: Arc<AtomicBool>,
+}
+
+impl FileSender {
+    /// Send a file line by line
+    fn send_file(&self, path: PathBuf) -> Result<(), String> {
+        let contents = fs::read(&path).map_err(|e| e.to_string())?;
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let total = contents.len();
+        let mut sent = 0;
+
+        let mut lines = contents.split_inclusive(|b| *b == b'\n').peekable();
+        while let Some(line) = lines.next() {
+            sent += line.len();
+
+            // Replace the line ending with the configured one
+            let mut data = line.to_vec();
+            if data.ends_with(b"\n") {
+                data.pop();
+                if data.ends_with(b"\r") {
+                    data.pop();
+                }
+                data.extend_from_slice(self.settings.line_ending.as_bytes());
+            }
+
+            // Drop anything received before this line so stale prompts don't match
+            while self.serial_input_rx.try_recv().is_ok() {}
+
+            self.send_line(data)?;
+
+            self.display_update_tx
+                .send(DisplayUpdateEvent::FileSend(SendStatus::Progress {
+                    file_name: file_name.clone(),
+                    sent,
+                    total,
+                }))
+                .map_err(|e| e.to_string())?;
+
+            if lines.peek().is_some() {
+                if let Some(prompt) = &self.settings.prompt {
+                    self.wait_for_prompt(prompt)?;
+                }
+                thread::sleep(self.settings.line_delay);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send a single line, pausing between characters if configured
+    fn send_line(&self, data: Vec<u8>) -> Result<(), String> {
+        if self.settings.char_delay.as_millis() == 0 {
+            self.check_cancelled()?;
+            return self.serial_send_tx.send(data).map_err(|e| e.to_string());
+        }
+
+        for byte in data {
+            self.check_cancelled()?;
+            self.serial_send_tx
+                .send(vec![byte])
+                .map_err(|e| e.to_string())?;
+            thread::sleep(self.settings.char_delay);
+        }
+
+        Ok(())
+    }
+
+    /// Wait for received data to match the prompt pattern
+    fn wait_for_prompt(&self, prompt: &Regex) -> Result<(), String> {
+        let deadline = Instant::now() + self.settings.prompt_timeout;
+        let mut received = Vec::new();
+
+        loop {
+            self.check_cancelled()?;
+
+            // Wake up periodically to check for cancellation
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self
+                .serial_input_rx
+                .recv_timeout(remaining.min(Duration::from_millis(100)))
+            {
+                Ok(data) => {
+                    received.extend(data);
+                    if prompt.is_match(&received) {
+                        return Ok(());
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if remaining.as_millis() == 0 {
+                        return Err("Timed out waiting for prompt".to_string());
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err("Cancelled".to_string()),
+            }
+        }
+    }
+
+    /// Check if the send has been cancelled
+    fn check_cancelled(&self) -> Result<(), String> {
+        if self.cancel.load(Ordering::Relaxed) {
+            Err("Cancelled".to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
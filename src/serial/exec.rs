@@ -0,0 +1,327 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use serialport::{
+    ClearBuffer, DataBits, Error, ErrorKind, FlowControl, Parity, SerialPort, SerialPortSettings,
+    StopBits,
+};
+
+use crate::ui::DisplayUpdateEvent;
+
+/// Forward everything read from a child output pipe to the port
+fn child_output_thread<T: Read>(mut pipe: T, tx: Sender<Vec<u8>>) {
+    let mut buffer = vec![0; 128];
+    loop {
+        match pipe.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => {
+                if tx.send(buffer[..count].to_vec()).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Virtual serial port backed by a child process
+///
+/// Data written to the port goes to the child's stdin, and the child's stdout and stderr
+/// are read back as serial data. When the child exits, a `ProcessExit` event is sent to the
+/// display loop.
+pub struct ChildPort {
+    /// Command line used to start the child
+    command: Vec<String>,
+    /// Current port settings
+    settings: SerialPortSettings,
+    /// Child process
+    child: Child,
+    /// Child's stdin
+    stdin: ChildStdin,
+    /// Output from the child's stdout and stderr
+    output_rx: Receiver<Vec<u8>>,
+    /// Output that has been received but not yet read
+    pending: RefCell<VecDeque<u8>>,
+    /// Channel to notify the display loop of the child exiting
+    display_update_tx: Sender<DisplayUpdateEvent>,
+    /// Set once both of the child's output pipes have closed
+    output_closed: bool,
+    /// Set once the child has exited
+    exited: bool,
+}
+
+impl ChildPort {
+    /// Spawn a child process and connect to its stdio
+    pub fn spawn(
+        command: &[String],
+        settings: &SerialPortSettings,
+        display_update_tx: Sender<DisplayUpdateEvent>,
+    ) -> serialport::Result<Self> {
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No command given."))?;
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+
+        let (output_tx, output_rx) = channel();
+        let stderr_tx = output_tx.clone();
+        thread::spawn(move || child_output_thread(stdout, output_tx));
+        thread::spawn(move || child_output_thread(stderr, stderr_tx));
+
+        Ok(Self {
+            command: command.to_vec(),
+            settings: *settings,
+            child,
+            stdin,
+            output_rx,
+            pending: RefCell::new(VecDeque::new()),
+            display_update_tx,
+            output_closed: false,
+            exited: false,
+        })
+    }
+
+    /// Reap the child and notify the display loop if it has exited
+    ///
+    /// A child can close its output and keep running, so this doesn't wait for it and is
+    /// called again on later reads
+    fn poll_exit(&mut self) {
+        match self.child.try_wait() {
+            Ok(Some(status)) => {
+                self.display_update_tx
+                    .send(DisplayUpdateEvent::ProcessExit(status))
+                    .ok();
+                self.exited = true;
+            }
+            Ok(None) => {}
+            Err(_) => self.exited = true,
+        }
+    }
+}
+
+impl Drop for ChildPort {
+    fn drop(&mut self) {
+        if !self.exited {
+            self.child.kill().ok();
+            self.child.wait().ok();
+        }
+    }
+}
+
+impl io::Read for ChildPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.get_mut().is_empty() {
+            if self.output_closed {
+                if !self.exited {
+                    self.poll_exit();
+                }
+                thread::sleep(self.settings.timeout);
+            } else {
+                match self.output_rx.recv_timeout(self.settings.timeout) {
+                    Ok(data) => self.pending.get_mut().extend(data),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // Both output pipes have closed
+                    Err(RecvTimeoutError::Disconnected) => {
+                        self.output_closed = true;
+                        self.poll_exit();
+                    }
+                }
+            }
+        }
+
+        let pending = self.pending.get_mut();
+        if pending.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Operation timed out",
+            ));
+        }
+
+        let count = buf.len().min(pending.len());
+        for (dest, byte) in buf.iter_mut().zip(pending.drain(..count)) {
+            *dest = byte;
+        }
+        Ok(count)
+    }
+}
+
+impl io::Write for ChildPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Data sent after the child exits is dropped so the TX thread stays alive for a restart
+        if self.exited {
+            return Ok(buf.len());
+        }
+
+        match self.stdin.write(buf) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(buf.len()),
+            res => res,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stdin.flush() {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            res => res,
+        }
+    }
+}
+
+impl SerialPort for ChildPort {
+    fn name(&self) -> Option<String> {
+        Some(self.command.join(" "))
+    }
+
+    fn settings(&self) -> SerialPortSettings {
+        self.settings
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.settings.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(self.settings.data_bits)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(self.settings.flow_control)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(self.settings.parity)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(self.settings.stop_bits)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.settings.timeout
+    }
+
+    fn set_all(&mut self, settings: &SerialPortSettings) -> serialport::Result<()> {
+        self.settings = *settings;
+        Ok(())
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.settings.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.settings.data_bits = data_bits;
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.settings.flow_control = flow_control;
+        Ok(())
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.settings.parity = parity;
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.settings.stop_bits = stop_bits;
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.settings.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(!self.exited)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(!self.exited)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(!self.exited)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        // Count output the reader threads have passed on but read hasn't picked up yet
+        let mut pending = self.pending.borrow_mut();
+        while let Ok(data) = self.output_rx.try_recv() {
+            pending.extend(data);
+        }
+        Ok(pending.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, _buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Err(Error::new(
+            ErrorKind::Unknown,
+            "Process ports can not be cloned.",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn output_closed_before_exit() {
+        let (tx, rx) = channel();
+        let settings = SerialPortSettings {
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let command = ["sh", "-c", "exec >&- 2>&-; sleep 0.5"].map(String::from);
+        let mut port = ChildPort::spawn(&command, &settings, tx).unwrap();
+
+        // Reads keep timing out while the child runs with its output closed
+        let start = Instant::now();
+        while rx.try_recv().is_err() {
+            let read_start = Instant::now();
+            let err = port.read(&mut [0; 8]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert!(read_start.elapsed() < Duration::from_millis(250));
+            assert!(start.elapsed() < Duration::from_secs(5));
+        }
+        assert!(start.elapsed() >= Duration::from_millis(400));
+        assert!(port.exited);
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serialport::posix::TTYPort;
+use serialport::{
+    ClearBuffer, DataBits, FlowControl, Parity, SerialPort, SerialPortSettings, StopBits,
+};
+
+use crate::serial::LinkCounters;
+
+/// `struct serial_icounter_struct` from `linux/serial.h`, filled by `TIOCGICOUNT`
+///
+/// Only the error counts are used, the other fields are needed for the layout
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+struct SerialIcounter {
+    cts: libc::c_int,
+    dsr: libc::c_int,
+    rng: libc::c_int,
+    dcd: libc::c_int,
+    rx: libc::c_int,
+    tx: libc::c_int,
+    frame: libc::c_int,
+    overrun: libc::c_int,
+    parity: libc::c_int,
+    brk: libc::c_int,
+    buf_overrun: libc::c_int,
+    reserved: [libc::c_int; 9],
+}
+
+/// Where the decoder is in a `PARMRK` escape
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Escape {
+    /// Plain data
+    None,
+    /// Read `\xff`, either a doubled `\xff` or an error mark follows
+    Ff,
+    /// Read `\xff\x00`, the next byte was received with an error
+    FfNul,
+}
+
+/// Hardware serial port that counts UART line errors
+///
+/// `PARMRK` makes the kernel mark bytes received with framing or parity errors, and breaks, with a
+/// `\xff\x00` prefix and double real `\xff` bytes. The marks are removed from the data and
+/// counted, and the bad bytes can be shown in reverse video. Drivers that support `TIOCGICOUNT`
+/// also report framing, parity, overrun, and break counts, ptys and many USB adapters don't.
+pub struct LineErrorPort {
+    /// Underlying port
+    port: TTYPort,
+    /// Counters shared with the display
+    counters: Arc<LinkCounters>,
+    /// Show bytes received with errors in reverse video
+    mark: bool,
+    /// Escape decoder state, escapes can be split across reads
+    escape: Escape,
+    /// Decoded data not yet read
+    pending: VecDeque<u8>,
+    /// Last driver error counts, `None` until read or if the driver doesn't support it
+    icount: Option<SerialIcounter>,
+}
+
+impl LineErrorPort {
+    /// Open a serial port with line error marking enabled
+    pub fn open(
+        path: &str,
+        settings: &SerialPortSettings,
+        counters: Arc<LinkCounters>,
+        mark: bool,
+    ) -> serialport::Result<Self> {
+        let mut port = Self {
+            port: TTYPort::open(Path::new(path), settings)?,
+            counters,
+            mark,
+            escape: Escape::None,
+            pending: VecDeque::new(),
+            icount: None,
+        };
+        port.set_rate(settings.baud_rate)?;
+        port.enable_marking()?;
+        port.icount = port.read_icount();
+        Ok(port)
+    }
+
+    /// Get the port's termios, including the separate input and output rates
+    fn termios2(&self) -> io::Result<libc::termios2> {
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios2>() };
+        if unsafe { libc::ioctl(self.port.as_raw_fd(), libc::TCGETS2, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(termios)
+    }
+
+    /// Change the port's termios
+    fn set_termios2(&self, termios: &libc::termios2) -> io::Result<()> {
+        if unsafe { libc::ioctl(self.port.as_raw_fd(), libc::TCSETS2, termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Turn on error marking, serialport resets the input flags when parity changes
+    fn enable_marking(&mut self) -> serialport::Result<()> {
+        let mut termios = self.termios2()?;
+        termios.c_iflag |= libc::PARMRK | libc::INPCK;
+        termios.c_iflag &= !(libc::IGNPAR | libc::ISTRIP | libc::IGNBRK | libc::BRKINT);
+        Ok(self.set_termios2(&termios)?)
+    }
+
+    /// Set any baud rate with `BOTHER`, the driver picks the closest rate it can do
+    fn set_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        let mut termios = self.termios2()?;
+        termios.c_cflag &= !(libc::CBAUD | libc::CIBAUD);
+        termios.c_cflag |= libc::BOTHER;
+        termios.c_ispeed = baud_rate;
+        termios.c_ospeed = baud_rate;
+        Ok(self.set_termios2(&termios)?)
+    }
+
+    /// Read the driver's error counts
+    fn read_icount(&self) -> Option<SerialIcounter> {
+        let mut icount = SerialIcounter::default();
+        let result = unsafe {
+            libc::ioctl(
+                self.port.as_raw_fd(),
+                libc::TIOCGICOUNT,
+                &mut icount as *mut SerialIcounter,
+            )
+        };
+        if result == 0 {
+            Some(icount)
+        } else {
+            None
+        }
+    }
+
+    /// Add the change in the driver's error counts to the link counters
+    fn update_icount(&mut self) {
+        let (last, icount) = match (self.icount, self.read_icount()) {
+            (Some(last), Some(icount)) => (last, icount),
+            _ => return,
+        };
+        let delta = |now: libc::c_int, then: libc::c_int| now.wrapping_sub(then).max(0) as u64;
+
+        let counters = &self.counters;
+        counters
+            .framing_errors
+            .fetch_add(delta(icount.frame, last.frame), Ordering::Relaxed);
+        counters
+            .parity_errors
+            .fetch_add(delta(icount.parity, last.parity), Ordering::Relaxed);
+        counters.overruns.fetch_add(
+            delta(icount.overrun, last.overrun) + delta(icount.buf_overrun, last.buf_overrun),
+            Ordering::Relaxed,
+        );
+        counters
+            .breaks
+            .fetch_add(delta(icount.brk, last.brk), Ordering::Relaxed);
+        self.icount = Some(icount);
+    }
+
+    /// Remove error marks from raw data
+    fn decode(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.escape = match (self.escape, byte) {
+                (Escape::None, 0xff) => Escape::Ff,
+                (Escape::None, _) => {
+                    self.pending.push_back(byte);
+                    Escape::None
+                }
+                (Escape::Ff, 0x00) => Escape::FfNul,
+                // A doubled `\xff` is a real one
+                (Escape::Ff, 0xff) => {
+                    self.pending.push_back(byte);
+                    Escape::None
+                }
+                (Escape::Ff, _) => {
+                    self.pending.extend([0xff, byte]);
+                    Escape::None
+                }
+                (Escape::FfNul, _) => {
+                    self.counters.marked_bytes.fetch_add(1, Ordering::Relaxed);
+                    if self.mark {
+                        self.pending
+                            .extend(format!("\x1b[7m{:02x}\x1b[27m", byte).bytes());
+                    } else {
+                        self.pending.push_back(byte);
+                    }
+                    Escape::None
+                }
+            };
+        }
+    }
+}
+
+impl Read for LineErrorPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            let mut raw = vec![0; buf.len()];
+            // Check the driver counts even when nothing was read, errors can drop the bytes
+            let result = self.port.read(&mut raw);
+            self.update_icount();
+            let count = result?;
+            self.decode(&raw[..count]);
+        }
+
+        // All that was read was the start of an escape
+        if self.pending.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Operation timed out",
+            ));
+        }
+
+        let count = buf.len().min(self.pending.len());
+        for (dest, byte) in buf.iter_mut().zip(self.pending.drain(..count)) {
+            *dest = byte;
+        }
+        Ok(count)
+    }
+}
+
+impl Write for LineErrorPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl SerialPort for LineErrorPort {
+    fn name(&self) -> Option<String> {
+        self.port.name()
+    }
+
+    fn settings(&self) -> SerialPortSettings {
+        self.port.settings()
+    }
+
+    /// Read back the rate the driver actually set
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.termios2()?.c_ospeed)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        self.port.data_bits()
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        self.port.flow_control()
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        self.port.parity()
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        self.port.stop_bits()
+    }
+
+    fn timeout(&self) -> Duration {
+        self.port.timeout()
+    }
+
+    fn set_all(&mut self, settings: &SerialPortSettings) -> serialport::Result<()> {
+        self.port.set_all(settings)?;
+        self.set_rate(settings.baud_rate)?;
+        self.enable_marking()
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.set_rate(baud_rate)
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.port.set_data_bits(data_bits)
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.port.set_flow_control(flow_control)
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.port.set_parity(parity)?;
+        self.enable_marking()
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.port.set_stop_bits(stop_bits)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.port.set_timeout(timeout)
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+        self.port.write_request_to_send(level)
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+        self.port.write_data_terminal_ready(level)
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        self.port.read_clear_to_send()
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        self.port.read_data_set_ready()
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        self.port.read_ring_indicator()
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        self.port.read_carrier_detect()
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.port.bytes_to_read()? + self.pending.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        self.port.bytes_to_write()
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        self.port.clear(buffer_to_clear)
+    }
+
+    /// Clone the port with its own decoder, so data read from the clone is decoded too
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        let fd = unsafe { libc::dup(self.port.as_raw_fd()) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let mut port = unsafe { TTYPort::from_raw_fd(fd) };
+        port.set_timeout(self.port.timeout())?;
+
+        Ok(Box::new(Self {
+            port,
+            counters: self.counters.clone(),
+            mark: self.mark,
+            escape: Escape::None,
+            pending: VecDeque::new(),
+            icount: self.icount,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Get a port on one side of a pty pair, and the other side to write to it with
+    fn pair(mark: bool) -> (LineErrorPort, TTYPort) {
+        let (master, slave) = TTYPort::pair().unwrap();
+        let mut port = LineErrorPort {
+            port: slave,
+            counters: Arc::new(LinkCounters::default()),
+            mark,
+            escape: Escape::None,
+            pending: VecDeque::new(),
+            icount: None,
+        };
+        port.enable_marking().unwrap();
+        (port, master)
+    }
+
+    /// Decode raw data in pieces, returning what would be read
+    fn decode(port: &mut LineErrorPort, pieces: &[&[u8]]) -> Vec<u8> {
+        for piece in pieces {
+            port.decode(piece);
+        }
+        port.pending.drain(..).collect()
+    }
+
+    #[test]
+    fn error_marks() {
+        let (mut port, _master) = pair(false);
+        assert_eq!(decode(&mut port, &[b"a\xff\x00xb"]), b"axb");
+        assert_eq!(port.counters.marked_bytes.load(Ordering::Relaxed), 1);
+
+        // A doubled `\xff` is data, even split across reads
+        assert_eq!(decode(&mut port, &[b"a\xff", b"\xffb"]), b"a\xffb");
+        // A break is marked as a NUL
+        assert_eq!(decode(&mut port, &[b"\xff\x00", b"\x00"]), b"\x00");
+        assert_eq!(port.counters.marked_bytes.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn shows_marked_bytes() {
+        let (mut port, _master) = pair(true);
+        assert_eq!(decode(&mut port, &[b"\xff\x00x"]), b"\x1b[7m78\x1b[27m");
+        assert_eq!(decode(&mut port, &[b"\xff\xff"]), b"\xff");
+    }
+
+    #[test]
+    fn clone_decodes() {
+        let (port, mut master) = pair(false);
+        let mut clone = port.try_clone().unwrap();
+
+        // With PARMRK the kernel doubles each received `\xff`, the clone must undo it
+        master.write_all(b"a\xff\xffb").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let mut buf = [0; 16];
+        let mut read = Vec::new();
+        while let Ok(count) = clone.read(&mut buf) {
+            read.extend_from_slice(&buf[..count]);
+        }
+        assert_eq!(read, b"a\xff\xffb");
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::VecDeque;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serialport::{
+    ClearBuffer, DataBits, Error, ErrorKind, FlowControl, Parity, SerialPort, SerialPortSettings,
+    StopBits,
+};
+
+/// Target prefixes that select the loopback device
+const LOOPBACK_PREFIXES: [&str; 2] = ["loop://", "echo://"];
+
+/// Longest a read sleeps before checking for data written by the other end of a pair
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Bytes in flight and the time they become readable
+type Line = Arc<Mutex<VecDeque<(Instant, u8)>>>;
+
+/// Check if a serial device path refers to the loopback device
+pub fn is_loopback_target(dev: &str) -> bool {
+    LOOPBACK_PREFIXES
+        .iter()
+        .any(|prefix| dev.starts_with(prefix))
+}
+
+/// Virtual serial port that echos all written data back to the reader
+///
+/// Options are passed as a query string on the target, for example
+/// `loop://?latency=50&errors=0.01`
+/// * `latency` - Delay in milliseconds before written data can be read back
+/// * `errors` - Probability (0.0 to 1.0) that an echoed byte has a bit flipped
+pub struct LoopbackPort {
+    /// Target name the port was opened with
+    name: String,
+    /// Current port settings
+    settings: SerialPortSettings,
+    /// Bytes waiting to be read
+    buffer: Line,
+    /// Where written bytes go, the same as `buffer` unless the port is one end of a pair
+    remote: Line,
+    /// Echo latency
+    latency: Duration,
+    /// Byte error probability
+    error_rate: f64,
+    /// Error injection RNG state
+    rng_state: u64,
+    /// Request to send level, looped back to CTS
+    rts: bool,
+    /// Data terminal ready level, looped back to DSR and CD
+    dtr: bool,
+}
+
+impl LoopbackPort {
+    /// Open a loopback port from a target string
+    pub fn open(target: &str, settings: &SerialPortSettings) -> serialport::Result<Self> {
+        let buffer = Line::default();
+        let mut port = Self {
+            name: target.to_string(),
+            settings: *settings,
+            buffer: buffer.clone(),
+            remote: buffer,
+            latency: Duration::from_millis(0),
+            error_rate: 0.0,
+            rng_state: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0)
+                | 1,
+            rts: true,
+            dtr: true,
+        };
+
+        if let Some((_, query)) = target.split_once('?') {
+            for option in query.split('&').filter(|o| !o.is_empty()) {
+                let (key, value) = option.split_once('=').unwrap_or((option, ""));
+
+                match key {
+                    "latency" => {
+                        let ms = u64::from_str(value)
+                            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+                        port.latency = Duration::from_millis(ms);
+                    }
+                    "errors" => {
+                        let rate = f64::from_str(value)
+                            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+                        if !(0.0..=1.0).contains(&rate) {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                "Loopback error rate must be between 0.0 and 1.0.",
+                            ));
+                        }
+                        port.error_rate = rate;
+                    }
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Unknown loopback option '{}'.", key),
+                        ))
+                    }
+                }
+            }
+        }
+
+        Ok(port)
+    }
+
+    /// Open two ports connected to each other, like a null modem cable
+    ///
+    /// Each end reads what the other writes, modem lines still loop back on each end
+    #[cfg(test)]
+    pub fn pair(settings: &SerialPortSettings) -> (Self, Self) {
+        let mut a = Self::open("loop://", settings).unwrap();
+        let mut b = Self::open("loop://", settings).unwrap();
+        a.remote = b.buffer.clone();
+        b.remote = a.buffer.clone();
+        (a, b)
+    }
+
+    /// Get the next pseudo random number in [0.0, 1.0)
+    fn next_random(&mut self) -> f64 {
+        // xorshift64
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+        (self.rng_state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Corrupt a byte based on the configured error rate
+    fn inject_error(&mut self, byte: u8) -> u8 {
+        if self.error_rate > 0.0 && self.next_random() < self.error_rate {
+            byte ^ (1 << (self.next_random() * 8.0) as u8)
+        } else {
+            byte
+        }
+    }
+}
+
+impl io::Read for LoopbackPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + self.settings.timeout;
+
+        loop {
+            let now = Instant::now();
+            let mut count = 0;
+            let mut buffer = self.buffer.lock().unwrap();
+
+            while count < buf.len() {
+                match buffer.front() {
+                    Some((ready, byte)) if *ready <= now => {
+                        buf[count] = *byte;
+                        count += 1;
+                        buffer.pop_front();
+                    }
+                    _ => break,
+                }
+            }
+
+            if count > 0 {
+                return Ok(count);
+            }
+
+            if now >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Operation timed out",
+                ));
+            }
+
+            // Sleep until the next byte is ready or the read times out, the other end of a pair
+            // can write in the meantime
+            let wake = match buffer.front() {
+                Some((ready, _)) if *ready < deadline => *ready,
+                _ => deadline,
+            };
+            drop(buffer);
+            thread::sleep((wake - now).min(POLL_INTERVAL));
+        }
+    }
+}
+
+impl io::Write for LoopbackPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let ready = Instant::now() + self.latency;
+        let bytes: Vec<u8> = buf.iter().map(|byte| self.inject_error(*byte)).collect();
+        self.remote
+            .lock()
+            .unwrap()
+            .extend(bytes.into_iter().map(|byte| (ready, byte)));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for LoopbackPort {
+    fn name(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
+
+    fn settings(&self) -> SerialPortSettings {
+        self.settings
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.settings.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(self.settings.data_bits)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(self.settings.flow_control)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(self.settings.parity)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(self.settings.stop_bits)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.settings.timeout
+    }
+
+    fn set_all(&mut self, settings: &SerialPortSettings) -> serialport::Result<()> {
+        self.settings = *settings;
+        Ok(())
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.settings.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.settings.data_bits = data_bits;
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.settings.flow_control = flow_control;
+        Ok(())
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.settings.parity = parity;
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.settings.stop_bits = stop_bits;
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.settings.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+        self.rts = level;
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+        self.dtr = level;
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(self.rts)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(self.dtr)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(self.dtr)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        let now = Instant::now();
+        Ok(self
+            .buffer
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(ready, _)| *ready <= now)
+            .count() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        // Written data is echoed immediately, so only the input buffer holds data
+        match buffer_to_clear {
+            ClearBuffer::Input | ClearBuffer::All => self.buffer.lock().unwrap().clear(),
+            ClearBuffer::Output => {}
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Err(Error::new(
+            ErrorKind::Unknown,
+            "Loopback ports can not be cloned.",
+        ))
+    }
+}
@@ -1,15 +1,25 @@
+pub mod exec;
+#[cfg(target_os = "linux")]
+pub mod line_errors;
+pub mod loopback;
+
 use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use serialport::{open_with_settings, SerialPort};
+#[cfg(not(target_os = "linux"))]
+use serialport::open_with_settings;
+use serialport::SerialPort;
 
 use crate::app::{error, CerialState};
+use crate::serial::exec::ChildPort;
+use crate::serial::loopback::{is_loopback_target, LoopbackPort};
 use crate::ui::DisplayUpdateEvent;
 
 /// Serial port telemetry
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub struct SerialTelemetry {
     /// Clear to send
     cts: bool,
@@ -19,6 +29,8 @@ pub struct SerialTelemetry {
     ri: bool,
     /// Data set ready
     dsr: bool,
+    /// Baud rate read back from the port, which can differ from the rate requested
+    pub baud_rate: Option<u32>,
 }
 
 impl Display for SerialTelemetry {
@@ -31,17 +43,6 @@ impl Display for SerialTelemetry {
     }
 }
 
-impl Default for SerialTelemetry {
-    fn default() -> Self {
-        Self {
-            cts: false,
-            cd: false,
-            ri: false,
-            dsr: false,
-        }
-    }
-}
-
 impl SerialTelemetry {
     /// Read serial telemetry from a serial port
     fn read_serial_telemetry(serial_port: &mut Box<dyn SerialPort>) -> SerialTelemetry {
@@ -49,8 +50,97 @@ impl SerialTelemetry {
             cts: serial_port.read_clear_to_send().unwrap_or(false),
             cd: serial_port.read_carrier_detect().unwrap_or(false),
             ri: serial_port.read_ring_indicator().unwrap_or(false),
-            dsr: serial_port.read_ring_indicator().unwrap_or(false),
+            dsr: serial_port.read_data_set_ready().unwrap_or(false),
+            baud_rate: serial_port.baud_rate().ok(),
+        }
+    }
+}
+
+/// Get a warning if the port is running at a different baud rate than requested
+pub fn baud_rate_warning(requested: u32, actual: u32) -> Option<String> {
+    if requested == actual {
+        None
+    } else {
+        Some(format!(
+            "Warning: requested {} baud but the driver set {}",
+            requested, actual
+        ))
+    }
+}
+
+/// Link counters shared with the serial threads
+#[derive(Debug, Default)]
+pub struct LinkCounters {
+    /// Bytes read from the port
+    pub rx: AtomicU64,
+    /// Bytes written to the port
+    pub tx: AtomicU64,
+    /// Failed reads
+    pub read_errors: AtomicU64,
+    /// Bytes the OS marked as received with a framing or parity error, or a break
+    pub marked_bytes: AtomicU64,
+    /// Framing errors counted by the driver
+    pub framing_errors: AtomicU64,
+    /// Parity errors counted by the driver
+    pub parity_errors: AtomicU64,
+    /// Receive overruns counted by the driver
+    pub overruns: AtomicU64,
+    /// Breaks counted by the driver
+    pub breaks: AtomicU64,
+}
+
+impl LinkCounters {
+    /// Set all counters back to zero
+    pub fn reset(&self) {
+        self.rx.store(0, Ordering::Relaxed);
+        self.tx.store(0, Ordering::Relaxed);
+        self.read_errors.store(0, Ordering::Relaxed);
+        self.marked_bytes.store(0, Ordering::Relaxed);
+        self.framing_errors.store(0, Ordering::Relaxed);
+        self.parity_errors.store(0, Ordering::Relaxed);
+        self.overruns.store(0, Ordering::Relaxed);
+        self.breaks.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Throughput measured from the link counters
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Throughput {
+    /// Time and byte totals of the last sample
+    sample: Option<(Instant, u64, u64)>,
+    /// Current receive rate in bytes per second
+    pub rx_rate: f64,
+    /// Current transmit rate in bytes per second
+    pub tx_rate: f64,
+    /// Highest receive rate since the last reset
+    pub rx_peak: f64,
+    /// Highest transmit rate since the last reset
+    pub tx_peak: f64,
+}
+
+impl Throughput {
+    /// Rates are averaged over this long
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Sample the counters, updating the rates once per interval
+    pub fn update(&mut self, counters: &LinkCounters) {
+        let now = Instant::now();
+        let rx = counters.rx.load(Ordering::Relaxed);
+        let tx = counters.tx.load(Ordering::Relaxed);
+
+        if let Some((time, last_rx, last_tx)) = self.sample {
+            let elapsed = now.duration_since(time);
+            if elapsed < Self::INTERVAL {
+                return;
+            }
+            let secs = elapsed.as_secs_f64();
+            // Counters may have been reset since the last sample
+            self.rx_rate = rx.saturating_sub(last_rx) as f64 / secs;
+            self.tx_rate = tx.saturating_sub(last_tx) as f64 / secs;
+            self.rx_peak = self.rx_peak.max(self.rx_rate);
+            self.tx_peak = self.tx_peak.max(self.tx_rate);
         }
+        self.sample = Some((now, rx, tx));
     }
 }
 
@@ -60,6 +150,7 @@ impl SerialTelemetry {
 pub fn serial_rx_thread(
     tx: Sender<DisplayUpdateEvent>,
     serial_port: Arc<Mutex<Box<dyn SerialPort>>>,
+    counters: Arc<LinkCounters>,
 ) {
     loop {
         // Grab lock on serial port
@@ -69,6 +160,8 @@ pub fn serial_rx_thread(
             // Block for read (timeout has also been set)
             match serial_port.read(&mut buffer) {
                 Ok(count) => {
+                    counters.rx.fetch_add(count as u64, Ordering::Relaxed);
+
                     // Try to send all bytes received to the display loop
                     if tx
                         .send(DisplayUpdateEvent::SerialInput(
@@ -81,11 +174,12 @@ pub fn serial_rx_thread(
                     }
                 }
                 Err(err) => match err.kind() {
-                    // Ignore timout
-                    std::io::ErrorKind::TimedOut => {}
-                    // On error, print error and exit
-                    err => {
-                        println!("Error {:?}", err);
+                    // Timeouts just mean nothing was received, and interrupted reads are retried
+                    std::io::ErrorKind::TimedOut | std::io::ErrorKind::Interrupted => {}
+                    // On error, report error and exit
+                    _ => {
+                        counters.read_errors.fetch_add(1, Ordering::Relaxed);
+                        tx.send(DisplayUpdateEvent::SerialError(err)).ok();
                         break;
                     }
                 },
@@ -111,7 +205,11 @@ pub fn serial_rx_thread(
 /// Serial TX thread
 ///
 /// Handles writing data to a serial port
-pub fn serial_tx_thread(rx: Receiver<Vec<u8>>, serial_port: Arc<Mutex<Box<dyn SerialPort>>>) {
+pub fn serial_tx_thread(
+    rx: Receiver<Vec<u8>>,
+    serial_port: Arc<Mutex<Box<dyn SerialPort>>>,
+    counters: Arc<LinkCounters>,
+) {
     // Wait for data to be available
     while let Ok(buffer) = rx.recv() {
         // Lock serial port
@@ -120,6 +218,9 @@ pub fn serial_tx_thread(rx: Receiver<Vec<u8>>, serial_port: Arc<Mutex<Box<dyn Se
             if serial_port.write_all(buffer.as_slice()).is_err() {
                 break;
             }
+            counters
+                .tx
+                .fetch_add(buffer.len() as u64, Ordering::Relaxed);
         } else {
             // Break if mutex lock fails
             break;
@@ -127,10 +228,70 @@ pub fn serial_tx_thread(rx: Receiver<Vec<u8>>, serial_port: Arc<Mutex<Box<dyn Se
     }
 }
 
+/// Open a port based on the application state
+///
+/// `loop://` and `echo://` targets open a virtual loopback device, and an exec command
+/// spawns a child process, instead of opening hardware
+fn open_port(
+    cerial_state: &CerialState,
+    display_update_tx: &Sender<DisplayUpdateEvent>,
+) -> error::Result<Box<dyn SerialPort>> {
+    let serialport: Box<dyn SerialPort> = if let Some(command) = &cerial_state.exec_command {
+        Box::new(ChildPort::spawn(
+            command,
+            &cerial_state.serial_settings,
+            display_update_tx.clone(),
+        )?)
+    } else if is_loopback_target(&cerial_state.serial_dev) {
+        Box::new(LoopbackPort::open(
+            &cerial_state.serial_dev,
+            &cerial_state.serial_settings,
+        )?)
+    } else {
+        open_hardware_port(cerial_state)?
+    };
+    Ok(serialport)
+}
+
+/// Open a hardware serial port, counting line errors where the OS reports them
+#[cfg(target_os = "linux")]
+fn open_hardware_port(cerial_state: &CerialState) -> error::Result<Box<dyn SerialPort>> {
+    Ok(Box::new(line_errors::LineErrorPort::open(
+        &cerial_state.serial_dev,
+        &cerial_state.serial_settings,
+        cerial_state.link_counters.clone(),
+        cerial_state.mark_errors,
+    )?))
+}
+
+/// Open a hardware serial port
+#[cfg(not(target_os = "linux"))]
+fn open_hardware_port(cerial_state: &CerialState) -> error::Result<Box<dyn SerialPort>> {
+    Ok(open_with_settings(
+        &cerial_state.serial_dev,
+        &cerial_state.serial_settings,
+    )?)
+}
+
 /// Open a serial port based on the application state
 pub fn open_serial_port(
     cerial_state: &CerialState,
+    display_update_tx: &Sender<DisplayUpdateEvent>,
 ) -> error::Result<Arc<Mutex<Box<dyn SerialPort>>>> {
-    let serialport = open_with_settings(&cerial_state.serial_dev, &cerial_state.serial_settings)?;
-    Ok(Arc::new(Mutex::new(serialport)))
+    Ok(Arc::new(Mutex::new(open_port(
+        cerial_state,
+        display_update_tx,
+    )?)))
+}
+
+/// Replace an open serial port with a newly opened one based on the application state
+pub fn reopen_serial_port(
+    serial_port: &Arc<Mutex<Box<dyn SerialPort>>>,
+    cerial_state: &CerialState,
+    display_update_tx: &Sender<DisplayUpdateEvent>,
+) -> error::Result<()> {
+    if let Ok(mut serial_port) = serial_port.lock() {
+        *serial_port = open_port(cerial_state, display_update_tx)?;
+    }
+    Ok(())
 }
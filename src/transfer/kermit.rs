@@ -0,0 +1,1043 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::checksum::crc16_kermit;
+use crate::transfer::{TransferContext, TransferError, TransferProtocol};
+
+/// Start of packet
+const MARK: u8 = 0x01;
+/// End of packet
+const EOL: u8 = b'\r';
+/// Control character prefix
+const QCTL: u8 = b'#';
+/// Repeat count prefix
+const REPT: u8 = b'~';
+
+/// Send-Init
+const SEND_INIT: u8 = b'S';
+/// File header
+const FILE_HEADER: u8 = b'F';
+/// File attributes
+const ATTRIBUTES: u8 = b'A';
+/// File data
+const DATA: u8 = b'D';
+/// End of file
+const EOF: u8 = b'Z';
+/// End of transaction
+const BREAK: u8 = b'B';
+/// Acknowledge
+const ACK: u8 = b'Y';
+/// Negative acknowledge
+const NAK: u8 = b'N';
+/// Fatal error
+const ERROR: u8 = b'E';
+
+/// Capability: long packets
+const CAPAS_LONG: u8 = 0x02;
+/// Capability: attribute packets
+const CAPAS_ATTRIBUTES: u8 = 0x08;
+
+/// Largest normal packet length
+const MAXL: usize = 94;
+/// Largest long packet length we receive or send
+const MAXLX: usize = 4096;
+/// Long packet length assumed when the peer supports long packets but doesn't give one
+const DEFAULT_MAXLX: usize = 500;
+/// Data packet size used at the start of a transfer
+const START_DATA_SIZE: usize = 1024;
+/// Smallest data packet size used on noisy links
+const MIN_DATA_SIZE: usize = 32;
+/// Timeout requested from the peer in seconds
+const TIMEOUT_SECS: usize = 5;
+
+/// Max number of consecutive errors before giving up
+const MAX_RETRIES: u32 = 10;
+/// Time to wait for a packet
+const PACKET_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time to wait for each byte within a packet
+const BYTE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Encode a small number as a printable character
+fn tochar(x: usize) -> u8 {
+    (x as u8).wrapping_add(32)
+}
+
+/// Decode a printable character to a small number
+fn unchar(c: u8) -> usize {
+    c.wrapping_sub(32) as usize
+}
+
+/// Check if a character can be used as a prefix
+fn is_prefix(c: u8) -> bool {
+    matches!(c, 33..=62 | 96..=126)
+}
+
+/// Single character block check, type 1
+fn check1(data: &[u8]) -> u8 {
+    let sum: usize = data.iter().map(|b| *b as usize).sum();
+    tochar((sum + ((sum & 0xc0) >> 6)) & 0x3f)
+}
+
+/// Compute a block check of the given type, `b'1'`, `b'2'`, or `b'3'`
+fn block_check(check_type: u8, data: &[u8]) -> Vec<u8> {
+    match check_type {
+        b'2' => {
+            let sum = data.iter().map(|b| *b as usize).sum::<usize>() & 0xfff;
+            vec![tochar(sum >> 6), tochar(sum & 0x3f)]
+        }
+        b'3' => {
+            let crc = crc16_kermit(data) as usize;
+            vec![
+                tochar((crc >> 12) & 0x0f),
+                tochar((crc >> 6) & 0x3f),
+                tochar(crc & 0x3f),
+            ]
+        }
+        _ => vec![check1(data)],
+    }
+}
+
+/// Length of a block check type
+fn check_len(check_type: u8) -> usize {
+    match check_type {
+        b'2' => 2,
+        b'3' => 3,
+        _ => 1,
+    }
+}
+
+/// Received packet
+struct Packet {
+    /// Sequence number
+    seq: u8,
+    /// Packet type
+    packet_type: u8,
+    /// Encoded packet data
+    data: Vec<u8>,
+}
+
+/// Send-Init parameters
+#[derive(Debug, Clone)]
+struct Params {
+    /// Longest normal packet the sender of these parameters can receive
+    maxl: usize,
+    /// Prefix used for 8th bit quoting, or `b'Y'`/`b'N'`
+    qbin: u8,
+    /// Control character prefix used by the sender of these parameters
+    qctl: u8,
+    /// Block check type
+    chkt: u8,
+    /// Repeat prefix, or a space if not supported
+    rept: u8,
+    /// Capability bits
+    capas: u8,
+    /// Longest long packet the sender of these parameters can receive
+    maxlx: usize,
+}
+
+impl Params {
+    /// Local parameters
+    fn local() -> Self {
+        Self {
+            maxl: MAXL,
+            qbin: b'Y',
+            qctl: QCTL,
+            chkt: b'3',
+            rept: REPT,
+            capas: CAPAS_LONG | CAPAS_ATTRIBUTES,
+            maxlx: MAXLX,
+        }
+    }
+
+    /// Encode as Send-Init packet data
+    fn encode(&self) -> Vec<u8> {
+        vec![
+            tochar(self.maxl),
+            tochar(TIMEOUT_SECS),
+            tochar(0),
+            b'@',
+            tochar(EOL as usize),
+            self.qctl,
+            self.qbin,
+            self.chkt,
+            self.rept,
+            tochar(self.capas as usize),
+            tochar(1),
+            tochar(self.maxlx / 95),
+            tochar(self.maxlx % 95),
+        ]
+    }
+
+    /// Decode Send-Init packet data, missing fields get their default values
+    fn decode(data: &[u8]) -> Self {
+        let field = |i: usize| data.get(i).copied().filter(|c| *c != b' ');
+
+        let capas = field(9).map(|c| unchar(c) as u8).unwrap_or(0);
+        let maxlx = match (field(11), field(12)) {
+            (Some(x1), Some(x2)) => unchar(x1) * 95 + unchar(x2),
+            _ => DEFAULT_MAXLX,
+        };
+
+        Self {
+            maxl: field(0).map(unchar).unwrap_or(80).clamp(10, MAXL),
+            qctl: field(5).unwrap_or(QCTL),
+            qbin: field(6).unwrap_or(b'N'),
+            chkt: field(7).unwrap_or(b'1'),
+            rept: field(8).unwrap_or(b' '),
+            capas,
+            maxlx,
+        }
+    }
+}
+
+/// Settings negotiated with the peer
+#[derive(Debug, Clone)]
+struct Session {
+    /// Block check type
+    check_type: u8,
+    /// Control prefix the peer uses
+    peer_qctl: u8,
+    /// 8th bit prefix, if in use
+    qbin: Option<u8>,
+    /// Repeat prefix, if in use
+    rept: Option<u8>,
+    /// Max encoded data bytes per packet sent
+    max_data: usize,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        // Settings used until the Send-Init exchange is done
+        Self {
+            check_type: b'1',
+            peer_qctl: QCTL,
+            qbin: None,
+            rept: None,
+            max_data: MAXL - 2 - 1,
+        }
+    }
+}
+
+impl Session {
+    /// Negotiate a session from both sides' Send-Init parameters
+    fn negotiate(local: &Params, peer: &Params) -> Self {
+        let check_type = if local.chkt == peer.chkt && matches!(peer.chkt, b'1'..=b'3') {
+            peer.chkt
+        } else {
+            b'1'
+        };
+
+        let qbin = match (local.qbin, peer.qbin) {
+            (b'Y', c) | (c, b'Y') if is_prefix(c) => Some(c),
+            (a, b) if a == b && is_prefix(a) => Some(a),
+            _ => None,
+        };
+
+        let rept = if local.rept == peer.rept && is_prefix(peer.rept) {
+            Some(peer.rept)
+        } else {
+            None
+        };
+
+        let long_packets = local.capas & peer.capas & CAPAS_LONG != 0 && peer.maxlx > MAXL;
+        let max_data = if long_packets {
+            peer.maxlx.min(MAXLX) - 9 - check_len(check_type)
+        } else {
+            peer.maxl - 2 - check_len(check_type)
+        };
+
+        Self {
+            check_type,
+            peer_qctl: peer.qctl,
+            qbin,
+            rept,
+            max_data,
+        }
+    }
+
+    /// Encode a single byte, repeated `count` times
+    fn encode_byte(&self, byte: u8, count: usize, out: &mut Vec<u8>) {
+        if let Some(rept) = self.rept {
+            if count > 1 {
+                out.push(rept);
+                out.push(tochar(count));
+            }
+        }
+
+        let mut byte = byte;
+        if let Some(qbin) = self.qbin {
+            if byte & 0x80 != 0 {
+                out.push(qbin);
+                byte &= 0x7f;
+            }
+        }
+
+        let low = byte & 0x7f;
+        if low < 32 || low == 127 {
+            out.push(QCTL);
+            out.push(byte ^ 0x40);
+        } else if low == QCTL || Some(low) == self.qbin || Some(low) == self.rept {
+            out.push(QCTL);
+            out.push(byte);
+        } else {
+            out.push(byte);
+        }
+    }
+
+    /// Encode up to `max_len` bytes of packet data
+    ///
+    /// Returns the encoded data and the number of bytes of `data` it holds
+    fn encode(&self, data: &[u8], max_len: usize) -> (Vec<u8>, usize) {
+        let mut out = Vec::with_capacity(max_len);
+        let mut unit = Vec::with_capacity(5);
+        let mut consumed = 0;
+
+        while consumed < data.len() {
+            let byte = data[consumed];
+
+            // Only use a repeat count when it saves space
+            let mut count = 1;
+            if self.rept.is_some() {
+                while count < 94 && consumed + count < data.len() && data[consumed + count] == byte
+                {
+                    count += 1;
+                }
+                if count < 4 {
+                    count = 1;
+                }
+            }
+
+            unit.clear();
+            self.encode_byte(byte, count, &mut unit);
+
+            if out.len() + unit.len() > max_len {
+                break;
+            }
+            out.extend_from_slice(&unit);
+            consumed += count;
+        }
+
+        (out, consumed)
+    }
+
+    /// Decode packet data
+    fn decode(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        let mut iter = data.iter().copied();
+
+        while let Some(mut c) = iter.next() {
+            let mut count = 1;
+            if Some(c) == self.rept {
+                count = iter.next().map(unchar).unwrap_or(1);
+                c = match iter.next() {
+                    Some(c) => c,
+                    None => break,
+                };
+            }
+
+            let mut high = 0;
+            if Some(c) == self.qbin {
+                high = 0x80;
+                c = match iter.next() {
+                    Some(c) => c,
+                    None => break,
+                };
+            }
+
+            if c == self.peer_qctl {
+                c = match iter.next() {
+                    Some(c) => c,
+                    None => break,
+                };
+                let low = c & 0x7f;
+                if (0x40..=0x5f).contains(&low) || low == 0x3f {
+                    c ^= 0x40;
+                }
+            }
+
+            for _ in 0..count {
+                out.push(c | high);
+            }
+        }
+
+        out
+    }
+
+    /// Build a packet
+    fn packet(&self, packet_type: u8, seq: u8, data: &[u8]) -> Vec<u8> {
+        // Send-Init always uses a type 1 block check
+        let check_type = match packet_type {
+            SEND_INIT => b'1',
+            _ => self.check_type,
+        };
+        let len = 2 + data.len() + check_len(check_type);
+
+        let mut packet = vec![MARK];
+        if len <= MAXL {
+            packet.extend_from_slice(&[tochar(len), tochar(seq as usize), packet_type]);
+        } else {
+            let lenx = data.len() + check_len(check_type);
+            packet.extend_from_slice(&[
+                tochar(0),
+                tochar(seq as usize),
+                packet_type,
+                tochar(lenx / 95),
+                tochar(lenx % 95),
+            ]);
+            let hcheck = check1(&packet[1..]);
+            packet.push(hcheck);
+        }
+        packet.extend_from_slice(data);
+
+        let check = block_check(check_type, &packet[1..]);
+        packet.extend(check);
+        packet.push(EOL);
+        packet
+    }
+}
+
+/// Kermit implementation
+#[derive(Default)]
+pub struct Kermit {
+    /// Negotiated session settings
+    session: Session,
+    /// Sequence number of the current packet
+    seq: u8,
+    /// Current data packet size
+    data_size: usize,
+}
+
+impl Kermit {
+    /// Tell the peer about an error it doesn't know about
+    fn error_on_error<T>(
+        &self,
+        ctx: &mut TransferContext,
+        res: Result<T, TransferError>,
+    ) -> Result<T, TransferError> {
+        if let Err(e) = &res {
+            match e {
+                TransferError::RemoteCancelled | TransferError::Protocol(_) => {}
+                _ => {
+                    let (msg, _) = self
+                        .session
+                        .encode(e.to_string().as_bytes(), self.session.max_data);
+                    ctx.write_all(&self.session.packet(ERROR, self.seq, &msg))
+                        .ok();
+                }
+            }
+        }
+        res
+    }
+
+    /// Read a packet
+    ///
+    /// Returns `None` on a timeout or a corrupt packet
+    fn read_packet(
+        &self,
+        ctx: &mut TransferContext,
+        timeout: Duration,
+    ) -> Result<Option<Packet>, TransferError> {
+        // Find the start of a packet
+        loop {
+            match ctx.read_byte(timeout)? {
+                Some(MARK) => break,
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+
+        let mut header = [0u8; 3];
+        match ctx.read_exact(&mut header, BYTE_TIMEOUT) {
+            Ok(()) => {}
+            Err(TransferError::Timeout) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let seq = unchar(header[1]);
+        let packet_type = header[2];
+        if seq > 63 {
+            return Ok(None);
+        }
+
+        // Send-Init always uses a type 1 block check
+        let check_type = match packet_type {
+            SEND_INIT => b'1',
+            _ => self.session.check_type,
+        };
+
+        let mut packet = header.to_vec();
+        let len = unchar(header[0]);
+        let rest = match len {
+            0 => {
+                // Long packet
+                let mut lenx = [0u8; 3];
+                match ctx.read_exact(&mut lenx, BYTE_TIMEOUT) {
+                    Ok(()) => {}
+                    Err(TransferError::Timeout) => return Ok(None),
+                    Err(e) => return Err(e),
+                }
+                packet.extend_from_slice(&lenx[..2]);
+                if check1(&packet) != lenx[2] {
+                    ctx.add_check_error();
+                    return Ok(None);
+                }
+                packet.push(lenx[2]);
+                unchar(lenx[0]) * 95 + unchar(lenx[1])
+            }
+            len if (2 + check_len(check_type)..=MAXL).contains(&len) => len - 2,
+            _ => return Ok(None),
+        };
+
+        if rest < check_len(check_type) || rest > MAXLX {
+            return Ok(None);
+        }
+
+        let mut body = vec![0u8; rest];
+        match ctx.read_exact(&mut body, BYTE_TIMEOUT) {
+            Ok(()) => {}
+            Err(TransferError::Timeout) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let data_len = rest - check_len(check_type);
+        packet.extend_from_slice(&body[..data_len]);
+        if block_check(check_type, &packet) != body[data_len..] {
+            ctx.add_check_error();
+            return Ok(None);
+        }
+
+        Ok(Some(Packet {
+            seq: seq as u8,
+            packet_type,
+            data: body[..data_len].to_vec(),
+        }))
+    }
+
+    /// Build the error for an error packet from the peer
+    fn remote_error(&self, packet: &Packet) -> TransferError {
+        let msg = String::from_utf8_lossy(&self.session.decode(&packet.data)).to_string();
+        TransferError::Protocol(format!("Remote error: {}", msg))
+    }
+
+    /// Wait for the current packet to be acknowledged
+    ///
+    /// Returns the acknowledgement data, or `None` if the packet needs to be sent again
+    fn wait_for_ack(
+        &mut self,
+        ctx: &mut TransferContext,
+    ) -> Result<Option<Vec<u8>>, TransferError> {
+        let next = (self.seq + 1) % 64;
+
+        loop {
+            match self.read_packet(ctx, PACKET_TIMEOUT)? {
+                Some(response) if response.packet_type == ACK && response.seq == self.seq => {
+                    self.seq = next;
+                    return Ok(Some(response.data));
+                }
+                // A NAK for the next packet means this one was received
+                Some(response) if response.packet_type == NAK && response.seq == next => {
+                    self.seq = next;
+                    return Ok(Some(Vec::new()));
+                }
+                Some(response) if response.packet_type == ERROR => {
+                    return Err(self.remote_error(&response))
+                }
+                // Stale acknowledgements
+                Some(response) if response.packet_type == ACK => {}
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Count a failed attempt at sending a packet
+    fn add_retry(ctx: &mut TransferContext, errors: &mut u32) -> Result<(), TransferError> {
+        *errors += 1;
+        if *errors > MAX_RETRIES {
+            return Err(TransferError::TooManyRetries);
+        }
+        ctx.add_retry();
+        Ok(())
+    }
+
+    /// Send a packet and wait for it to be acknowledged, returning the acknowledgement data
+    fn send_packet(
+        &mut self,
+        ctx: &mut TransferContext,
+        packet_type: u8,
+        data: &[u8],
+    ) -> Result<Vec<u8>, TransferError> {
+        let packet = self.session.packet(packet_type, self.seq, data);
+        let mut errors = 0;
+
+        loop {
+            ctx.write_all(&packet)?;
+            match self.wait_for_ack(ctx)? {
+                Some(ack) => return Ok(ack),
+                None => Self::add_retry(ctx, &mut errors)?,
+            }
+        }
+    }
+
+    /// Send as much data as fits in a data packet
+    ///
+    /// Packets shrink after errors so noisy links still make progress, and grow back after
+    /// they get through. A packet is never changed once sent, as the receiver may have it
+    /// already. Returns the acknowledgement data and the number of bytes sent.
+    fn send_data(
+        &mut self,
+        ctx: &mut TransferContext,
+        data: &[u8],
+    ) -> Result<(Vec<u8>, usize), TransferError> {
+        let (encoded, count) = self.session.encode(data, self.data_size);
+        let packet = self.session.packet(DATA, self.seq, &encoded);
+        let mut errors = 0;
+
+        loop {
+            ctx.write_all(&packet)?;
+            match self.wait_for_ack(ctx)? {
+                Some(ack) => {
+                    if errors == 0 {
+                        self.data_size =
+                            (self.data_size + self.data_size / 4).min(self.session.max_data);
+                    }
+                    return Ok((ack, count));
+                }
+                None => {
+                    Self::add_retry(ctx, &mut errors)?;
+                    self.data_size = (self.data_size / 2).max(MIN_DATA_SIZE);
+                }
+            }
+        }
+    }
+
+    /// Send a single file, returns false if the receiver cancelled the rest of the batch
+    fn send_file(
+        &mut self,
+        ctx: &mut TransferContext,
+        file_name: &str,
+        data: &[u8],
+        attributes: bool,
+    ) -> Result<bool, TransferError> {
+        let (name, _) = self
+            .session
+            .encode(file_name.as_bytes(), self.session.max_data);
+        self.send_packet(ctx, FILE_HEADER, &name)?;
+
+        if attributes {
+            let size = data.len().to_string();
+            let mut attrs = vec![b'1', tochar(size.len())];
+            attrs.extend_from_slice(size.as_bytes());
+            let response = self.send_packet(ctx, ATTRIBUTES, &attrs)?;
+            if response.first() == Some(&b'N') {
+                self.send_packet(ctx, EOF, b"D")?;
+                return Ok(true);
+            }
+        }
+
+        let mut sent = 0;
+        while sent < data.len() {
+            let (response, count) = self.send_data(ctx, &data[sent..])?;
+            sent += count;
+            ctx.add_bytes(count as u64);
+
+            // The receiver can cancel this file, or the whole batch
+            match response.first() {
+                Some(b'X') => {
+                    self.send_packet(ctx, EOF, b"D")?;
+                    return Ok(true);
+                }
+                Some(b'Z') => {
+                    self.send_packet(ctx, EOF, b"D")?;
+                    return Ok(false);
+                }
+                _ => {}
+            }
+        }
+
+        self.send_packet(ctx, EOF, &[])?;
+        Ok(true)
+    }
+
+    /// Send files
+    fn send_files(
+        &mut self,
+        ctx: &mut TransferContext,
+        files: &[PathBuf],
+    ) -> Result<(), TransferError> {
+        let local = Params::local();
+        let response = self.send_packet(ctx, SEND_INIT, &local.encode())?;
+        let peer = Params::decode(&response);
+        self.session = Session::negotiate(&local, &peer);
+        self.data_size = self.session.max_data.min(START_DATA_SIZE);
+        let attributes = peer.capas & CAPAS_ATTRIBUTES != 0;
+
+        for file in files {
+            let data = fs::read(file)?;
+            let file_name = file
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            ctx.start_file(&file_name, Some(data.len() as u64));
+
+            if !self.send_file(ctx, &file_name, &data, attributes)? {
+                break;
+            }
+        }
+
+        self.send_packet(ctx, BREAK, &[])?;
+        Ok(())
+    }
+
+    /// Parse the file size from attribute packet data
+    fn attribute_size(attrs: &[u8]) -> Option<u64> {
+        let mut i = 0;
+        while i + 1 < attrs.len() {
+            let tag = attrs[i];
+            let len = unchar(attrs[i + 1]);
+            let value = attrs.get(i + 2..i + 2 + len)?;
+            match tag {
+                b'1' => return std::str::from_utf8(value).ok()?.parse().ok(),
+                b'!' => {
+                    let kb: u64 = std::str::from_utf8(value).ok()?.parse().ok()?;
+                    return Some(kb * 1024);
+                }
+                _ => {}
+            }
+            i += 2 + len;
+        }
+        None
+    }
+
+    /// Receive files into a directory
+    fn receive_files(
+        &mut self,
+        ctx: &mut TransferContext,
+        dest: &Path,
+    ) -> Result<(), TransferError> {
+        fs::create_dir_all(dest)?;
+
+        let local = Params::local();
+        // Open file and its path
+        let mut file: Option<(File, PathBuf)> = None;
+        let mut last_ack: Option<Vec<u8>> = None;
+        let mut errors = 0;
+        self.seq = 0;
+
+        loop {
+            let packet = match self.read_packet(ctx, PACKET_TIMEOUT)? {
+                Some(packet) => packet,
+                None => {
+                    errors += 1;
+                    if errors > MAX_RETRIES {
+                        return Err(TransferError::TooManyRetries);
+                    }
+                    ctx.add_retry();
+                    ctx.write_all(&self.session.packet(NAK, self.seq, &[]))?;
+                    continue;
+                }
+            };
+
+            if packet.packet_type == ERROR {
+                return Err(self.remote_error(&packet));
+            }
+            errors = 0;
+
+            if packet.seq != self.seq {
+                // Our acknowledgement was lost, send it again
+                if packet.seq == (self.seq + 63) % 64 {
+                    if let Some(ack) = &last_ack {
+                        ctx.write_all(ack)?;
+                    }
+                } else {
+                    ctx.write_all(&self.session.packet(NAK, self.seq, &[]))?;
+                }
+                continue;
+            }
+
+            let mut ack_data = Vec::new();
+            let mut done = false;
+            match packet.packet_type {
+                SEND_INIT => {
+                    let peer = Params::decode(&packet.data);
+                    // The acknowledgement is sent before switching to the negotiated block check
+                    let ack = self.session.packet(ACK, self.seq, &local.encode());
+                    self.session = Session::negotiate(&local, &peer);
+                    ctx.write_all(&ack)?;
+                    last_ack = Some(ack);
+                    self.seq = (self.seq + 1) % 64;
+                    continue;
+                }
+                FILE_HEADER => {
+                    let name =
+                        String::from_utf8_lossy(&self.session.decode(&packet.data)).to_string();
+
+                    // Never trust the sender with a path
+                    let file_name = Path::new(&name)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .ok_or_else(|| {
+                            TransferError::Protocol(format!("Invalid file name '{}'", name))
+                        })?;
+
+                    ctx.start_file(&file_name, None);
+                    let path = dest.join(&file_name);
+                    file = Some((File::create(&path)?, path));
+                }
+                ATTRIBUTES => {
+                    if let Some(size) = Self::attribute_size(&packet.data) {
+                        let file_name = ctx.progress.file_name.clone();
+                        ctx.start_file(&file_name, Some(size));
+                    }
+                    ack_data = vec![b'Y'];
+                }
+                DATA => {
+                    let data = self.session.decode(&packet.data);
+                    match &mut file {
+                        Some((file, _)) => file.write_all(&data)?,
+                        None => {
+                            return Err(TransferError::Protocol(
+                                "Data received before a file header".to_string(),
+                            ))
+                        }
+                    }
+                    ctx.add_bytes(data.len() as u64);
+                }
+                EOF => {
+                    if let Some((file, path)) = file.take() {
+                        drop(file);
+                        // The sender discarded the file
+                        if packet.data.first() == Some(&b'D') {
+                            fs::remove_file(path)?;
+                        }
+                    }
+                }
+                BREAK => done = true,
+                other => {
+                    return Err(TransferError::Protocol(format!(
+                        "Unexpected packet type '{}'",
+                        other as char
+                    )))
+                }
+            }
+
+            let ack = self.session.packet(ACK, self.seq, &ack_data);
+            ctx.write_all(&ack)?;
+            last_ack = Some(ack);
+            self.seq = (self.seq + 1) % 64;
+
+            if done {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl TransferProtocol for Kermit {
+    fn send(&mut self, ctx: &mut TransferContext, files: &[PathBuf]) -> Result<(), TransferError> {
+        let res = self.send_files(ctx, files);
+        self.error_on_error(ctx, res)
+    }
+
+    fn receive(&mut self, ctx: &mut TransferContext, dest: &Path) -> Result<(), TransferError> {
+        let res = self.receive_files(ctx, dest);
+        self.error_on_error(ctx, res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::loopback::LoopbackPort;
+    use crate::transfer::tests::{round_trip, temp_dir};
+    use crate::transfer::{Direction, Protocol};
+    use serialport::SerialPortSettings;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    /// Session with every kind of prefixing and long packets
+    fn session(qbin: Option<u8>) -> Session {
+        Session {
+            check_type: b'3',
+            peer_qctl: QCTL,
+            qbin,
+            rept: Some(REPT),
+            max_data: MAXLX - 9 - 3,
+        }
+    }
+
+    /// Read a packet from received data with a session
+    fn parse(kermit: &Kermit, received: Vec<u8>) -> (Option<Packet>, u32) {
+        let mut port = LoopbackPort::open("loop://", &SerialPortSettings::default()).unwrap();
+        let mut ctx = TransferContext::new(
+            &mut port,
+            Protocol::Kermit,
+            Direction::Receive,
+            received,
+            Arc::new(AtomicBool::new(false)),
+            Box::new(|_| {}),
+        );
+        let packet = kermit
+            .read_packet(&mut ctx, Duration::from_millis(10))
+            .unwrap();
+        (packet, ctx.progress.check_errors)
+    }
+
+    #[test]
+    fn prefixing() {
+        let plain = session(None);
+        let (encoded, count) = plain.encode(b"a\x01\x7f#~\x81b", 100);
+        assert_eq!(count, 7);
+        // Control characters, including ones with the 8th bit set, and prefix characters
+        assert_eq!(encoded, b"a#A#?###~#\xc1b");
+        assert_eq!(plain.decode(&encoded), b"a\x01\x7f#~\x81b");
+
+        let eight_bit = session(Some(b'&'));
+        let (encoded, _) = eight_bit.encode(b"\x81\xe1&", 100);
+        assert_eq!(encoded, b"&#A&a#&");
+        assert_eq!(eight_bit.decode(&encoded), b"\x81\xe1&");
+    }
+
+    #[test]
+    fn repeats() {
+        let session = session(None);
+        // Runs shorter than four aren't worth a repeat count
+        let (encoded, _) = session.encode(b"aaabbbbbbbbbb", 100);
+        assert_eq!(encoded, b"aaa~*b");
+        assert_eq!(session.decode(&encoded), b"aaabbbbbbbbbb");
+
+        // Counts go up to 94
+        let (encoded, _) = session.encode(&[0; 200], 100);
+        assert_eq!(encoded, b"~~#@~~#@~,#@");
+        assert_eq!(session.decode(&encoded), [0; 200]);
+    }
+
+    #[test]
+    fn encode_fills_packet() {
+        let session = session(None);
+        // A prefixed byte isn't split across packets
+        let (encoded, count) = session.encode(b"abc\x01", 4);
+        assert_eq!((encoded.as_slice(), count), (&b"abc"[..], 3));
+
+        let data: Vec<u8> = (0..=255).collect();
+        let mut decoded = Vec::new();
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let (encoded, count) = session.encode(rest, 20);
+            assert!(encoded.len() <= 20);
+            decoded.extend(session.decode(&encoded));
+            rest = &rest[count..];
+        }
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn negotiation() {
+        let local = Params::local();
+        let peer = Params::decode(&local.encode());
+        assert_eq!(peer.maxlx, MAXLX);
+        let session = Session::negotiate(&local, &peer);
+        assert_eq!(session.check_type, b'3');
+        assert_eq!(session.rept, Some(REPT));
+        assert_eq!(session.qbin, None);
+        assert_eq!(session.max_data, MAXLX - 9 - 3);
+
+        // A minimal peer gets short packets and type 1 checks
+        let minimal = Params::decode(&[tochar(80), tochar(5)]);
+        let session = Session::negotiate(&local, &minimal);
+        assert_eq!(session.check_type, b'1');
+        assert_eq!(session.rept, None);
+        assert_eq!(session.max_data, 80 - 2 - 1);
+
+        // A peer asking for 8th bit prefixing gets it
+        let mut eight_bit = local.clone();
+        eight_bit.qbin = b'&';
+        assert_eq!(Session::negotiate(&local, &eight_bit).qbin, Some(b'&'));
+    }
+
+    #[test]
+    fn packets() {
+        let kermit = Kermit {
+            session: session(None),
+            ..Default::default()
+        };
+
+        let packet = kermit.session.packet(DATA, 5, b"hello");
+        assert_eq!(&packet[..4], &[MARK, tochar(2 + 5 + 3), tochar(5), DATA]);
+        let (parsed, _) = parse(&kermit, packet);
+        let parsed = parsed.unwrap();
+        assert_eq!((parsed.seq, parsed.packet_type), (5, DATA));
+        assert_eq!(parsed.data, b"hello");
+
+        // Data too long for a normal packet is sent as a long packet
+        let data = vec![b'x'; 1000];
+        let packet = kermit.session.packet(DATA, 63, &data);
+        assert_eq!(packet[1], tochar(0));
+        let (parsed, _) = parse(&kermit, packet);
+        assert_eq!(parsed.unwrap().data, data);
+
+        // Send-Init always uses a type 1 check
+        let packet = kermit.session.packet(SEND_INIT, 0, b"abc");
+        assert_eq!(packet.len(), 1 + 3 + 3 + 1 + 1);
+        assert!(parse(&kermit, packet).0.is_some());
+    }
+
+    #[test]
+    fn corrupt_packets() {
+        let kermit = Kermit {
+            session: session(None),
+            ..Default::default()
+        };
+
+        let mut packet = kermit.session.packet(DATA, 1, b"hello");
+        packet[5] ^= 1;
+        let (read_packet, check_errors) = parse(&kermit, packet);
+        assert!(read_packet.is_none());
+        assert_eq!(check_errors, 1);
+
+        // A bad long packet header check
+        let mut packet = kermit.session.packet(DATA, 1, &[b'x'; 200]);
+        packet[6] ^= 1;
+        let (read_packet, check_errors) = parse(&kermit, packet);
+        assert!(read_packet.is_none());
+        assert_eq!(check_errors, 1);
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(Kermit::attribute_size(b"1$1234"), Some(1234));
+        assert_eq!(Kermit::attribute_size(b".!A!\"12"), Some(12 * 1024));
+        assert_eq!(Kermit::attribute_size(b"#!x"), None);
+        assert_eq!(Kermit::attribute_size(b"1)12"), None);
+    }
+
+    #[test]
+    fn round_trip_batch() {
+        let dir = temp_dir("kermit");
+        let out = dir.join("out");
+        let dest = dir.join("in");
+        fs::create_dir_all(&out).unwrap();
+
+        // Every byte value, long runs, and more than fits in one packet
+        let mut binary: Vec<u8> = (0..=255).cycle().take(20_000).collect();
+        binary.extend([0u8; 500]);
+        binary.extend((0..5000).map(|i| (i * 31 % 256) as u8));
+        let files = [
+            ("data.bin", binary),
+            ("empty.txt", Vec::new()),
+            ("text.txt", b"line 1\r\nline 2\n".to_vec()),
+        ];
+        for (name, data) in &files {
+            fs::write(out.join(name), data).unwrap();
+        }
+
+        let paths: Vec<PathBuf> = files.iter().map(|(name, _)| out.join(name)).collect();
+        let (sent, received) = round_trip(Protocol::Kermit, &paths, &dest);
+        assert_eq!(sent.retries, 0);
+        assert_eq!(received.check_errors, 0);
+        for (name, data) in &files {
+            assert_eq!(&fs::read(dest.join(name)).unwrap(), data, "{}", name);
+        }
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,589 @@
+pub mod kermit;
+pub mod xmodem;
+pub mod zmodem;
+
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serialport::SerialPort;
+
+use crate::transfer::kermit::Kermit;
+use crate::transfer::xmodem::{Xmodem, XmodemVariant};
+use crate::transfer::zmodem::Zmodem;
+use crate::ui::DisplayUpdateEvent;
+
+/// Minimum time between progress reports
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+/// Port read timeout used while a transfer has the port
+const TRANSFER_READ_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// File transfer protocol
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Protocol {
+    /// XMODEM with 128 byte blocks
+    Xmodem,
+    /// XMODEM with 1024 byte blocks
+    Xmodem1k,
+    /// YMODEM batch transfer
+    Ymodem,
+    /// ZMODEM streaming batch transfer
+    Zmodem,
+    /// Kermit batch transfer
+    Kermit,
+}
+
+impl Protocol {
+    /// Get the implementation of the protocol
+    fn implementation(&self) -> Box<dyn TransferProtocol> {
+        match self {
+            Protocol::Xmodem => Box::new(Xmodem::new(XmodemVariant::Xmodem)),
+            Protocol::Xmodem1k => Box::new(Xmodem::new(XmodemVariant::Xmodem1k)),
+            Protocol::Ymodem => Box::new(Xmodem::new(XmodemVariant::Ymodem)),
+            Protocol::Zmodem => Box::new(Zmodem::default()),
+            Protocol::Kermit => Box::new(Kermit::default()),
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "x" | "xmodem" => Ok(Protocol::Xmodem),
+            "1k" | "xmodem-1k" | "xmodem1k" => Ok(Protocol::Xmodem1k),
+            "y" | "ymodem" => Ok(Protocol::Ymodem),
+            "z" | "zmodem" => Ok(Protocol::Zmodem),
+            "k" | "kermit" => Ok(Protocol::Kermit),
+            _ => Err(format!("Unknown transfer protocol '{}'", s)),
+        }
+    }
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Protocol::Xmodem => "XMODEM",
+            Protocol::Xmodem1k => "XMODEM-1K",
+            Protocol::Ymodem => "YMODEM",
+            Protocol::Zmodem => "ZMODEM",
+            Protocol::Kermit => "Kermit",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// File transfer direction
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    /// Send files to the device
+    Send,
+    /// Receive files from the device
+    Receive,
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "s" | "send" => Ok(Direction::Send),
+            "r" | "recv" | "receive" => Ok(Direction::Receive),
+            _ => Err(format!("Unknown transfer direction '{}'", s)),
+        }
+    }
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Direction::Send => "send",
+            Direction::Receive => "receive",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Transfer error
+#[derive(Debug)]
+pub enum TransferError {
+    /// Serial port or file IO error
+    IOError(io::Error),
+    /// Peer did not respond in time
+    Timeout,
+    /// Transfer was cancelled locally
+    Cancelled,
+    /// Peer cancelled the transfer
+    RemoteCancelled,
+    /// Too many errors on a single block
+    TooManyRetries,
+    /// Peer broke the protocol
+    Protocol(String),
+}
+
+impl From<io::Error> for TransferError {
+    fn from(e: io::Error) -> Self {
+        Self::IOError(e)
+    }
+}
+
+impl Display for TransferError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransferError::IOError(e) => write!(f, "{}", e),
+            TransferError::Timeout => write!(f, "Timed out"),
+            TransferError::Cancelled => write!(f, "Cancelled"),
+            TransferError::RemoteCancelled => write!(f, "Cancelled by remote"),
+            TransferError::TooManyRetries => write!(f, "Too many retries"),
+            TransferError::Protocol(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// Transfer progress
+#[derive(Debug, Clone)]
+pub struct TransferProgress {
+    /// Transfer protocol
+    pub protocol: Protocol,
+    /// Transfer direction
+    pub direction: Direction,
+    /// Name of the current file
+    pub file_name: String,
+    /// Bytes of the current file transferred
+    pub bytes: u64,
+    /// Size of the current file, if known
+    pub total: Option<u64>,
+    /// Number of retransmitted blocks
+    pub retries: u32,
+    /// Number of blocks received with a bad CRC or checksum
+    pub check_errors: u32,
+}
+
+impl Display for TransferProgress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.protocol, self.direction, self.file_name, self.bytes
+        )?;
+        if let Some(total) = self.total {
+            let percent = (self.bytes * 100).checked_div(total).unwrap_or(100);
+            write!(f, "/{} B ({}%)", total, percent)?;
+        } else {
+            write!(f, " B")?;
+        }
+        write!(
+            f,
+            " Retries: {} CRC errors: {}",
+            self.retries, self.check_errors
+        )
+    }
+}
+
+/// Transfer status
+#[derive(Debug, Clone)]
+pub enum TransferStatus {
+    /// Transfer in progress
+    Progress(TransferProgress),
+    /// Transfer completed successfully
+    Complete(TransferProgress),
+    /// Transfer failed
+    Failed(String),
+}
+
+impl Display for TransferStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransferStatus::Progress(progress) => write!(f, "{}", progress),
+            TransferStatus::Complete(progress) => write!(
+                f,
+                "{} {} complete, Retries: {} CRC errors: {}",
+                progress.protocol, progress.direction, progress.retries, progress.check_errors
+            ),
+            TransferStatus::Failed(msg) => write!(f, "Transfer failed: {}", msg),
+        }
+    }
+}
+
+/// File transfer protocol implementation
+pub trait TransferProtocol {
+    /// Send files to the peer
+    fn send(&mut self, ctx: &mut TransferContext, files: &[PathBuf]) -> Result<(), TransferError>;
+
+    /// Receive files from the peer
+    ///
+    /// `dest` is the file to write for protocols without file names, otherwise it is the
+    /// directory files are written to
+    fn receive(&mut self, ctx: &mut TransferContext, dest: &Path) -> Result<(), TransferError>;
+}
+
+/// Port access and progress tracking for a running transfer
+pub struct TransferContext<'a> {
+    /// Serial port, owned exclusively for the transfer
+    port: &'a mut dyn SerialPort,
+    /// Bytes read from the port but not yet consumed
+    buffer: VecDeque<u8>,
+    /// Cancel flag
+    cancel: Arc<AtomicBool>,
+    /// Current progress
+    pub progress: TransferProgress,
+    /// Progress callback
+    report: Box<dyn FnMut(&TransferProgress) + 'a>,
+    /// Time of the last progress report
+    last_report: Instant,
+}
+
+impl<'a> TransferContext<'a> {
+    /// Create a new transfer context
+    fn new(
+        port: &'a mut dyn SerialPort,
+        protocol: Protocol,
+        direction: Direction,
+        received: Vec<u8>,
+        cancel: Arc<AtomicBool>,
+        report: Box<dyn FnMut(&TransferProgress) + 'a>,
+    ) -> Self {
+        Self {
+            port,
+            buffer: received.into(),
+            cancel,
+            progress: TransferProgress {
+                protocol,
+                direction,
+                file_name: String::new(),
+                bytes: 0,
+                total: None,
+                retries: 0,
+                check_errors: 0,
+            },
+            report,
+            last_report: Instant::now() - REPORT_INTERVAL,
+        }
+    }
+
+    /// Return an error if the transfer has been cancelled
+    pub fn check_cancelled(&self) -> Result<(), TransferError> {
+        if self.cancel.load(Ordering::Relaxed) {
+            Err(TransferError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Read a single byte, waiting up to `timeout`
+    pub fn read_byte(&mut self, timeout: Duration) -> Result<Option<u8>, TransferError> {
+        let deadline = Instant::now() + timeout;
+
+        while self.buffer.is_empty() {
+            self.check_cancelled()?;
+
+            let mut buf = [0u8; 1024];
+            match self.port.read(&mut buf) {
+                Ok(count) => self.buffer.extend(&buf[..count]),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            if self.buffer.is_empty() && Instant::now() >= deadline {
+                return Ok(None);
+            }
+        }
+
+        Ok(self.buffer.pop_front())
+    }
+
+    /// Get the next received byte without consuming it or waiting for it
+    pub fn peek_byte(&mut self) -> Result<Option<u8>, TransferError> {
+        if self.buffer.is_empty() && self.port.bytes_to_read().unwrap_or(0) > 0 {
+            let mut buf = [0u8; 1024];
+            match self.port.read(&mut buf) {
+                Ok(count) => self.buffer.extend(&buf[..count]),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(self.buffer.front().copied())
+    }
+
+    /// Put a byte back to be read next
+    pub fn unread_byte(&mut self, byte: u8) {
+        self.buffer.push_front(byte);
+    }
+
+    /// Read exactly `buf.len()` bytes, waiting up to `timeout` for each byte
+    pub fn read_exact(&mut self, buf: &mut [u8], timeout: Duration) -> Result<(), TransferError> {
+        for byte in buf.iter_mut() {
+            *byte = self.read_byte(timeout)?.ok_or(TransferError::Timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Write all data to the port
+    pub fn write_all(&mut self, data: &[u8]) -> Result<(), TransferError> {
+        self.port.write_all(data)?;
+        self.port.flush()?;
+        Ok(())
+    }
+
+    /// Discard incoming data until the line has been quiet for `quiet`
+    pub fn purge(&mut self, quiet: Duration) -> Result<(), TransferError> {
+        self.buffer.clear();
+        while self.read_byte(quiet)?.is_some() {}
+        Ok(())
+    }
+
+    /// Start a new file
+    pub fn start_file(&mut self, file_name: &str, total: Option<u64>) {
+        self.progress.file_name = file_name.to_string();
+        self.progress.total = total;
+        self.progress.bytes = 0;
+        self.report(true);
+    }
+
+    /// Record transferred bytes
+    pub fn add_bytes(&mut self, count: u64) {
+        self.progress.bytes += count;
+        if let Some(total) = self.progress.total {
+            self.progress.bytes = self.progress.bytes.min(total);
+        }
+        self.report(false);
+    }
+
+    /// Set the number of bytes of the current file transferred
+    pub fn set_bytes(&mut self, bytes: u64) {
+        self.progress.bytes = 0;
+        self.add_bytes(bytes);
+    }
+
+    /// Record a retransmitted block
+    pub fn add_retry(&mut self) {
+        self.progress.retries += 1;
+        self.report(false);
+    }
+
+    /// Record a block with a bad CRC or checksum
+    pub fn add_check_error(&mut self) {
+        self.progress.check_errors += 1;
+        self.report(false);
+    }
+
+    /// Report progress, limited to once per report interval unless forced
+    fn report(&mut self, force: bool) {
+        if force || self.last_report.elapsed() >= REPORT_INTERVAL {
+            (self.report)(&self.progress);
+            self.last_report = Instant::now();
+        }
+    }
+}
+
+/// Run a transfer on a port
+///
+/// The port read timeout is shortened for the duration of the transfer, and restored after.
+/// `received` is data already taken from the port, which the transfer reads first.
+pub fn run_transfer(
+    port: &mut dyn SerialPort,
+    protocol: Protocol,
+    direction: Direction,
+    paths: &[PathBuf],
+    received: Vec<u8>,
+    cancel: Arc<AtomicBool>,
+    report: Box<dyn FnMut(&TransferProgress) + '_>,
+) -> Result<TransferProgress, TransferError> {
+    let timeout = port.timeout();
+    port.set_timeout(TRANSFER_READ_TIMEOUT)
+        .map_err(io::Error::from)?;
+
+    let res = {
+        let mut ctx = TransferContext::new(port, protocol, direction, received, cancel, report);
+        let mut implementation = protocol.implementation();
+
+        let res = match direction {
+            Direction::Send => implementation.send(&mut ctx, paths),
+            Direction::Receive => {
+                let dest = paths.first().cloned().unwrap_or_else(|| PathBuf::from("."));
+                implementation.receive(&mut ctx, &dest)
+            }
+        };
+        res.map(|_| ctx.progress.clone())
+    };
+
+    port.set_timeout(timeout).map_err(io::Error::from)?;
+    res
+}
+
+/// Handle to a transfer running in the background
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    /// Set to cancel the transfer
+    cancel: Arc<AtomicBool>,
+}
+
+impl Transfer {
+    /// Start a transfer in a new thread
+    ///
+    /// The transfer takes the serial port lock for its whole duration, which pauses the serial
+    /// RX and TX threads until it is done. `received` is read before anything new from the port.
+    pub fn start(
+        serial_port: Arc<Mutex<Box<dyn SerialPort>>>,
+        protocol: Protocol,
+        direction: Direction,
+        paths: Vec<PathBuf>,
+        received: Vec<u8>,
+        display_update_tx: Sender<DisplayUpdateEvent>,
+    ) -> Self {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let thread_cancel = cancel.clone();
+
+        thread::spawn(move || {
+            let status = match serial_port.lock() {
+                Ok(mut serial_port) => {
+                    let progress_tx = display_update_tx.clone();
+                    let res = run_transfer(
+                        &mut **serial_port,
+                        protocol,
+                        direction,
+                        &paths,
+                        received,
+                        thread_cancel,
+                        Box::new(move |progress| {
+                            progress_tx
+                                .send(DisplayUpdateEvent::Transfer(TransferStatus::Progress(
+                                    progress.clone(),
+                                )))
+                                .ok();
+                        }),
+                    );
+
+                    match res {
+                        Ok(progress) => TransferStatus::Complete(progress),
+                        Err(e) => TransferStatus::Failed(e.to_string()),
+                    }
+                }
+                Err(_) => TransferStatus::Failed("Serial port unavailable".to_string()),
+            };
+
+            display_update_tx
+                .send(DisplayUpdateEvent::Transfer(status))
+                .ok();
+        });
+
+        Self { cancel }
+    }
+
+    /// Cancel the transfer
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Parse a transfer command in the form `<protocol> <send|receive> [paths...]`
+pub fn parse_transfer_command(
+    command: &str,
+) -> Result<(Protocol, Direction, Vec<PathBuf>), String> {
+    let mut parts = command.split_whitespace();
+    let protocol = parts
+        .next()
+        .ok_or_else(|| "Missing protocol".to_string())?
+        .parse()?;
+    let direction = parts
+        .next()
+        .ok_or_else(|| "Missing direction".to_string())?
+        .parse()?;
+    let paths: Vec<PathBuf> = parts.map(PathBuf::from).collect();
+
+    if direction == Direction::Send && paths.is_empty() {
+        return Err("No files to send".to_string());
+    }
+
+    Ok((protocol, direction, paths))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::serial::loopback::LoopbackPort;
+    use serialport::SerialPortSettings;
+    use std::fs;
+
+    /// Create an empty directory for a test's files
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cerial-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Settings for the test's end of a link, which it reads by hand
+    pub(crate) fn settings() -> SerialPortSettings {
+        SerialPortSettings {
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        }
+    }
+
+    /// Run a transfer on one end of a pair in a new thread, returning the other end
+    pub(crate) fn start(
+        protocol: Protocol,
+        direction: Direction,
+        paths: &[PathBuf],
+    ) -> (
+        LoopbackPort,
+        thread::JoinHandle<Result<TransferProgress, TransferError>>,
+    ) {
+        let (mut port, peer) = LoopbackPort::pair(&settings());
+        let paths = paths.to_vec();
+        let handle = thread::spawn(move || {
+            run_transfer(
+                &mut port,
+                protocol,
+                direction,
+                &paths,
+                Vec::new(),
+                Arc::new(AtomicBool::new(false)),
+                Box::new(|_| {}),
+            )
+        });
+        (peer, handle)
+    }
+
+    /// Send files with a protocol and receive them into `dest`
+    pub(crate) fn round_trip(
+        protocol: Protocol,
+        files: &[PathBuf],
+        dest: &Path,
+    ) -> (TransferProgress, TransferProgress) {
+        let (mut port, sender) = start(protocol, Direction::Send, files);
+        let received = run_transfer(
+            &mut port,
+            protocol,
+            Direction::Receive,
+            &[dest.to_path_buf()],
+            Vec::new(),
+            Arc::new(AtomicBool::new(false)),
+            Box::new(|_| {}),
+        )
+        .unwrap();
+        (sender.join().unwrap().unwrap(), received)
+    }
+
+    #[test]
+    fn commands() {
+        let (protocol, direction, paths) =
+            parse_transfer_command("ymodem send a.bin b.bin").unwrap();
+        assert_eq!(protocol, Protocol::Ymodem);
+        assert_eq!(direction, Direction::Send);
+        assert_eq!(paths.len(), 2);
+        assert!(parse_transfer_command("xmodem send").is_err());
+        assert!(parse_transfer_command("xmodem").is_err());
+        assert!(parse_transfer_command("").is_err());
+    }
+}
@@ -0,0 +1,596 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::checksum::{crc16_xmodem, sum8};
+use crate::transfer::{TransferContext, TransferError, TransferProtocol};
+
+/// Start of 128 byte block
+const SOH: u8 = 0x01;
+/// Start of 1024 byte block
+const STX: u8 = 0x02;
+/// End of transmission
+const EOT: u8 = 0x04;
+/// Acknowledge
+const ACK: u8 = 0x06;
+/// Negative acknowledge, also requests checksum mode
+const NAK: u8 = 0x15;
+/// Cancel
+const CAN: u8 = 0x18;
+/// Padding for partial blocks
+const SUB: u8 = 0x1a;
+/// Requests CRC mode
+const CRC_START: u8 = b'C';
+
+/// Max number of consecutive errors before giving up
+const MAX_RETRIES: u32 = 10;
+/// Number of attempts to start a receive in CRC mode before falling back to checksum mode
+const CRC_START_ATTEMPTS: u32 = 4;
+/// Total number of attempts to start a receive
+const START_ATTEMPTS: u32 = 10;
+/// Time between receive start attempts
+const START_INTERVAL: Duration = Duration::from_secs(3);
+/// Time to wait for a receiver to request a send
+const SEND_START_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time to wait for a block or response
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time to wait for each byte within a block
+const BYTE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// XMODEM family protocol variant
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum XmodemVariant {
+    /// XMODEM with 128 byte blocks
+    Xmodem,
+    /// XMODEM with 1024 byte blocks
+    Xmodem1k,
+    /// YMODEM batch transfer with file names and sizes
+    Ymodem,
+}
+
+/// Received packet
+enum Packet {
+    /// Data block with its block number
+    Block(u8, Vec<u8>),
+    /// End of transmission
+    Eot,
+}
+
+/// XMODEM, XMODEM-1K, and YMODEM implementation
+pub struct Xmodem {
+    /// Protocol variant
+    variant: XmodemVariant,
+}
+
+impl Xmodem {
+    /// Create a new instance of a protocol variant
+    pub fn new(variant: XmodemVariant) -> Self {
+        Self { variant }
+    }
+
+    /// Send a cancel sequence to the peer
+    fn send_cancel(ctx: &mut TransferContext) {
+        ctx.write_all(&[CAN; 8]).ok();
+    }
+
+    /// Cancel the peer on errors the peer doesn't know about
+    fn cancel_on_error<T>(
+        ctx: &mut TransferContext,
+        res: Result<T, TransferError>,
+    ) -> Result<T, TransferError> {
+        if let Err(e) = &res {
+            match e {
+                TransferError::RemoteCancelled => {}
+                _ => Self::send_cancel(ctx),
+            }
+        }
+        res
+    }
+
+    /// Check for a second CAN to confirm the peer cancelled
+    fn check_remote_cancel(ctx: &mut TransferContext) -> Result<(), TransferError> {
+        if ctx.read_byte(BYTE_TIMEOUT)? == Some(CAN) {
+            Err(TransferError::RemoteCancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Wait for a receiver to request data, returns true if the receiver requested CRC mode
+    fn wait_for_start(ctx: &mut TransferContext) -> Result<bool, TransferError> {
+        loop {
+            match ctx.read_byte(SEND_START_TIMEOUT)? {
+                Some(CRC_START) => return Ok(true),
+                Some(NAK) => return Ok(false),
+                Some(CAN) => Self::check_remote_cancel(ctx)?,
+                Some(_) => {}
+                None => return Err(TransferError::Timeout),
+            }
+        }
+    }
+
+    /// Wait for a response to a block
+    fn wait_for_response(ctx: &mut TransferContext) -> Result<Option<u8>, TransferError> {
+        loop {
+            match ctx.read_byte(BLOCK_TIMEOUT)? {
+                Some(CAN) => Self::check_remote_cancel(ctx)?,
+                Some(byte) if byte == ACK || byte == NAK || byte == CRC_START => {
+                    return Ok(Some(byte))
+                }
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Send a block until it is acknowledged
+    fn send_block(
+        ctx: &mut TransferContext,
+        block_num: u8,
+        data: &[u8],
+        crc: bool,
+    ) -> Result<(), TransferError> {
+        let mut packet = Vec::with_capacity(data.len() + 5);
+        packet.push(if data.len() == 1024 { STX } else { SOH });
+        packet.push(block_num);
+        packet.push(!block_num);
+        packet.extend_from_slice(data);
+        if crc {
+            packet.extend_from_slice(&crc16_xmodem(data).to_be_bytes());
+        } else {
+            packet.push(sum8(data));
+        }
+
+        for _ in 0..MAX_RETRIES {
+            ctx.write_all(&packet)?;
+            match Self::wait_for_response(ctx)? {
+                Some(ACK) => return Ok(()),
+                _ => ctx.add_retry(),
+            }
+        }
+
+        Err(TransferError::TooManyRetries)
+    }
+
+    /// Send file data as numbered blocks starting at block 1
+    fn send_data(
+        &self,
+        ctx: &mut TransferContext,
+        data: &[u8],
+        crc: bool,
+    ) -> Result<(), TransferError> {
+        let mut block_num: u8 = 1;
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let remaining = data.len() - offset;
+            // Use small blocks for the end of the file to reduce padding
+            let block_size = if self.variant == XmodemVariant::Xmodem || remaining <= 128 {
+                128
+            } else {
+                1024
+            };
+
+            let end = data.len().min(offset + block_size);
+            let mut block = data[offset..end].to_vec();
+            block.resize(block_size, SUB);
+
+            Self::send_block(ctx, block_num, &block, crc)?;
+            ctx.add_bytes((end - offset) as u64);
+
+            block_num = block_num.wrapping_add(1);
+            offset = end;
+        }
+
+        // End of transmission, YMODEM receivers NAK the first EOT
+        for _ in 0..MAX_RETRIES {
+            ctx.write_all(&[EOT])?;
+            if Self::wait_for_response(ctx)? == Some(ACK) {
+                return Ok(());
+            }
+        }
+
+        Err(TransferError::TooManyRetries)
+    }
+
+    /// Build a YMODEM header block
+    fn ymodem_header(file_name: &str, size: usize) -> Vec<u8> {
+        let mut block = Vec::new();
+        block.extend_from_slice(file_name.as_bytes());
+        block.push(0);
+        block.extend_from_slice(size.to_string().as_bytes());
+
+        let block_size = if block.len() > 128 { 1024 } else { 128 };
+        block.resize(block_size, 0);
+        block
+    }
+
+    /// Send files
+    fn send_files(
+        &self,
+        ctx: &mut TransferContext,
+        files: &[PathBuf],
+    ) -> Result<(), TransferError> {
+        if self.variant != XmodemVariant::Ymodem && files.len() != 1 {
+            return Err(TransferError::Protocol(
+                "XMODEM can only send one file".to_string(),
+            ));
+        }
+
+        for file in files {
+            let data = fs::read(file)?;
+            let file_name = file
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            ctx.start_file(&file_name, Some(data.len() as u64));
+
+            let mut crc = Self::wait_for_start(ctx)?;
+            if self.variant == XmodemVariant::Ymodem {
+                Self::send_block(ctx, 0, &Self::ymodem_header(&file_name, data.len()), crc)?;
+                crc = Self::wait_for_start(ctx)?;
+            }
+
+            self.send_data(ctx, &data, crc)?;
+        }
+
+        // An empty header ends a YMODEM batch
+        if self.variant == XmodemVariant::Ymodem {
+            let crc = Self::wait_for_start(ctx)?;
+            Self::send_block(ctx, 0, &[0; 128], crc)?;
+        }
+
+        Ok(())
+    }
+
+    /// Receive a single packet
+    ///
+    /// Returns `None` if the packet timed out or was corrupt
+    fn receive_packet(
+        ctx: &mut TransferContext,
+        crc: bool,
+        timeout: Duration,
+    ) -> Result<Option<Packet>, TransferError> {
+        let block_size = loop {
+            match ctx.read_byte(timeout)? {
+                Some(SOH) => break 128,
+                Some(STX) => break 1024,
+                Some(EOT) => return Ok(Some(Packet::Eot)),
+                Some(CAN) => Self::check_remote_cancel(ctx)?,
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        };
+
+        let mut header = [0u8; 2];
+        let mut data = vec![0u8; block_size];
+        let mut check = vec![0u8; if crc { 2 } else { 1 }];
+
+        let res = ctx
+            .read_exact(&mut header, BYTE_TIMEOUT)
+            .and_then(|_| ctx.read_exact(&mut data, BYTE_TIMEOUT))
+            .and_then(|_| ctx.read_exact(&mut check, BYTE_TIMEOUT));
+        match res {
+            Err(TransferError::Timeout) => {
+                ctx.purge(BYTE_TIMEOUT)?;
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+            Ok(()) => {}
+        }
+
+        let check_ok = if crc {
+            u16::from_be_bytes([check[0], check[1]]) == crc16_xmodem(&data)
+        } else {
+            check[0] == sum8(&data)
+        };
+
+        if header[0] != !header[1] || !check_ok {
+            ctx.add_check_error();
+            ctx.purge(BYTE_TIMEOUT)?;
+            return Ok(None);
+        }
+
+        Ok(Some(Packet::Block(header[0], data)))
+    }
+
+    /// Request a sender to start sending, returns the CRC mode and the first packet
+    fn start_receive(&self, ctx: &mut TransferContext) -> Result<(bool, Packet), TransferError> {
+        for attempt in 0..START_ATTEMPTS {
+            // YMODEM requires CRC mode
+            let crc = self.variant == XmodemVariant::Ymodem || attempt < CRC_START_ATTEMPTS;
+            ctx.write_all(&[if crc { CRC_START } else { NAK }])?;
+
+            if let Some(packet) = Self::receive_packet(ctx, crc, START_INTERVAL)? {
+                return Ok((crc, packet));
+            }
+        }
+
+        Err(TransferError::Timeout)
+    }
+
+    /// Receive file data blocks until the end of transmission
+    fn receive_data<W: Write>(
+        &self,
+        ctx: &mut TransferContext,
+        crc: bool,
+        first: Packet,
+        writer: &mut W,
+    ) -> Result<(), TransferError> {
+        let mut expected: u8 = 1;
+        let mut errors = 0;
+        let mut eot_count = 0;
+        let mut packet = Some(first);
+
+        loop {
+            match packet {
+                Some(Packet::Block(block_num, data)) => {
+                    if block_num == expected {
+                        // Only write up to the file size if the sender gave one
+                        let count = match ctx.progress.total {
+                            Some(total) => (total - ctx.progress.bytes).min(data.len() as u64),
+                            None => data.len() as u64,
+                        } as usize;
+                        writer.write_all(&data[..count])?;
+                        ctx.add_bytes(count as u64);
+                        expected = expected.wrapping_add(1);
+                        errors = 0;
+                        ctx.write_all(&[ACK])?;
+                    } else if block_num == expected.wrapping_sub(1) {
+                        // Our ACK was lost and the sender repeated the block
+                        ctx.add_retry();
+                        ctx.write_all(&[ACK])?;
+                    } else {
+                        return Err(TransferError::Protocol(format!(
+                            "Expected block {} but got {}",
+                            expected, block_num
+                        )));
+                    }
+                }
+                Some(Packet::Eot) => {
+                    eot_count += 1;
+                    // YMODEM NAKs the first EOT to make sure it is not line noise
+                    if self.variant == XmodemVariant::Ymodem && eot_count == 1 {
+                        ctx.write_all(&[NAK])?;
+                    } else {
+                        ctx.write_all(&[ACK])?;
+                        return Ok(());
+                    }
+                }
+                None => {
+                    errors += 1;
+                    if errors > MAX_RETRIES {
+                        return Err(TransferError::TooManyRetries);
+                    }
+                    ctx.add_retry();
+                    ctx.write_all(&[NAK])?;
+                }
+            }
+
+            packet = Self::receive_packet(ctx, crc, BLOCK_TIMEOUT)?;
+        }
+    }
+
+    /// Receive a single XMODEM file
+    fn receive_xmodem(&self, ctx: &mut TransferContext, dest: &Path) -> Result<(), TransferError> {
+        if dest.is_dir() {
+            return Err(TransferError::Protocol(
+                "XMODEM needs a file name to receive to".to_string(),
+            ));
+        }
+
+        let file_name = dest.to_string_lossy().to_string();
+        ctx.start_file(&file_name, None);
+
+        let (crc, first) = self.start_receive(ctx)?;
+        let mut file = File::create(dest)?;
+        self.receive_data(ctx, crc, first, &mut file)
+    }
+
+    /// Receive a YMODEM batch into a directory
+    fn receive_ymodem(&self, ctx: &mut TransferContext, dest: &Path) -> Result<(), TransferError> {
+        fs::create_dir_all(dest)?;
+
+        loop {
+            let header = match self.start_receive(ctx)? {
+                (_, Packet::Block(0, header)) => header,
+                _ => {
+                    return Err(TransferError::Protocol(
+                        "Expected a YMODEM header".to_string(),
+                    ))
+                }
+            };
+            ctx.write_all(&[ACK])?;
+
+            // An empty file name ends the batch
+            let mut fields = header.split(|b| *b == 0);
+            let name = String::from_utf8_lossy(fields.next().unwrap_or_default()).to_string();
+            if name.is_empty() {
+                return Ok(());
+            }
+            let size = fields
+                .next()
+                .and_then(|info| info.split(|b| *b == b' ').next())
+                .and_then(|size| std::str::from_utf8(size).ok())
+                .and_then(|size| size.parse::<u64>().ok());
+
+            // Never trust the sender with a path
+            let file_name = Path::new(&name)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .ok_or_else(|| TransferError::Protocol(format!("Invalid file name '{}'", name)))?;
+            ctx.start_file(&file_name, size);
+
+            let mut file = File::create(dest.join(&file_name))?;
+            let (crc, first) = self.start_receive(ctx)?;
+            self.receive_data(ctx, crc, first, &mut file)?;
+        }
+    }
+}
+
+impl TransferProtocol for Xmodem {
+    fn send(&mut self, ctx: &mut TransferContext, files: &[PathBuf]) -> Result<(), TransferError> {
+        let res = self.send_files(ctx, files);
+        Self::cancel_on_error(ctx, res)
+    }
+
+    fn receive(&mut self, ctx: &mut TransferContext, dest: &Path) -> Result<(), TransferError> {
+        let res = match self.variant {
+            XmodemVariant::Ymodem => self.receive_ymodem(ctx, dest),
+            _ => self.receive_xmodem(ctx, dest),
+        };
+        Self::cancel_on_error(ctx, res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::loopback::LoopbackPort;
+    use crate::transfer::tests::{round_trip, start, temp_dir};
+    use crate::transfer::{Direction, Protocol};
+    use std::io::Read;
+
+    /// File contents that aren't a whole number of blocks
+    fn contents(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// Build a 128 byte CRC block
+    fn block(block_num: u8, data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        data.resize(128, SUB);
+        let mut packet = vec![SOH, block_num, !block_num];
+        packet.extend_from_slice(&data);
+        packet.extend_from_slice(&crc16_xmodem(&data).to_be_bytes());
+        packet
+    }
+
+    fn read_byte(port: &mut LoopbackPort) -> u8 {
+        let mut byte = [0];
+        port.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    #[test]
+    fn xmodem_round_trip() {
+        let dir = temp_dir("xmodem");
+        let data = contents(300);
+        fs::write(dir.join("out.bin"), &data).unwrap();
+
+        let (sent, received) = round_trip(
+            Protocol::Xmodem,
+            &[dir.join("out.bin")],
+            &dir.join("in.bin"),
+        );
+        assert_eq!(sent.bytes, 300);
+        assert_eq!(received.retries, 0);
+
+        // XMODEM doesn't send the size, so the last block's padding is kept
+        let received = fs::read(dir.join("in.bin")).unwrap();
+        assert_eq!(received.len(), 384);
+        assert_eq!(&received[..300], &data[..]);
+        assert!(received[300..].iter().all(|b| *b == SUB));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn xmodem_1k_round_trip() {
+        let dir = temp_dir("xmodem-1k");
+        let data = contents(1100);
+        fs::write(dir.join("out.bin"), &data).unwrap();
+
+        round_trip(
+            Protocol::Xmodem1k,
+            &[dir.join("out.bin")],
+            &dir.join("in.bin"),
+        );
+
+        // One 1024 byte block, then a 128 byte block for the end of the file
+        let received = fs::read(dir.join("in.bin")).unwrap();
+        assert_eq!(received.len(), 1024 + 128);
+        assert_eq!(&received[..1100], &data[..]);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn ymodem_batch() {
+        let dir = temp_dir("ymodem");
+        let out = dir.join("out");
+        let dest = dir.join("in");
+        fs::create_dir_all(&out).unwrap();
+        let files = [("a.bin", contents(2000)), ("b.txt", b"hi\n".to_vec())];
+        for (name, data) in &files {
+            fs::write(out.join(name), data).unwrap();
+        }
+
+        let paths: Vec<PathBuf> = files.iter().map(|(name, _)| out.join(name)).collect();
+        let (_, received) = round_trip(Protocol::Ymodem, &paths, &dest);
+        assert_eq!(received.file_name, "b.txt");
+
+        // Files are cut to the size in their header, and the empty header ends the batch
+        for (name, data) in &files {
+            assert_eq!(&fs::read(dest.join(name)).unwrap(), data, "{}", name);
+        }
+        assert_eq!(fs::read_dir(&dest).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn ymodem_header() {
+        let header = Xmodem::ymodem_header("a.bin", 2000);
+        assert_eq!(header.len(), 128);
+        assert!(header.starts_with(b"a.bin\x002000\x00"));
+        assert_eq!(Xmodem::ymodem_header(&"x".repeat(200), 1).len(), 1024);
+    }
+
+    #[test]
+    fn duplicate_block_acked() {
+        let dir = temp_dir("xmodem-duplicate");
+        let dest = [dir.join("in.bin")];
+        let (mut port, receiver) = start(Protocol::Xmodem, Direction::Receive, &dest);
+
+        assert_eq!(read_byte(&mut port), CRC_START);
+        port.write_all(&block(1, b"first")).unwrap();
+        assert_eq!(read_byte(&mut port), ACK);
+
+        // The ACK was lost as far as the sender knows, so block 1 is sent again
+        port.write_all(&block(1, b"first")).unwrap();
+        assert_eq!(read_byte(&mut port), ACK);
+        port.write_all(&block(2, b"second")).unwrap();
+        assert_eq!(read_byte(&mut port), ACK);
+        port.write_all(&[EOT]).unwrap();
+        assert_eq!(read_byte(&mut port), ACK);
+
+        let progress = receiver.join().unwrap().unwrap();
+        assert_eq!(progress.retries, 1);
+        let received = fs::read(&dest[0]).unwrap();
+        assert_eq!(received.len(), 256);
+        assert!(received.starts_with(b"first"));
+        assert!(received[128..].starts_with(b"second"));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn eot_nak_retried() {
+        let dir = temp_dir("xmodem-eot");
+        fs::write(dir.join("out.bin"), b"data").unwrap();
+        let (mut port, sender) = start(Protocol::Xmodem, Direction::Send, &[dir.join("out.bin")]);
+
+        port.write_all(&[CRC_START]).unwrap();
+        let mut packet = vec![0; 133];
+        port.read_exact(&mut packet).unwrap();
+        assert_eq!(packet, block(1, b"data"));
+        port.write_all(&[ACK]).unwrap();
+
+        // A NAKed EOT is sent again until it's acknowledged
+        assert_eq!(read_byte(&mut port), EOT);
+        port.write_all(&[NAK]).unwrap();
+        assert_eq!(read_byte(&mut port), EOT);
+        port.write_all(&[ACK]).unwrap();
+
+        assert_eq!(sender.join().unwrap().unwrap().bytes, 4);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,927 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::checksum::{crc16_xmodem, crc32};
+use crate::transfer::{Direction, TransferContext, TransferError, TransferProtocol};
+
+/// Frame padding character
+const ZPAD: u8 = b'*';
+/// ZMODEM escape character, the same as CAN
+const ZDLE: u8 = 0x18;
+/// Binary header with a CRC16
+const ZBIN: u8 = b'A';
+/// Hex header
+const ZHEX: u8 = b'B';
+/// Binary header with a CRC32
+const ZBIN32: u8 = b'C';
+/// Flow control characters, ignored when received
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+/// Request receive init
+const ZRQINIT: u8 = 0;
+/// Receive init
+const ZRINIT: u8 = 1;
+/// Send init sequence
+const ZSINIT: u8 = 2;
+/// Acknowledge
+const ZACK: u8 = 3;
+/// File name from sender
+const ZFILE: u8 = 4;
+/// Skip this file
+const ZSKIP: u8 = 5;
+/// Abort batch transfer
+const ZABORT: u8 = 7;
+/// Finish session
+const ZFIN: u8 = 8;
+/// Resume data transfer at this position
+const ZRPOS: u8 = 9;
+/// Data packet(s) follow
+const ZDATA: u8 = 10;
+/// End of file
+const ZEOF: u8 = 11;
+/// Fatal read or write error
+const ZFERR: u8 = 12;
+/// Other end cancelled the session
+const ZCAN: u8 = 16;
+
+/// Data subpacket end, frame ends with no response expected
+const ZCRCE: u8 = b'h';
+/// Data subpacket end, frame continues with no response expected
+const ZCRCG: u8 = b'i';
+/// Data subpacket end, frame continues and a ZACK is expected
+const ZCRCQ: u8 = b'j';
+/// Data subpacket end, frame ends and a ZACK is expected
+const ZCRCW: u8 = b'k';
+/// Escaped 0x7f
+const ZRUB0: u8 = b'l';
+/// Escaped 0xff
+const ZRUB1: u8 = b'm';
+
+/// ZRINIT flag: can send and receive in full duplex
+const CANFDX: u8 = 0x01;
+/// ZRINIT flag: can receive data during disk IO
+const CANOVIO: u8 = 0x02;
+/// ZRINIT flag: can use 32 bit CRCs
+const CANFC32: u8 = 0x20;
+/// ZFILE flag: binary transfer
+const ZCBIN: u8 = 1;
+
+/// Data bytes per subpacket sent
+const SUBPACKET_SIZE: usize = 1024;
+/// Largest subpacket accepted
+const MAX_SUBPACKET_SIZE: usize = 8192;
+/// Most garbage accepted while looking for a header
+const MAX_HEADER_GARBAGE: usize = 65536;
+/// Time to wait for a header
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time to wait for each byte within a frame
+const BYTE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time to wait for the line ending and XON after a hex header
+const TRAILER_TIMEOUT: Duration = Duration::from_millis(50);
+/// Max number of consecutive errors before giving up
+const MAX_RETRIES: u32 = 10;
+/// Most data held for a transfer while it waits to be accepted
+const MAX_HELD: usize = 65536;
+
+/// Start sequence of a hex ZRQINIT header, sent by a ZMODEM sender
+const ZRQINIT_START: &[u8] = b"**\x18B00";
+/// Start sequence of a hex ZRINIT header, sent by a ZMODEM receiver
+const ZRINIT_START: &[u8] = b"**\x18B01";
+
+/// Cancels a ZMODEM session, eight CANs followed by backspaces to erase them
+pub const CANCEL_SEQUENCE: &[u8] = &[
+    ZDLE, ZDLE, ZDLE, ZDLE, ZDLE, ZDLE, ZDLE, ZDLE, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08,
+];
+
+/// Watches received data for a ZMODEM peer starting a transfer
+#[derive(Debug, Clone, Default)]
+pub struct ZmodemDetector {
+    /// End of the previously received data, so sequences split across reads are found
+    tail: Vec<u8>,
+    /// Data received since the start sequence, held for the transfer while it's accepted
+    held: Vec<u8>,
+}
+
+impl ZmodemDetector {
+    /// Check received data for a ZMODEM start sequence
+    ///
+    /// Returns the direction the local side should transfer in if one is found
+    pub fn detect(&mut self, data: &[u8]) -> Option<Direction> {
+        self.tail.extend_from_slice(data);
+
+        let contains = |seq: &[u8], data: &[u8]| data.windows(seq.len()).any(|w| w == seq);
+        let direction = if contains(ZRQINIT_START, &self.tail) {
+            Some(Direction::Receive)
+        } else if contains(ZRINIT_START, &self.tail) {
+            Some(Direction::Send)
+        } else {
+            None
+        };
+
+        if direction.is_some() {
+            self.held = std::mem::take(&mut self.tail);
+        } else if self.tail.len() >= ZRQINIT_START.len() {
+            self.tail
+                .drain(..self.tail.len() - (ZRQINIT_START.len() - 1));
+        }
+
+        direction
+    }
+
+    /// Hold data received while the transfer waits to be accepted
+    pub fn hold(&mut self, data: &[u8]) {
+        let room = MAX_HELD.saturating_sub(self.held.len());
+        self.held.extend_from_slice(&data[..data.len().min(room)]);
+    }
+
+    /// Take the held data, starting with the start sequence
+    pub fn take_held(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.held)
+    }
+}
+
+/// ZMODEM frame header
+#[derive(Debug, Copy, Clone)]
+struct Header {
+    /// Frame type
+    frame_type: u8,
+    /// Position or flags, ZP0..ZP3
+    data: [u8; 4],
+}
+
+impl Header {
+    /// Create a header with flags, `flags` is ZF0
+    fn with_flags(frame_type: u8, flags: u8) -> Self {
+        Self {
+            frame_type,
+            data: [0, 0, 0, flags],
+        }
+    }
+
+    /// Create a header with a file position
+    fn with_position(frame_type: u8, position: u32) -> Self {
+        Self {
+            frame_type,
+            data: position.to_le_bytes(),
+        }
+    }
+
+    /// File position of the header
+    fn position(&self) -> u32 {
+        u32::from_le_bytes(self.data)
+    }
+
+    /// ZF0 flags of the header
+    fn flags(&self) -> u8 {
+        self.data[3]
+    }
+
+    /// Frame type and data, the bytes covered by the header CRC
+    fn bytes(&self) -> [u8; 5] {
+        [
+            self.frame_type,
+            self.data[0],
+            self.data[1],
+            self.data[2],
+            self.data[3],
+        ]
+    }
+}
+
+/// Byte read from an escaped stream
+enum Escaped {
+    /// Data byte
+    Byte(u8),
+    /// Subpacket end marker
+    End(u8),
+}
+
+/// ZDLE escape data into a buffer
+fn escape(data: &[u8], out: &mut Vec<u8>) {
+    for byte in data {
+        match *byte {
+            ZDLE | 0x10 | 0x90 | XON | 0x91 | XOFF | 0x93 => {
+                out.push(ZDLE);
+                out.push(byte ^ 0x40);
+            }
+            byte => out.push(byte),
+        }
+    }
+}
+
+/// Encode a hex header
+fn hex_header(header: Header) -> Vec<u8> {
+    let bytes = header.bytes();
+    let crc = crc16_xmodem(&bytes);
+
+    let mut out = vec![ZPAD, ZPAD, ZDLE, ZHEX];
+    for byte in bytes.iter().chain(crc.to_be_bytes().iter()) {
+        out.extend_from_slice(format!("{:02x}", byte).as_bytes());
+    }
+    out.extend_from_slice(b"\r\x8a");
+    if header.frame_type != ZACK && header.frame_type != ZFIN {
+        out.push(XON);
+    }
+    out
+}
+
+/// Encode a binary header
+fn binary_header(header: Header, use_crc32: bool) -> Vec<u8> {
+    let bytes = header.bytes();
+    let mut out = vec![ZPAD, ZDLE];
+
+    if use_crc32 {
+        out.push(ZBIN32);
+        escape(&bytes, &mut out);
+        escape(&crc32(&bytes).to_le_bytes(), &mut out);
+    } else {
+        out.push(ZBIN);
+        escape(&bytes, &mut out);
+        escape(&crc16_xmodem(&bytes).to_be_bytes(), &mut out);
+    }
+    out
+}
+
+/// Encode a data subpacket
+fn subpacket(data: &[u8], end: u8, use_crc32: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() * 2 + 12);
+    escape(data, &mut out);
+    out.push(ZDLE);
+    out.push(end);
+
+    let mut crc_data = data.to_vec();
+    crc_data.push(end);
+    if use_crc32 {
+        escape(&crc32(&crc_data).to_le_bytes(), &mut out);
+    } else {
+        escape(&crc16_xmodem(&crc_data).to_be_bytes(), &mut out);
+    }
+
+    if end == ZCRCW {
+        out.push(XON);
+    }
+    out
+}
+
+/// Read a byte from an escaped stream, `None` on timeout or a bad escape sequence
+fn read_escaped(ctx: &mut TransferContext) -> Result<Option<Escaped>, TransferError> {
+    loop {
+        match ctx.read_byte(BYTE_TIMEOUT)? {
+            None => return Ok(None),
+            Some(XON) | Some(XOFF) | Some(0x91) | Some(0x93) => {}
+            Some(ZDLE) => break,
+            Some(byte) => return Ok(Some(Escaped::Byte(byte))),
+        }
+    }
+
+    // Five CANs in a row cancel the session, the first was read as the ZDLE
+    let mut cancels = 1;
+    loop {
+        let byte = match ctx.read_byte(BYTE_TIMEOUT)? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+
+        return Ok(match byte {
+            ZDLE => {
+                cancels += 1;
+                if cancels >= 5 {
+                    return Err(TransferError::RemoteCancelled);
+                }
+                continue;
+            }
+            XON | XOFF | 0x91 | 0x93 => continue,
+            ZCRCE | ZCRCG | ZCRCQ | ZCRCW => Some(Escaped::End(byte)),
+            ZRUB0 => Some(Escaped::Byte(0x7f)),
+            ZRUB1 => Some(Escaped::Byte(0xff)),
+            byte if byte & 0x60 == 0x40 => Some(Escaped::Byte(byte ^ 0x40)),
+            _ => None,
+        });
+    }
+}
+
+/// Read escaped data bytes, `None` if the data is cut short
+fn read_escaped_bytes(
+    ctx: &mut TransferContext,
+    count: usize,
+) -> Result<Option<Vec<u8>>, TransferError> {
+    let mut bytes = Vec::with_capacity(count);
+    while bytes.len() < count {
+        match read_escaped(ctx)? {
+            Some(Escaped::Byte(byte)) => bytes.push(byte),
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(bytes))
+}
+
+/// Decode two hex digits
+fn parse_hex_byte(digits: &[u8]) -> Option<u8> {
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+}
+
+/// Read the next header
+///
+/// Returns the header and whether it used a CRC32, or `None` if no valid header was received
+fn read_header(
+    ctx: &mut TransferContext,
+    timeout: Duration,
+) -> Result<Option<(Header, bool)>, TransferError> {
+    let mut garbage = 0;
+    let mut saw_pad = false;
+    let mut cancels = 0;
+
+    // Find ZPAD ZDLE
+    loop {
+        let byte = match ctx.read_byte(timeout)? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+
+        match byte {
+            ZPAD => {
+                saw_pad = true;
+                cancels = 0;
+            }
+            ZDLE if saw_pad => break,
+            ZDLE => {
+                cancels += 1;
+                if cancels >= 5 {
+                    return Err(TransferError::RemoteCancelled);
+                }
+            }
+            _ => {
+                saw_pad = false;
+                cancels = 0;
+            }
+        }
+
+        garbage += 1;
+        if garbage > MAX_HEADER_GARBAGE {
+            return Ok(None);
+        }
+    }
+
+    let format = ctx.read_byte(BYTE_TIMEOUT)?;
+    let (bytes, use_crc32) = match format {
+        Some(ZHEX) => {
+            let mut digits = [0u8; 14];
+            match ctx.read_exact(&mut digits, BYTE_TIMEOUT) {
+                Ok(()) => {}
+                Err(TransferError::Timeout) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+
+            // Drop the line ending and XON so they aren't mistaken for the start of more input,
+            // anything else is the start of the next frame
+            while let Some(byte) = ctx.read_byte(TRAILER_TIMEOUT)? {
+                if !matches!(byte, b'\r' | b'\n' | 0x8a | XON) {
+                    ctx.unread_byte(byte);
+                    break;
+                }
+            }
+
+            let bytes: Option<Vec<u8>> = digits.chunks(2).map(parse_hex_byte).collect();
+            match bytes {
+                Some(bytes) => {
+                    let crc = u16::from_be_bytes([bytes[5], bytes[6]]);
+                    if crc != crc16_xmodem(&bytes[..5]) {
+                        ctx.add_check_error();
+                        return Ok(None);
+                    }
+                    (bytes, false)
+                }
+                None => return Ok(None),
+            }
+        }
+        Some(ZBIN) => match read_escaped_bytes(ctx, 7)? {
+            Some(bytes) => {
+                let crc = u16::from_be_bytes([bytes[5], bytes[6]]);
+                if crc != crc16_xmodem(&bytes[..5]) {
+                    ctx.add_check_error();
+                    return Ok(None);
+                }
+                (bytes, false)
+            }
+            None => return Ok(None),
+        },
+        Some(ZBIN32) => match read_escaped_bytes(ctx, 9)? {
+            Some(bytes) => {
+                let crc = u32::from_le_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]);
+                if crc != crc32(&bytes[..5]) {
+                    ctx.add_check_error();
+                    return Ok(None);
+                }
+                (bytes, true)
+            }
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+
+    let header = Header {
+        frame_type: bytes[0],
+        data: [bytes[1], bytes[2], bytes[3], bytes[4]],
+    };
+
+    match header.frame_type {
+        ZCAN | ZABORT | ZFERR => Err(TransferError::RemoteCancelled),
+        _ => Ok(Some((header, use_crc32))),
+    }
+}
+
+/// Read a data subpacket
+///
+/// Returns the data and end marker, or `None` if the subpacket was corrupt
+fn read_subpacket(
+    ctx: &mut TransferContext,
+    use_crc32: bool,
+) -> Result<Option<(Vec<u8>, u8)>, TransferError> {
+    let mut data = Vec::new();
+
+    let end = loop {
+        match read_escaped(ctx)? {
+            Some(Escaped::Byte(byte)) => {
+                data.push(byte);
+                if data.len() > MAX_SUBPACKET_SIZE {
+                    return Ok(None);
+                }
+            }
+            Some(Escaped::End(end)) => break end,
+            None => return Ok(None),
+        }
+    };
+
+    let crc_bytes = match read_escaped_bytes(ctx, if use_crc32 { 4 } else { 2 })? {
+        Some(crc_bytes) => crc_bytes,
+        None => return Ok(None),
+    };
+
+    let mut crc_data = data.clone();
+    crc_data.push(end);
+    let crc_ok = if use_crc32 {
+        u32::from_le_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]])
+            == crc32(&crc_data)
+    } else {
+        u16::from_be_bytes([crc_bytes[0], crc_bytes[1]]) == crc16_xmodem(&crc_data)
+    };
+
+    if crc_ok {
+        Ok(Some((data, end)))
+    } else {
+        ctx.add_check_error();
+        Ok(None)
+    }
+}
+
+/// ZMODEM implementation
+#[derive(Default)]
+pub struct Zmodem {}
+
+impl Zmodem {
+    /// Send a cancel sequence to the peer
+    fn send_cancel(ctx: &mut TransferContext) {
+        ctx.write_all(CANCEL_SEQUENCE).ok();
+    }
+
+    /// Cancel the peer on errors the peer doesn't know about
+    fn cancel_on_error<T>(
+        ctx: &mut TransferContext,
+        res: Result<T, TransferError>,
+    ) -> Result<T, TransferError> {
+        if let Err(e) = &res {
+            match e {
+                TransferError::RemoteCancelled => {}
+                _ => Self::send_cancel(ctx),
+            }
+        }
+        res
+    }
+
+    /// Write a header and count it as a retry
+    fn retry(
+        ctx: &mut TransferContext,
+        errors: &mut u32,
+        header: &[u8],
+    ) -> Result<(), TransferError> {
+        *errors += 1;
+        if *errors > MAX_RETRIES {
+            return Err(TransferError::TooManyRetries);
+        }
+        ctx.add_retry();
+        ctx.write_all(header)
+    }
+
+    /// Handle a ZRPOS from the receiver, returning the position to resume sending from
+    fn rewind(
+        ctx: &mut TransferContext,
+        errors: &mut u32,
+        last_rpos: &mut Option<u32>,
+        header: Header,
+    ) -> Result<u32, TransferError> {
+        let position = header.position();
+        if *last_rpos != Some(position) {
+            *errors = 0;
+        }
+        *last_rpos = Some(position);
+
+        Self::retry(ctx, errors, &[])?;
+        Ok(position)
+    }
+
+    /// Receive files into a directory
+    fn receive_files(&self, ctx: &mut TransferContext, dest: &Path) -> Result<(), TransferError> {
+        fs::create_dir_all(dest)?;
+
+        let zrinit = hex_header(Header::with_flags(ZRINIT, CANFDX | CANOVIO | CANFC32));
+        // Open file and the position in it
+        let mut file: Option<(File, u32)> = None;
+        let mut errors = 0;
+
+        ctx.write_all(&zrinit)?;
+
+        loop {
+            let (header, use_crc32) = match read_header(ctx, HEADER_TIMEOUT)? {
+                Some(header) => header,
+                None => {
+                    // Ask again for whatever we were waiting on
+                    let request = match &file {
+                        Some((_, position)) => hex_header(Header::with_position(ZRPOS, *position)),
+                        None => zrinit.clone(),
+                    };
+                    Self::retry(ctx, &mut errors, &request)?;
+                    continue;
+                }
+            };
+            errors = 0;
+
+            match header.frame_type {
+                ZRQINIT => ctx.write_all(&zrinit)?,
+                ZSINIT => {
+                    // The attention string is not needed
+                    read_subpacket(ctx, use_crc32)?;
+                    ctx.write_all(&hex_header(Header::with_position(ZACK, 0)))?;
+                }
+                ZFILE => {
+                    let info = match read_subpacket(ctx, use_crc32)? {
+                        Some((info, _)) => info,
+                        None => {
+                            Self::retry(ctx, &mut errors, &zrinit)?;
+                            continue;
+                        }
+                    };
+
+                    let mut fields = info.split(|b| *b == 0);
+                    let name =
+                        String::from_utf8_lossy(fields.next().unwrap_or_default()).to_string();
+                    let size = fields
+                        .next()
+                        .and_then(|info| info.split(|b| *b == b' ').next())
+                        .and_then(|size| std::str::from_utf8(size).ok())
+                        .and_then(|size| size.parse::<u64>().ok());
+
+                    // Never trust the sender with a path
+                    let file_name = Path::new(&name)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .ok_or_else(|| {
+                            TransferError::Protocol(format!("Invalid file name '{}'", name))
+                        })?;
+
+                    ctx.start_file(&file_name, size);
+                    file = Some((File::create(dest.join(&file_name))?, 0));
+                    ctx.write_all(&hex_header(Header::with_position(ZRPOS, 0)))?;
+                }
+                ZDATA => {
+                    let (file, position) = match &mut file {
+                        Some(file) => file,
+                        None => {
+                            ctx.write_all(&zrinit)?;
+                            continue;
+                        }
+                    };
+
+                    if header.position() != *position {
+                        Self::retry(
+                            ctx,
+                            &mut errors,
+                            &hex_header(Header::with_position(ZRPOS, *position)),
+                        )?;
+                        continue;
+                    }
+
+                    loop {
+                        match read_subpacket(ctx, use_crc32)? {
+                            Some((data, end)) => {
+                                file.write_all(&data)?;
+                                *position += data.len() as u32;
+                                ctx.add_bytes(data.len() as u64);
+
+                                let ack = hex_header(Header::with_position(ZACK, *position));
+                                match end {
+                                    ZCRCW => {
+                                        ctx.write_all(&ack)?;
+                                        break;
+                                    }
+                                    ZCRCQ => ctx.write_all(&ack)?,
+                                    ZCRCG => {}
+                                    _ => break,
+                                }
+                            }
+                            None => {
+                                // Ask the sender to go back to the last good position
+                                Self::retry(
+                                    ctx,
+                                    &mut errors,
+                                    &hex_header(Header::with_position(ZRPOS, *position)),
+                                )?;
+                                break;
+                            }
+                        }
+                    }
+                }
+                ZEOF => match &file {
+                    Some((_, position)) if header.position() == *position => {
+                        file = None;
+                        ctx.write_all(&zrinit)?;
+                    }
+                    // Data was lost, ask for it again
+                    Some((_, position)) => {
+                        let zrpos = hex_header(Header::with_position(ZRPOS, *position));
+                        Self::retry(ctx, &mut errors, &zrpos)?;
+                    }
+                    // The sender missed our ZRINIT
+                    None => ctx.write_all(&zrinit)?,
+                },
+                ZFIN => {
+                    ctx.write_all(&hex_header(Header::with_position(ZFIN, 0)))?;
+                    // Read the sender's "OO", it doesn't matter if it never comes
+                    ctx.read_byte(BYTE_TIMEOUT)?;
+                    ctx.read_byte(BYTE_TIMEOUT)?;
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Wait for the receiver to be ready, returns if the receiver supports CRC32
+    fn wait_for_zrinit(ctx: &mut TransferContext, request: &[u8]) -> Result<bool, TransferError> {
+        let mut errors = 0;
+        loop {
+            match read_header(ctx, HEADER_TIMEOUT)? {
+                Some((header, _)) if header.frame_type == ZRINIT => {
+                    return Ok(header.flags() & CANFC32 != 0)
+                }
+                Some(_) => {}
+                None => Self::retry(ctx, &mut errors, request)?,
+            }
+        }
+    }
+
+    /// Send a single file
+    fn send_file(
+        ctx: &mut TransferContext,
+        file_name: &str,
+        data: &[u8],
+        use_crc32: bool,
+    ) -> Result<(), TransferError> {
+        let mut errors = 0;
+
+        // Send file info until the receiver asks for data
+        let mut info = format!("{}\0{}", file_name, data.len()).into_bytes();
+        info.push(0);
+        let mut zfile = binary_header(Header::with_flags(ZFILE, ZCBIN), use_crc32);
+        zfile.extend(subpacket(&info, ZCRCW, use_crc32));
+
+        ctx.write_all(&zfile)?;
+        let mut position = loop {
+            match read_header(ctx, HEADER_TIMEOUT)? {
+                Some((header, _)) if header.frame_type == ZRPOS => break header.position(),
+                Some((header, _)) if header.frame_type == ZSKIP => return Ok(()),
+                Some((header, _)) if header.frame_type == ZACK => {}
+                _ => Self::retry(ctx, &mut errors, &zfile)?,
+            }
+        };
+
+        // Position of the last ZRPOS, only repeated requests for the same position are errors
+        let mut last_rpos = None;
+        loop {
+            // Stream the file from the requested position
+            ctx.set_bytes(position as u64);
+            if (position as usize) < data.len() {
+                ctx.write_all(&binary_header(
+                    Header::with_position(ZDATA, position),
+                    use_crc32,
+                ))?;
+            }
+
+            let mut interrupted = false;
+            while (position as usize) < data.len() {
+                let start = position as usize;
+                let end = data.len().min(start + SUBPACKET_SIZE);
+                let frame_end = if end == data.len() { ZCRCE } else { ZCRCG };
+
+                ctx.write_all(&subpacket(&data[start..end], frame_end, use_crc32))?;
+                position = end as u32;
+                ctx.set_bytes(position as u64);
+
+                // The receiver only interrupts a stream to report an error
+                while let Some(XON) | Some(XOFF) | Some(b'\r') | Some(b'\n') | Some(0x8a) =
+                    ctx.peek_byte()?
+                {
+                    ctx.read_byte(BYTE_TIMEOUT)?;
+                }
+                if ctx.peek_byte()?.is_some() {
+                    if let Some((header, _)) = read_header(ctx, BYTE_TIMEOUT)? {
+                        if header.frame_type == ZRPOS {
+                            position = Self::rewind(ctx, &mut errors, &mut last_rpos, header)?;
+                            interrupted = true;
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if interrupted {
+                continue;
+            }
+
+            // End of file, wait for the receiver to be ready for the next file
+            let zeof = hex_header(Header::with_position(ZEOF, data.len() as u32));
+            ctx.write_all(&zeof)?;
+            loop {
+                match read_header(ctx, HEADER_TIMEOUT)? {
+                    Some((header, _)) if header.frame_type == ZRINIT => return Ok(()),
+                    Some((header, _)) if header.frame_type == ZRPOS => {
+                        position = Self::rewind(ctx, &mut errors, &mut last_rpos, header)?;
+                        break;
+                    }
+                    Some(_) => {}
+                    None => Self::retry(ctx, &mut errors, &zeof)?,
+                }
+            }
+        }
+    }
+
+    /// Send files
+    fn send_files(
+        &self,
+        ctx: &mut TransferContext,
+        files: &[PathBuf],
+    ) -> Result<(), TransferError> {
+        // Start the remote receiver in case it isn't already running
+        let mut zrqinit = b"rz\r".to_vec();
+        zrqinit.extend(hex_header(Header::with_flags(ZRQINIT, 0)));
+        ctx.write_all(&zrqinit)?;
+        let use_crc32 = Self::wait_for_zrinit(ctx, &zrqinit)?;
+
+        for file in files {
+            let data = fs::read(file)?;
+            let file_name = file
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            ctx.start_file(&file_name, Some(data.len() as u64));
+
+            Self::send_file(ctx, &file_name, &data, use_crc32)?;
+        }
+
+        // End the session
+        let zfin = hex_header(Header::with_position(ZFIN, 0));
+        let mut errors = 0;
+        ctx.write_all(&zfin)?;
+        loop {
+            match read_header(ctx, HEADER_TIMEOUT)? {
+                Some((header, _)) if header.frame_type == ZFIN => {
+                    ctx.write_all(b"OO")?;
+                    return Ok(());
+                }
+                Some(_) => {}
+                None => Self::retry(ctx, &mut errors, &zfin)?,
+            }
+        }
+    }
+}
+
+impl TransferProtocol for Zmodem {
+    fn send(&mut self, ctx: &mut TransferContext, files: &[PathBuf]) -> Result<(), TransferError> {
+        let res = self.send_files(ctx, files);
+        Self::cancel_on_error(ctx, res)
+    }
+
+    fn receive(&mut self, ctx: &mut TransferContext, dest: &Path) -> Result<(), TransferError> {
+        let res = self.receive_files(ctx, dest);
+        Self::cancel_on_error(ctx, res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::loopback::LoopbackPort;
+    use crate::transfer::Protocol;
+    use serialport::SerialPortSettings;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    /// Parse a header from received data, returning it and the data left over
+    fn parse(received: &[u8]) -> (Option<(Header, bool)>, Vec<u8>) {
+        let mut port = LoopbackPort::open("loop://", &SerialPortSettings::default()).unwrap();
+        let mut ctx = TransferContext::new(
+            &mut port,
+            Protocol::Zmodem,
+            Direction::Receive,
+            received.to_vec(),
+            Arc::new(AtomicBool::new(false)),
+            Box::new(|_| {}),
+        );
+        let header = read_header(&mut ctx, BYTE_TIMEOUT).unwrap();
+        let mut rest = Vec::new();
+        while let Some(byte) = ctx.read_byte(Duration::from_millis(1)).unwrap() {
+            rest.push(byte);
+        }
+        (header, rest)
+    }
+
+    #[test]
+    fn hex_header_round_trip() {
+        let header = Header::with_position(ZRPOS, 0x1234_5678);
+        let mut data = b"garbage".to_vec();
+        data.extend(hex_header(header));
+        let (parsed, rest) = parse(&data);
+        let (parsed, use_crc32) = parsed.unwrap();
+        assert_eq!(parsed.frame_type, ZRPOS);
+        assert_eq!(parsed.position(), 0x1234_5678);
+        assert!(!use_crc32);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn hex_header_keeps_next_frame() {
+        let mut data = hex_header(Header::with_flags(ZACK, 0));
+        data.extend(b"*\x18A");
+        let (parsed, rest) = parse(&data);
+        assert_eq!(parsed.unwrap().0.frame_type, ZACK);
+        assert_eq!(rest, b"*\x18A");
+    }
+
+    #[test]
+    fn binary_header_round_trip() {
+        for use_crc32 in [false, true] {
+            // 0x11 is XON and has to be escaped
+            let header = Header::with_flags(ZRINIT, CANFC32 | 0x11);
+            let (parsed, _) = parse(&binary_header(header, use_crc32));
+            let (parsed, parsed_crc32) = parsed.unwrap();
+            assert_eq!(parsed.frame_type, ZRINIT);
+            assert_eq!(parsed.flags(), CANFC32 | 0x11);
+            assert_eq!(parsed_crc32, use_crc32);
+        }
+    }
+
+    #[test]
+    fn bad_crc() {
+        let mut data = hex_header(Header::with_flags(ZRINIT, 0));
+        data[5] = b'2';
+        assert!(parse(&data).0.is_none());
+
+        let mut data = binary_header(Header::with_flags(ZRINIT, 0), true);
+        data[4] ^= 0x01;
+        assert!(parse(&data).0.is_none());
+    }
+
+    #[test]
+    fn remote_cancel() {
+        let mut port = LoopbackPort::open("loop://", &SerialPortSettings::default()).unwrap();
+        let mut ctx = TransferContext::new(
+            &mut port,
+            Protocol::Zmodem,
+            Direction::Receive,
+            CANCEL_SEQUENCE.to_vec(),
+            Arc::new(AtomicBool::new(false)),
+            Box::new(|_| {}),
+        );
+        assert!(matches!(
+            read_header(&mut ctx, BYTE_TIMEOUT),
+            Err(TransferError::RemoteCancelled)
+        ));
+    }
+
+    #[test]
+    fn detector() {
+        let mut detector = ZmodemDetector::default();
+        assert_eq!(detector.detect(b"rz\r**\x18B0"), None);
+        assert_eq!(detector.detect(b"0000"), Some(Direction::Receive));
+        detector.hold(b"more");
+        assert!(detector.take_held().ends_with(b"**\x18B00000more"));
+        assert!(detector.take_held().is_empty());
+
+        assert_eq!(detector.detect(b"**\x18B0100"), Some(Direction::Send));
+        assert_eq!(detector.detect(b"plain text"), None);
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt::{Display, Formatter};
+use std::sync::mpsc::Sender;
+
+use regex::bytes::Regex;
+use serde::Deserialize;
+
+use crate::checksum::TxChecksum;
+use crate::macros::{Macro, MacroDef};
+
+/// Number of received bytes kept for matching, patterns longer than this will not match
+const MATCH_WINDOW_SIZE: usize = 4096;
+
+/// Trigger rule as written in the config file
+#[derive(Debug, Clone, Deserialize)]
+pub struct TriggerDef {
+    /// Pattern to look for in received data
+    pub pattern: String,
+    /// Response to send, same format as a macro
+    pub send: MacroDef,
+    /// Fire every time the pattern is seen, otherwise only the first time
+    #[serde(default)]
+    pub repeat: bool,
+    /// Whether the rule starts enabled
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+/// Rules start enabled unless the config says otherwise
+fn default_enabled() -> bool {
+    true
+}
+
+/// Trigger rule, sends a response when received data matches a pattern
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    /// Pattern to look for in received data
+    pub pattern: Regex,
+    /// Response to send
+    pub response: Macro,
+    /// Fire every time the pattern is seen
+    pub repeat: bool,
+    /// Rule is enabled
+    pub enabled: bool,
+    /// One-shot rule has already fired
+    pub fired: bool,
+}
+
+impl Trigger {
+    /// Parse a trigger rule definition
+    pub fn parse(def: &TriggerDef) -> Result<Self, String> {
+        let pattern =
+            Regex::new(&def.pattern).map_err(|e| format!("Trigger '{}': {}", def.pattern, e))?;
+        // A pattern that matches nothing would fire on every byte without consuming any
+        if pattern.is_match(b"") {
+            return Err(format!(
+                "Trigger '{}': pattern matches empty text",
+                def.pattern
+            ));
+        }
+        let response =
+            Macro::parse(&def.send).map_err(|e| format!("Trigger '{}': {}", def.pattern, e))?;
+
+        Ok(Self {
+            pattern,
+            response,
+            repeat: def.repeat,
+            enabled: def.enabled,
+            fired: false,
+        })
+    }
+
+    /// Check if the rule can fire
+    fn armed(&self) -> bool {
+        self.enabled && (self.repeat || !self.fired)
+    }
+}
+
+/// Trigger rules and the received data they are matched against
+#[derive(Debug, Clone, Default)]
+pub struct Triggers {
+    /// Rules in config order
+    pub rules: Vec<Trigger>,
+    /// Received data not yet consumed by a match
+    window: Vec<u8>,
+}
+
+impl Triggers {
+    /// Parse trigger rule definitions from the config file
+    pub fn parse(defs: &[TriggerDef]) -> Result<Self, String> {
+        Ok(Self {
+            rules: defs.iter().map(Trigger::parse).collect::<Result<_, _>>()?,
+            window: Vec::new(),
+        })
+    }
+
+    /// Match received data against the rules, sending the responses of any that fire
+    ///
+    /// Data is kept between calls so patterns split across reads still match. Data up to the
+    /// end of a match is consumed so the same text can't fire a rule twice. `tx_checksum` is
+    /// appended to hex responses like it is for macros.
+    pub fn process(
+        &mut self,
+        data: &[u8],
+        serial_send_tx: &Sender<Vec<u8>>,
+        tx_checksum: Option<&TxChecksum>,
+    ) {
+        if !self.rules.iter().any(Trigger::armed) {
+            self.window.clear();
+            return;
+        }
+
+        self.window.extend_from_slice(data);
+
+        // Fire the earliest match first, then look again in what follows it
+        loop {
+            let earliest = self
+                .rules
+                .iter()
+                .enumerate()
+                .filter(|(_, rule)| rule.armed())
+                .filter_map(|(i, rule)| rule.pattern.find(&self.window).map(|m| (i, m.end())))
+                .min_by_key(|(_, end)| *end);
+
+            match earliest {
+                Some((i, end)) => {
+                    let rule = &mut self.rules[i];
+                    rule.fired = true;
+                    rule.response.run(serial_send_tx, tx_checksum);
+                    self.window.drain(..end);
+                }
+                None => break,
+            }
+        }
+
+        if self.window.len() > MATCH_WINDOW_SIZE {
+            self.window.drain(..self.window.len() - MATCH_WINDOW_SIZE);
+        }
+    }
+
+    /// Toggle a rule by its 1-based number, re-arming one-shot rules when enabled
+    pub fn toggle(&mut self, number: usize) {
+        if let Some(rule) = number.checked_sub(1).and_then(|i| self.rules.get_mut(i)) {
+            rule.enabled = !rule.enabled;
+            rule.fired = false;
+        }
+    }
+
+    /// Enable all rules if any are disabled, otherwise disable them all
+    pub fn toggle_all(&mut self) {
+        let enable = self.rules.iter().any(|rule| !rule.enabled);
+        for rule in &mut self.rules {
+            rule.enabled = enable;
+            rule.fired = false;
+        }
+    }
+}
+
+impl Display for Triggers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, rule) in self.rules.iter().enumerate() {
+            let state = if !rule.enabled {
+                "off"
+            } else if rule.armed() {
+                "on"
+            } else {
+                "done"
+            };
+            write!(f, "[{}:{} {}] ", i + 1, state, rule.pattern)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::ChecksumKind;
+    use std::sync::mpsc;
+
+    fn def(pattern: &str, repeat: bool) -> TriggerDef {
+        TriggerDef {
+            pattern: pattern.to_string(),
+            send: MacroDef::Text("ok\\r".to_string()),
+            repeat,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn rejects_empty_matches() {
+        for pattern in ["x*", "a?", "^", "$", "(ab)*"] {
+            assert!(Trigger::parse(&def(pattern, true)).is_err(), "{}", pattern);
+        }
+        assert!(Trigger::parse(&def("x+", true)).is_ok());
+    }
+
+    #[test]
+    fn fires_once_per_match() {
+        let (tx, rx) = mpsc::channel();
+        let mut triggers = Triggers::parse(&[def("x+", true), def("login:", false)]).unwrap();
+
+        triggers.process(b"xx y x log", &tx, None);
+        triggers.process(b"in: login:", &tx, None);
+        let sent: Vec<Vec<u8>> = rx.try_iter().collect();
+        assert_eq!(sent, vec![b"ok\r".to_vec(); 3]);
+        assert!(!triggers.rules[1].armed());
+    }
+
+    #[test]
+    fn tx_checksum() {
+        let (tx, rx) = mpsc::channel();
+        let mut hex = def("ping", true);
+        hex.send = MacroDef::Hex {
+            hex: "01 02".to_string(),
+            checksum: None,
+            endian: None,
+        };
+        let mut triggers = Triggers::parse(&[hex, def("pong", true)]).unwrap();
+        let checksum = TxChecksum::new(ChecksumKind::Sum8, None);
+
+        // Text responses are sent as they are
+        triggers.process(b"ping pong", &tx, Some(&checksum));
+        let sent: Vec<Vec<u8>> = rx.try_iter().collect();
+        assert_eq!(sent, vec![vec![0x01, 0x02, 0x03], b"ok\r".to_vec()]);
+    }
+}
@@ -0,0 +1,207 @@
+use std::io::Write;
+
+use crossterm::cursor::{MoveTo, RestorePosition, SavePosition};
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::{execute, queue};
+
+use crate::app::error::Result;
+use crate::app::{CerialMode, CerialState};
+use crate::ui::layout::Panes;
+
+/// Receive pane cursor, saved with `SavePosition` whenever the cursor leaves the pane
+///
+/// Received data is written at the saved position and the terminal scroll region keeps it inside
+/// the receive pane.
+pub fn write_rx<T: Write>(stream: &mut T, data: &[u8]) -> Result<()> {
+    queue!(stream, RestorePosition)?;
+    stream.write_all(data)?;
+    queue!(stream, SavePosition)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Set the terminal scroll region to the receive pane
+pub fn set_rx_region<T: Write>(stream: &mut T, panes: &Panes) -> Result<()> {
+    write!(stream, "\x1b[1;{}r", panes.rx_height)?;
+    Ok(())
+}
+
+/// Reset the terminal scroll region to the whole screen
+pub fn reset_rx_region<T: Write>(stream: &mut T) -> Result<()> {
+    write!(stream, "\x1b[r")?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Redraw the receive pane from the scrollback, or draw the plot
+///
+/// When scrolled to the bottom the last row holds the line being received and the cursor is left
+/// at its end.
+pub fn redraw_rx<T: Write>(
+    stream: &mut T,
+    state: &mut CerialState,
+    panes: &Panes,
+    cols: u16,
+) -> Result<()> {
+    let height = panes.rx_height as usize;
+    let live = state.scroll_offset == 0;
+
+    // The plot fills the pane in place of the text
+    if let Some(plot) = &mut state.plot {
+        for (row, line) in plot.render(cols as usize, height).iter().enumerate() {
+            queue!(stream, MoveTo(0, row as u16), Clear(ClearType::CurrentLine))?;
+            stream.write_all(line.as_bytes())?;
+        }
+        queue!(stream, MoveTo(0, panes.rx_height - 1), SavePosition)?;
+        stream.flush()?;
+        return Ok(());
+    }
+
+    // Walk back from the newest line until the pane is full of lines the filter allows
+    let mut lines = Vec::new();
+    let mut current = None;
+    for (i, line) in state
+        .scrollback
+        .window(state.scroll_offset, state.scrollback.len())
+        .rev()
+        .enumerate()
+    {
+        let rendered = state.renderer.render_stored(line, cols as usize);
+        if live && i == 0 {
+            current = rendered;
+            continue;
+        }
+        if lines.len() + live as usize >= height {
+            break;
+        }
+        if let Some(rendered) = rendered {
+            lines.push(rendered);
+        }
+    }
+    lines.reverse();
+
+    for row in 0..panes.rx_height {
+        queue!(stream, MoveTo(0, row), Clear(ClearType::CurrentLine))?;
+    }
+    for (row, (line, _)) in lines.iter().enumerate() {
+        queue!(stream, MoveTo(0, row as u16))?;
+        stream.write_all(line)?;
+    }
+
+    // Received data carries on from the end of the current line
+    let row = lines.len() as u16;
+    let col = match &current {
+        Some((line, width)) => {
+            queue!(stream, MoveTo(0, row))?;
+            stream.write_all(line)?;
+            *width as u16
+        }
+        None => 0,
+    };
+    state.renderer.set_displayed(current.is_some());
+    queue!(stream, MoveTo(col, row), SavePosition)?;
+    stream.flush()?;
+
+    Ok(())
+}
+
+/// Draw a pane title line
+fn draw_title<T: Write>(stream: &mut T, row: u16, title: &str, cols: u16) -> Result<()> {
+    let mut line = format!("── {} ", title);
+    let width = line.chars().count();
+    line.extend(std::iter::repeat_n(
+        '─',
+        (cols as usize).saturating_sub(width),
+    ));
+
+    queue!(
+        stream,
+        MoveTo(0, row),
+        Clear(ClearType::CurrentLine),
+        SetForegroundColor(Color::DarkGrey),
+        Print(line.chars().take(cols as usize).collect::<String>()),
+        ResetColor
+    )?;
+    Ok(())
+}
+
+/// Draw a line of text, cut to the terminal width
+fn draw_line<T: Write>(stream: &mut T, row: u16, text: &str, cols: u16) -> Result<()> {
+    queue!(
+        stream,
+        MoveTo(0, row),
+        Clear(ClearType::CurrentLine),
+        SetForegroundColor(Color::White),
+        Print(text.chars().take(cols as usize).collect::<String>()),
+        ResetColor
+    )?;
+    Ok(())
+}
+
+/// Draw the transmit pane, status panel, and menu bar, then put the cursor back
+///
+/// The cursor is left on the transmit line while editing it, otherwise in the receive pane.
+pub fn draw_panes<T: Write>(
+    stream: &mut T,
+    state: &CerialState,
+    panes: &Panes,
+    cols: u16,
+) -> Result<()> {
+    let mut cursor = None;
+
+    if let Some(row) = panes.tx_row {
+        draw_title(stream, row, "TX", cols)?;
+        match state.mode {
+            CerialMode::Line | CerialMode::At | CerialMode::Nmea | CerialMode::HexInput => {
+                let (line, col) = state.line_editor.display();
+                // Scroll long lines sideways to keep the cursor on screen
+                let skip = (col + 1).saturating_sub(cols as usize);
+                let line: String = line.chars().skip(skip).collect();
+                draw_line(stream, row + 1, &line, cols)?;
+                cursor = Some(((col - skip) as u16, row + 1));
+            }
+            _ => {
+                queue!(
+                    stream,
+                    MoveTo(0, row + 1),
+                    Clear(ClearType::CurrentLine),
+                    SetForegroundColor(Color::DarkGrey),
+                    Print("Press l in menu mode to edit a line"),
+                    ResetColor
+                )?;
+            }
+        }
+    }
+
+    if let Some(row) = panes.status_row {
+        draw_title(stream, row, "Status", cols)?;
+        let mut lines = state.status_lines().into_iter();
+        for i in 1..=panes.status_height {
+            draw_line(stream, row + i, &lines.next().unwrap_or_default(), cols)?;
+        }
+    }
+
+    draw_line(stream, panes.menu_row, &state.menu_string(), cols)?;
+
+    match cursor {
+        Some((col, row)) => queue!(stream, MoveTo(col, row))?,
+        None => queue!(stream, RestorePosition)?,
+    }
+    stream.flush()?;
+
+    Ok(())
+}
+
+/// Clear the screen and draw every pane
+pub fn redraw_all<T: Write>(
+    stream: &mut T,
+    state: &mut CerialState,
+    panes: &Panes,
+    cols: u16,
+) -> Result<()> {
+    execute!(stream, Clear(ClearType::All))?;
+    set_rx_region(stream, panes)?;
+    redraw_rx(stream, state, panes, cols)?;
+    draw_panes(stream, state, panes, cols)
+}
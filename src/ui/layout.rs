@@ -0,0 +1,82 @@
+/// Smallest status panel height
+const MIN_STATUS_HEIGHT: u16 = 1;
+/// Largest status panel height
+const MAX_STATUS_HEIGHT: u16 = 8;
+
+/// Which panes are shown and their sizes
+#[derive(Debug, Copy, Clone)]
+pub struct Layout {
+    /// Show the transmit line-edit pane
+    pub show_tx: bool,
+    /// Show the status panel
+    pub show_status: bool,
+    /// Status panel height, not counting its title line
+    pub status_height: u16,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            show_tx: true,
+            show_status: true,
+            status_height: 3,
+        }
+    }
+}
+
+/// Screen rows of each pane
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Panes {
+    /// Number of rows in the receive pane, which starts at the top of the screen
+    pub rx_height: u16,
+    /// Title row of the transmit pane, the edit line is below it
+    pub tx_row: Option<u16>,
+    /// Title row of the status panel, its lines are below it
+    pub status_row: Option<u16>,
+    /// Status panel height, not counting its title line
+    pub status_height: u16,
+    /// Menu bar row, always the last row
+    pub menu_row: u16,
+}
+
+impl Layout {
+    /// Grow or shrink the status panel
+    pub fn resize_status(&mut self, grow: bool) {
+        self.status_height = if grow {
+            (self.status_height + 1).min(MAX_STATUS_HEIGHT)
+        } else {
+            self.status_height.saturating_sub(1).max(MIN_STATUS_HEIGHT)
+        };
+    }
+
+    /// Grow the status panel to at least `height` lines
+    pub fn grow_status(&mut self, height: u16) {
+        self.status_height = self.status_height.max(height.min(MAX_STATUS_HEIGHT));
+    }
+
+    /// Get the pane rows for a terminal with this many rows
+    ///
+    /// Panes that don't fit while leaving the receive pane a few rows are left out
+    pub fn panes(&self, rows: u16) -> Panes {
+        let menu_row = rows.saturating_sub(1);
+        let mut bottom = menu_row;
+        let mut panes = Panes {
+            menu_row,
+            ..Default::default()
+        };
+
+        if self.show_status && bottom > self.status_height + 1 + 3 {
+            bottom -= self.status_height + 1;
+            panes.status_row = Some(bottom);
+            panes.status_height = self.status_height;
+        }
+
+        if self.show_tx && bottom > 2 + 3 {
+            bottom -= 2;
+            panes.tx_row = Some(bottom);
+        }
+
+        panes.rx_height = bottom.max(1);
+        panes
+    }
+}
@@ -0,0 +1,301 @@
+use crate::history::History;
+
+/// Reverse history search started with `Ctrl-R`
+#[derive(Debug, Clone, Default)]
+struct Search {
+    /// Text being searched for
+    query: String,
+    /// History entry matching the query
+    found: Option<usize>,
+    /// The query has no more matches
+    failed: bool,
+}
+
+/// Line being edited in the transmit pane
+#[derive(Debug, Clone, Default)]
+pub struct LineEditor {
+    /// Line contents
+    buffer: Vec<char>,
+    /// Cursor position in characters
+    cursor: usize,
+    /// Lines sent in the current mode
+    history: History,
+    /// Mode the history belongs to
+    mode: &'static str,
+    /// Lines sent in the other modes
+    other_histories: Vec<(&'static str, History)>,
+    /// History entry being shown, `None` while editing a new line
+    recall: Option<usize>,
+    /// New line put aside while recalling history
+    draft: Vec<char>,
+    /// Reverse search in progress
+    search: Option<Search>,
+}
+
+impl LineEditor {
+    /// Create a line editor with the lines previously sent in each mode, the first mode is current
+    pub fn new(mut histories: Vec<(&'static str, History)>) -> Self {
+        let (mode, history) = if histories.is_empty() {
+            ("", History::default())
+        } else {
+            histories.remove(0)
+        };
+        Self {
+            history,
+            mode,
+            other_histories: histories,
+            ..Default::default()
+        }
+    }
+
+    /// Recall and search the lines sent in another mode
+    ///
+    /// The line being edited is kept, modes without a history share the current one
+    pub fn set_mode(&mut self, mode: &'static str) {
+        if mode == self.mode {
+            return;
+        }
+        if let Some(i) = self
+            .other_histories
+            .iter()
+            .position(|(name, _)| *name == mode)
+        {
+            let (_, history) = self.other_histories.swap_remove(i);
+            let previous = std::mem::replace(&mut self.history, history);
+            self.other_histories.push((self.mode, previous));
+            self.mode = mode;
+            self.recall = None;
+            self.draft.clear();
+            self.search = None;
+        }
+    }
+
+    /// Replace the line and move the cursor to its end
+    fn set_line(&mut self, line: Vec<char>) {
+        self.cursor = line.len();
+        self.buffer = line;
+    }
+
+    /// Get the line contents
+    pub fn text(&self) -> String {
+        self.buffer.iter().collect()
+    }
+
+    /// Replace the line contents, leaving the cursor at the end
+    pub fn replace(&mut self, text: &str) {
+        self.set_line(text.chars().collect());
+    }
+
+    /// Insert a character at the cursor
+    pub fn insert(&mut self, c: char) {
+        if let Some(search) = &mut self.search {
+            search.query.push(c);
+            let query = search.query.clone();
+            self.search_from(&query, self.history.len());
+            return;
+        }
+        self.buffer.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    /// Delete the character before the cursor
+    pub fn backspace(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.query.pop();
+            let query = search.query.clone();
+            self.search_from(&query, self.history.len());
+            return;
+        }
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.buffer.remove(self.cursor);
+        }
+    }
+
+    /// Delete the character under the cursor
+    pub fn delete(&mut self) {
+        self.end_search();
+        if self.cursor < self.buffer.len() {
+            self.buffer.remove(self.cursor);
+        }
+    }
+
+    /// Move the cursor one character left
+    pub fn left(&mut self) {
+        self.end_search();
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    /// Move the cursor one character right
+    pub fn right(&mut self) {
+        self.end_search();
+        self.cursor = (self.cursor + 1).min(self.buffer.len());
+    }
+
+    /// Move the cursor to the start of the line
+    pub fn home(&mut self) {
+        self.end_search();
+        self.cursor = 0;
+    }
+
+    /// Move the cursor to the end of the line
+    pub fn end(&mut self) {
+        self.end_search();
+        self.cursor = self.buffer.len();
+    }
+
+    /// Delete the word before the cursor, like `Ctrl-W` in a shell
+    pub fn delete_word(&mut self) {
+        self.end_search();
+        let mut start = self.cursor;
+        while start > 0 && self.buffer[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.buffer[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.buffer.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    /// Delete everything before the cursor, like `Ctrl-U` in a shell
+    pub fn delete_to_start(&mut self) {
+        self.end_search();
+        self.buffer.drain(..self.cursor);
+        self.cursor = 0;
+    }
+
+    /// Show the previous history entry
+    pub fn older(&mut self) {
+        self.end_search();
+        let index = match self.recall {
+            Some(0) => return,
+            Some(index) => index - 1,
+            None if self.history.is_empty() => return,
+            None => self.history.len() - 1,
+        };
+        self.recall_entry(index);
+    }
+
+    /// Show the next history entry, or the new line after the newest entry
+    pub fn newer(&mut self) {
+        self.end_search();
+        match self.recall {
+            Some(index) if index + 1 < self.history.len() => self.recall_entry(index + 1),
+            Some(_) => {
+                self.recall = None;
+                let draft = std::mem::take(&mut self.draft);
+                self.set_line(draft);
+            }
+            None => {}
+        }
+    }
+
+    /// Show a history entry in the line
+    fn recall_entry(&mut self, index: usize) {
+        if let Some(entry) = self.history.get(index) {
+            let line = entry.chars().collect();
+            if self.recall.is_none() {
+                self.draft = std::mem::take(&mut self.buffer);
+            }
+            self.recall = Some(index);
+            self.set_line(line);
+        }
+    }
+
+    /// Start a reverse search, or find an older match if one is running
+    pub fn search(&mut self) {
+        match &self.search {
+            Some(search) => {
+                let query = search.query.clone();
+                let before = search.found.unwrap_or(self.history.len());
+                self.search_from(&query, before);
+            }
+            None => self.search = Some(Search::default()),
+        }
+    }
+
+    /// Show the newest match for `query` older than the entry `before`
+    fn search_from(&mut self, query: &str, before: usize) {
+        let found = self.history.search(query, before);
+        if let Some(index) = found {
+            self.recall_entry(index);
+        }
+        if let Some(search) = &mut self.search {
+            // Keep the last match when the query stops matching
+            search.found = found.or(search.found);
+            search.failed = found.is_none() && !query.is_empty();
+        }
+    }
+
+    /// Stop searching and keep the matched line for editing
+    pub fn end_search(&mut self) {
+        self.search = None;
+    }
+
+    /// Stop searching and go back to the line from before the search
+    pub fn cancel_search(&mut self) {
+        if self.search.take().is_some() && self.recall.is_some() {
+            self.recall = None;
+            let draft = std::mem::take(&mut self.draft);
+            self.set_line(draft);
+        }
+    }
+
+    /// Take the line to send and add it to the history
+    pub fn accept(&mut self) -> String {
+        let line: String = self.buffer.drain(..).collect();
+        self.history.push(&line);
+        self.cursor = 0;
+        self.recall = None;
+        self.draft.clear();
+        self.search = None;
+        line
+    }
+
+    /// Get the prompt and line to display, with the cursor position in characters
+    pub fn display(&self) -> (String, usize) {
+        let prompt = match &self.search {
+            Some(search) if search.failed => {
+                format!("(failed reverse-i-search)`{}': ", search.query)
+            }
+            Some(search) => format!("(reverse-i-search)`{}': ", search.query),
+            None => "> ".to_string(),
+        };
+        let cursor = prompt.chars().count() + self.cursor;
+        let mut line = prompt;
+        line.extend(&self.buffer);
+        (line, cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode_histories() {
+        let mut editor = LineEditor::new(vec![
+            ("line", History::default()),
+            ("hex", History::default()),
+        ]);
+        editor.replace("hello");
+        editor.accept();
+
+        editor.set_mode("hex");
+        editor.replace("01 02");
+        editor.accept();
+        editor.older();
+        assert_eq!(editor.text(), "01 02");
+        editor.older();
+        assert_eq!(editor.text(), "01 02");
+
+        // Hex frames aren't recalled in line mode
+        editor.set_mode("line");
+        editor.replace("");
+        editor.older();
+        assert_eq!(editor.text(), "hello");
+        editor.older();
+        assert_eq!(editor.text(), "hello");
+    }
+}
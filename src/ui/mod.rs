@@ -1,8 +1,19 @@
+pub mod draw;
 pub mod input;
+pub mod layout;
+pub mod line_edit;
+pub mod render;
+pub mod scrollback;
 
+use crate::baud::AutoBaudStatus;
+use crate::modbus::ModbusStatus;
+use crate::script::ScriptStatus;
+use crate::send::SendStatus;
 use crate::serial::SerialTelemetry;
+use crate::transfer::TransferStatus;
 use crossterm::event;
 use crossterm::event::{Event, KeyEvent};
+use std::process::ExitStatus;
 use std::sync::mpsc::Sender;
 use std::time::Duration;
 
@@ -14,10 +25,28 @@ pub enum DisplayUpdateEvent {
     KeyInput(KeyEvent),
     /// Serial data input
     SerialInput(Vec<u8>),
+    /// Serial port read error, the serial RX thread exits after sending this
+    SerialError(std::io::Error),
     /// Serial telemetry update event
     SerialTelemetry(SerialTelemetry),
     /// Terminal update event (cols, rows)
     TerminalResize(u16, u16),
+    /// Exec child process exit event
+    ProcessExit(ExitStatus),
+    /// File send status update
+    FileSend(SendStatus),
+    /// File transfer status update
+    Transfer(TransferStatus),
+    /// Script status update
+    Script(ScriptStatus),
+    /// Baud rate changed by a script
+    BaudRate(u32),
+    /// Modbus master command status update
+    Modbus(ModbusStatus),
+    /// Auto baud detection status update
+    AutoBaud(AutoBaudStatus),
+    /// Stdin closed in pipe mode and everything read from it has been sent
+    InputClosed,
 }
 
 /// Terminal Event Thread
@@ -0,0 +1,262 @@
+use std::time::{Duration, Instant};
+
+use crate::filter::LineFilter;
+use crate::highlight::{line_styles, write_styled, HighlightRule};
+
+/// Lines longer than this are displayed without waiting for the line to end, and the rest of the
+/// line is held as a new one
+const MAX_HELD_LINE: usize = 1024;
+
+/// How long received data must pause before a partial line is shown while a filter is active
+const FILTERED_IDLE: Duration = Duration::from_millis(500);
+
+/// Highlights and filters received text a line at a time
+///
+/// Lines are held until they end so patterns split across reads still match. A partial line,
+/// like a shell prompt, is shown when [`LineRenderer::flush_idle`] is called. Once part of a line
+/// has been shown the rest of it is too, even if a filter would have hidden the whole line.
+#[derive(Debug, Clone, Default)]
+pub struct LineRenderer {
+    /// Highlight rules in priority order, the mode rules then the configured rules
+    rules: Vec<HighlightRule>,
+    /// Highlight rules of each mode used so far, the current mode first
+    mode_rules: Vec<(&'static str, Vec<HighlightRule>)>,
+    /// Highlight rules from the config file
+    configured: Vec<HighlightRule>,
+    /// Line filter
+    pub filter: LineFilter,
+    /// Current line
+    line: Vec<u8>,
+    /// Bytes of the current line already displayed
+    displayed: usize,
+    /// The current line is the rest of a long line that was displayed, so it isn't filtered
+    continued: bool,
+    /// When data was last received
+    last_input: Option<Instant>,
+}
+
+impl LineRenderer {
+    /// Create a renderer, earlier highlight rules take priority over later ones
+    pub fn new(rules: Vec<HighlightRule>) -> Self {
+        Self {
+            rules: rules.clone(),
+            configured: rules,
+            ..Default::default()
+        }
+    }
+
+    /// Give the highlight rules of the current mode priority, `rules` is called to get them the
+    /// first time the mode is used
+    ///
+    /// Rules of modes used before follow them, then the configured rules. Returns whether the
+    /// rules changed.
+    pub fn set_mode_rules<F>(&mut self, mode: &'static str, rules: F) -> bool
+    where
+        F: FnOnce() -> Vec<HighlightRule>,
+    {
+        match self.mode_rules.iter().position(|(name, _)| *name == mode) {
+            Some(0) => return false,
+            Some(i) => {
+                let entry = self.mode_rules.remove(i);
+                self.mode_rules.insert(0, entry);
+            }
+            None => self.mode_rules.insert(0, (mode, rules())),
+        }
+
+        self.rules = self
+            .mode_rules
+            .iter()
+            .flat_map(|(_, rules)| rules.iter())
+            .chain(&self.configured)
+            .cloned()
+            .collect();
+        true
+    }
+
+    /// Get the display bytes for received data
+    pub fn render(&mut self, data: &[u8]) -> Vec<u8> {
+        self.last_input = Some(Instant::now());
+        if self.rules.is_empty() && !self.filter.is_active() {
+            return data.to_vec();
+        }
+
+        let mut out = Vec::new();
+        for &byte in data {
+            self.line.push(byte);
+            if byte == b'\n' {
+                self.render_line(&mut out);
+                self.line.clear();
+                self.displayed = 0;
+                self.continued = false;
+            }
+        }
+
+        // Cut long lines so they aren't styled over and over as they grow
+        if self.line.len() > MAX_HELD_LINE {
+            out.extend(self.flush());
+            self.continued = self.displayed > 0;
+            self.line.clear();
+            self.displayed = 0;
+        }
+
+        out
+    }
+
+    /// Get the display bytes for the held part of the current line if received data has paused
+    ///
+    /// With a filter active the line is held for longer, so a line that arrives in pieces is still
+    /// filtered as a whole
+    pub fn flush_idle(&mut self) -> Vec<u8> {
+        let idle = if self.filter.is_active() {
+            FILTERED_IDLE
+        } else {
+            Duration::ZERO
+        };
+        match self.last_input {
+            Some(last_input) if last_input.elapsed() < idle => Vec::new(),
+            _ => self.flush(),
+        }
+    }
+
+    /// Get the display bytes for the held part of the current line
+    fn flush(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if self.displayed < self.line.len() {
+            self.render_line(&mut out);
+        }
+        out
+    }
+
+    /// Get the display bytes for a line kept in the scrollback, or `None` if it is filtered out
+    ///
+    /// Escape sequences and control characters are removed and the line is cut to `width`
+    /// characters
+    pub fn render_stored(&self, line: &[u8], width: usize) -> Option<(Vec<u8>, usize)> {
+        let text = String::from_utf8_lossy(&strip_control(line))
+            .chars()
+            .take(width)
+            .collect::<String>();
+        if !self.filter.allows(text.as_bytes()) {
+            return None;
+        }
+
+        let mut out = Vec::new();
+        let styles = line_styles(&self.rules, text.as_bytes());
+        write_styled(text.as_bytes(), &styles, &mut out);
+        Some((out, text.chars().count()))
+    }
+
+    /// Set whether the held line has been displayed, after the screen was redrawn
+    pub fn set_displayed(&mut self, displayed: bool) {
+        self.displayed = if displayed { self.line.len() } else { 0 };
+    }
+
+    /// Render the part of the current line not yet displayed
+    fn render_line(&mut self, out: &mut Vec<u8>) {
+        if self.displayed == 0 && !self.continued {
+            let end = self
+                .line
+                .iter()
+                .rposition(|b| *b != b'\n' && *b != b'\r')
+                .map_or(0, |i| i + 1);
+            if !self.filter.allows(&self.line[..end]) {
+                return;
+            }
+        }
+
+        let styles = line_styles(&self.rules, &self.line);
+        write_styled(&self.line[self.displayed..], &styles[self.displayed..], out);
+        self.displayed = self.line.len();
+    }
+}
+
+/// Remove escape sequences and control characters from a line, tabs become spaces
+pub fn strip_control(line: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(line.len());
+    let mut bytes = line.iter().peekable();
+
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            0x1b => match bytes.next() {
+                // CSI sequences end with a byte in 0x40-0x7e
+                Some(b'[') => {
+                    for b in bytes.by_ref() {
+                        if (0x40..=0x7e).contains(b) {
+                            break;
+                        }
+                    }
+                }
+                // OSC sequences end with BEL or ST
+                Some(b']') => {
+                    while let Some(&b) = bytes.next() {
+                        if b == 0x07 || (b == 0x1b && bytes.next_if_eq(&&b'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            b'\t' => out.push(b' '),
+            b if b < 0x20 || b == 0x7f => {}
+            b => out.push(b),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlight::preset;
+
+    #[test]
+    fn mode_rules() {
+        let configured = preset("dmesg").unwrap();
+        let at = preset("at").unwrap().len();
+        let nmea = preset("nmea").unwrap().len();
+        let mut renderer = LineRenderer::new(configured.clone());
+
+        assert!(renderer.set_mode_rules("at", || preset("at").unwrap()));
+        assert!(!renderer.set_mode_rules("at", || unreachable!()));
+        assert!(renderer.set_mode_rules("nmea", || preset("nmea").unwrap()));
+        assert_eq!(renderer.rules.len(), nmea + at + configured.len());
+
+        // Switching back keeps both modes' rules, the current mode's first
+        assert!(renderer.set_mode_rules("at", || unreachable!()));
+        let order: Vec<&str> = renderer.mode_rules.iter().map(|(mode, _)| *mode).collect();
+        assert_eq!(order, ["at", "nmea"]);
+        assert_eq!(renderer.rules.len(), nmea + at + configured.len());
+    }
+
+    #[test]
+    fn long_lines() {
+        let mut renderer = LineRenderer::new(preset("dmesg").unwrap());
+        renderer.filter.include = LineFilter::parse_pattern("shown").unwrap();
+
+        // A long line that's displayed is cut, and the rest of it is displayed too
+        let mut out = renderer.render(b"shown ");
+        for _ in 0..100 {
+            out.extend(renderer.render(&[b'x'; 100]));
+            assert!(renderer.line.len() <= MAX_HELD_LINE);
+        }
+        out.extend(renderer.render(b"\n"));
+        let text = strip_control(&out);
+        assert_eq!(text.len(), 6 + 100 * 100);
+
+        // Filtered out long lines aren't held either
+        let mut out = Vec::new();
+        for _ in 0..100 {
+            out.extend(renderer.render(&[b'y'; 100]));
+            assert!(renderer.line.len() <= MAX_HELD_LINE);
+        }
+        out.extend(renderer.render(b"\n"));
+        assert!(strip_control(&out).is_empty());
+    }
+
+    #[test]
+    fn strips_control() {
+        assert_eq!(strip_control(b"\x1b[1;31mred\x1b[0m\tx\r\n"), b"red x");
+        assert_eq!(strip_control(b"\x1b]0;title\x07ok"), b"ok");
+    }
+}
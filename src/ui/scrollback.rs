@@ -0,0 +1,57 @@
+use std::collections::VecDeque;
+
+/// Number of lines kept by default
+const DEFAULT_MAX_LINES: usize = 10000;
+
+/// Received lines kept for redrawing and scrolling back, before any filtering
+#[derive(Debug, Clone)]
+pub struct Scrollback {
+    /// Lines without line feeds, the last line is the one being received
+    lines: VecDeque<Vec<u8>>,
+    /// Oldest lines are dropped past this count
+    max_lines: usize,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        let mut lines = VecDeque::new();
+        lines.push_back(Vec::new());
+        Self {
+            lines,
+            max_lines: DEFAULT_MAX_LINES,
+        }
+    }
+}
+
+impl Scrollback {
+    /// Add received data, returning the number of lines completed
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        let mut completed = 0;
+        for &byte in data {
+            if byte == b'\n' {
+                self.lines.push_back(Vec::new());
+                completed += 1;
+            } else if let Some(line) = self.lines.back_mut() {
+                line.push(byte);
+            }
+        }
+
+        while self.lines.len() > self.max_lines {
+            self.lines.pop_front();
+        }
+
+        completed
+    }
+
+    /// Number of lines, including the one being received
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Get up to `count` lines ending `offset` lines before the newest line
+    pub fn window(&self, offset: usize, count: usize) -> impl DoubleEndedIterator<Item = &[u8]> {
+        let end = self.lines.len().saturating_sub(offset);
+        let start = end.saturating_sub(count);
+        self.lines.range(start..end).map(|line| line.as_slice())
+    }
+}
@@ -0,0 +1,387 @@
+//! Round trips over the `loop://` virtual serial device, no hardware needed
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use serialport::{DataBits, FlowControl, Parity, SerialPort, SerialPortSettings, StopBits};
+
+// cerial is a binary crate, so the device and key encoding are built into the test directly
+#[path = "../src/ui/input.rs"]
+mod input;
+#[path = "../src/serial/loopback.rs"]
+mod loopback;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use input::key_event_to_vec;
+use loopback::{is_loopback_target, LoopbackPort};
+
+fn settings() -> SerialPortSettings {
+    SerialPortSettings {
+        baud_rate: 115200,
+        data_bits: DataBits::Eight,
+        flow_control: FlowControl::None,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+        timeout: Duration::from_millis(100),
+    }
+}
+
+fn read_exact(port: &mut LoopbackPort, len: usize) -> Vec<u8> {
+    let mut data = vec![0; len];
+    port.read_exact(&mut data).unwrap();
+    data
+}
+
+#[test]
+fn targets() {
+    assert!(is_loopback_target("loop://"));
+    assert!(is_loopback_target("echo://?latency=5"));
+    assert!(!is_loopback_target("/dev/ttyUSB0"));
+}
+
+#[test]
+fn echo() {
+    let mut port = LoopbackPort::open("loop://", &settings()).unwrap();
+    port.write_all(b"hello\r\n").unwrap();
+    assert_eq!(port.bytes_to_read().unwrap(), 7);
+    assert_eq!(read_exact(&mut port, 7), b"hello\r\n");
+    assert_eq!(port.bytes_to_read().unwrap(), 0);
+
+    let binary: Vec<u8> = (0..=255).collect();
+    port.write_all(&binary).unwrap();
+    assert_eq!(read_exact(&mut port, 256), binary);
+}
+
+#[test]
+fn read_times_out_when_empty() {
+    let mut port = LoopbackPort::open("loop://", &settings()).unwrap();
+    let start = Instant::now();
+    let err = port.read(&mut [0; 8]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+}
+
+#[test]
+fn pair() {
+    let (mut a, mut b) = LoopbackPort::pair(&settings());
+    a.write_all(b"ping").unwrap();
+    assert_eq!(a.bytes_to_read().unwrap(), 0);
+    assert_eq!(read_exact(&mut b, 4), b"ping");
+
+    // Reads wait for the other end to write
+    let writer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        b.write_all(b"pong").unwrap();
+    });
+    assert_eq!(read_exact(&mut a, 4), b"pong");
+    writer.join().unwrap();
+}
+
+#[test]
+fn clear_input() {
+    let mut port = LoopbackPort::open("loop://", &settings()).unwrap();
+    port.write_all(b"stale").unwrap();
+    port.clear(serialport::ClearBuffer::Input).unwrap();
+    assert_eq!(port.bytes_to_read().unwrap(), 0);
+}
+
+#[test]
+fn latency() {
+    let mut port = LoopbackPort::open("loop://?latency=50", &settings()).unwrap();
+    let start = Instant::now();
+    port.write_all(b"x").unwrap();
+    assert_eq!(port.bytes_to_read().unwrap(), 0);
+    assert_eq!(read_exact(&mut port, 1), b"x");
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[test]
+fn errors_flip_one_bit() {
+    let mut port = LoopbackPort::open("loop://?errors=1", &settings()).unwrap();
+    port.write_all(&[0x00; 32]).unwrap();
+    for byte in read_exact(&mut port, 32) {
+        assert_eq!(byte.count_ones(), 1);
+    }
+}
+
+#[test]
+fn bad_options() {
+    for target in [
+        "loop://?errors=2",
+        "loop://?errors=x",
+        "loop://?latency=-1",
+        "loop://?speed=9600",
+    ] {
+        assert!(
+            LoopbackPort::open(target, &settings()).is_err(),
+            "{}",
+            target
+        );
+    }
+}
+
+#[test]
+fn settings_round_trip() {
+    let mut port = LoopbackPort::open("loop://", &settings()).unwrap();
+    assert_eq!(port.name().as_deref(), Some("loop://"));
+    assert_eq!(port.baud_rate().unwrap(), 115200);
+
+    port.set_baud_rate(9600).unwrap();
+    port.set_data_bits(DataBits::Seven).unwrap();
+    port.set_parity(Parity::Even).unwrap();
+    port.set_stop_bits(StopBits::Two).unwrap();
+    port.set_flow_control(FlowControl::Hardware).unwrap();
+    assert_eq!(port.baud_rate().unwrap(), 9600);
+    assert_eq!(port.data_bits().unwrap(), DataBits::Seven);
+    assert_eq!(port.parity().unwrap(), Parity::Even);
+    assert_eq!(port.stop_bits().unwrap(), StopBits::Two);
+    assert_eq!(port.flow_control().unwrap(), FlowControl::Hardware);
+
+    port.set_all(&settings()).unwrap();
+    assert_eq!(port.baud_rate().unwrap(), 115200);
+    assert_eq!(port.parity().unwrap(), Parity::None);
+
+    // Data still loops back at any setting
+    port.set_baud_rate(300).unwrap();
+    port.write_all(b"ok").unwrap();
+    assert_eq!(read_exact(&mut port, 2), b"ok");
+}
+
+#[test]
+fn modem_lines() {
+    let mut port = LoopbackPort::open("loop://", &settings()).unwrap();
+    assert!(port.read_clear_to_send().unwrap());
+    assert!(port.read_data_set_ready().unwrap());
+    assert!(port.read_carrier_detect().unwrap());
+    assert!(!port.read_ring_indicator().unwrap());
+
+    // RTS loops back to CTS
+    port.write_request_to_send(false).unwrap();
+    assert!(!port.read_clear_to_send().unwrap());
+    assert!(port.read_data_set_ready().unwrap());
+
+    // DTR loops back to DSR and CD
+    port.write_data_terminal_ready(false).unwrap();
+    assert!(!port.read_data_set_ready().unwrap());
+    assert!(!port.read_carrier_detect().unwrap());
+
+    port.write_request_to_send(true).unwrap();
+    port.write_data_terminal_ready(true).unwrap();
+    assert!(port.read_clear_to_send().unwrap());
+    assert!(port.read_carrier_detect().unwrap());
+}
+
+#[test]
+fn pipe_mode_echo() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cerial"))
+        .args([
+            "loop://",
+            "115200",
+            "--until",
+            "world",
+            "--exit-timeout",
+            "5000",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .as_mut()
+        .unwrap()
+        .write_all(b"hello world\n")
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"hello world\n");
+}
+
+#[test]
+fn key_encoding() {
+    let keys = [
+        (KeyCode::Char('a'), KeyModifiers::NONE, &b"a"[..]),
+        (KeyCode::Char('c'), KeyModifiers::CONTROL, b"\x03"),
+        (KeyCode::Enter, KeyModifiers::NONE, b"\n"),
+        (KeyCode::Backspace, KeyModifiers::NONE, b"\x08"),
+        (KeyCode::Up, KeyModifiers::NONE, b"\x1b[A"),
+        (KeyCode::Left, KeyModifiers::CONTROL, b"\x1b[1;5D"),
+        (KeyCode::Home, KeyModifiers::NONE, b"\x1b[1~"),
+        (KeyCode::Delete, KeyModifiers::NONE, b"\x1b[3~"),
+        (KeyCode::BackTab, KeyModifiers::SHIFT, b"\x1b[Z"),
+        (KeyCode::F(1), KeyModifiers::NONE, b"\x1bOP"),
+        (KeyCode::F(12), KeyModifiers::NONE, b"\x1b[24~"),
+        (KeyCode::Esc, KeyModifiers::NONE, b"\x1b"),
+    ];
+
+    // Keys reach the far end of the line exactly as encoded
+    let mut port = LoopbackPort::open("loop://", &settings()).unwrap();
+    for (code, modifiers, expected) in keys {
+        let data = key_event_to_vec(KeyEvent { code, modifiers });
+        assert_eq!(data, expected, "{:?} {:?}", modifiers, code);
+        port.write_all(&data).unwrap();
+        assert_eq!(read_exact(&mut port, expected.len()), expected);
+    }
+}
+
+#[test]
+fn pipe_mode_monitors_without_input() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cerial"))
+        .args(["loop://", "115200"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // Empty stdin doesn't end pipe mode, only the port or an exit condition does
+    std::thread::sleep(Duration::from_millis(1500));
+    let running = child.try_wait().unwrap().is_none();
+    child.kill().ok();
+    child.wait().ok();
+    assert!(running);
+}
+
+/// Pseudo terminal the UI runs in, with its output collected by a thread
+#[cfg(target_os = "linux")]
+struct Terminal {
+    /// Terminal side used to type keys
+    master: std::fs::File,
+    /// Everything the UI has written
+    output: std::sync::Arc<std::sync::Mutex<Vec<u8>>>,
+}
+
+#[cfg(target_os = "linux")]
+impl Terminal {
+    /// Start cerial in an 80x24 terminal
+    fn spawn(args: &[&str], data_dir: &std::path::Path) -> (Self, std::process::Child) {
+        use std::os::unix::io::FromRawFd;
+        use std::sync::{Arc, Mutex};
+
+        let (mut master, mut slave) = (0, 0);
+        let size = libc::winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &size,
+            )
+        };
+        assert_eq!(result, 0, "openpty failed");
+        let (master, slave) = unsafe {
+            (
+                std::fs::File::from_raw_fd(master),
+                std::fs::File::from_raw_fd(slave),
+            )
+        };
+
+        let child = Command::new(env!("CARGO_BIN_EXE_cerial"))
+            .args(args)
+            .env("XDG_DATA_HOME", data_dir)
+            .env("TERM", "xterm")
+            .stdin(slave.try_clone().unwrap())
+            .stdout(slave.try_clone().unwrap())
+            .stderr(slave)
+            .spawn()
+            .unwrap();
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut reader = master.try_clone().unwrap();
+        let thread_output = output.clone();
+        std::thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            while let Ok(count) = reader.read(&mut buffer) {
+                if count == 0 {
+                    break;
+                }
+                thread_output
+                    .lock()
+                    .unwrap()
+                    .extend_from_slice(&buffer[..count]);
+            }
+        });
+
+        (Self { master, output }, child)
+    }
+
+    /// Type keys
+    fn type_keys(&mut self, keys: &[u8]) {
+        self.master.write_all(keys).unwrap();
+    }
+
+    /// Wait until the screen output, without escape sequences, contains `text`
+    fn wait_for(&self, text: &str) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if screen_text(&self.output.lock().unwrap()).contains(text) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+}
+
+/// Remove CSI escape sequences from terminal output
+#[cfg(target_os = "linux")]
+fn screen_text(output: &[u8]) -> String {
+    let mut text = Vec::new();
+    let mut bytes = output.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            0x1b => {
+                if bytes.next() == Some(&b'[') {
+                    for b in bytes.by_ref() {
+                        if (0x40..=0x7e).contains(b) {
+                            break;
+                        }
+                    }
+                }
+            }
+            b => text.push(b),
+        }
+    }
+    String::from_utf8_lossy(&text).to_string()
+}
+
+/// Keys typed in input mode go out over the port and the echo is shown in the receive pane, as
+/// scripts/term_test.py checked with real hardware
+#[cfg(target_os = "linux")]
+#[test]
+fn terminal_echo() {
+    let data_dir = std::env::temp_dir().join(format!("cerial-term-{}", std::process::id()));
+    let (mut terminal, mut child) = Terminal::spawn(&["loop://", "115200"], &data_dir);
+    assert!(terminal.wait_for("loop://"), "UI didn't start");
+
+    // Input mode, then type a word
+    terminal.type_keys(b"i");
+    std::thread::sleep(Duration::from_millis(200));
+    terminal.type_keys(b"loopback-echo");
+    assert!(terminal.wait_for("loopback-echo"), "echo not displayed");
+
+    // Ctrl-5 back to the menu, then quit
+    terminal.type_keys(b"\x1d");
+    std::thread::sleep(Duration::from_millis(200));
+    terminal.type_keys(b"q");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().ok();
+            panic!("UI didn't exit");
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    std::fs::remove_dir_all(&data_dir).ok();
+    assert!(status.success());
+}